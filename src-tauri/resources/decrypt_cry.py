@@ -0,0 +1,281 @@
+#!/usr/bin/env python3
+# little-secret .cry 参考解密程序
+#
+# 独立于 little-secret，只按 FORMAT.json 描述的格式解密单个 .cry 文件，便于在软件不可用时恢复数据。
+# 用法：python3 decrypt_cry.py <文件.cry> <输出文件> [密码]
+# 依赖：cryptography (>= 44，提供 Argon2id 和 ChaCha20) 或 argon2-cffi；zstd 压缩的文件还需要 zstandard
+# 没有安装 cryptography 时使用下面的纯 Python ChaCha20，速度较慢但结果相同。
+# 使用保险库主密钥加密的文件会在同一目录及上级目录中查找 .little_secret.vault，密码为保险库的密码。
+
+import getpass
+import hashlib
+import hmac
+import os
+import struct
+import sys
+
+MAGIC_V1 = b"WCRY"
+MAGIC = b"WCRX"
+MAX_VERSION = 9
+
+# Argon2id 参数，与 FORMAT.json 中的 kdf 一致
+KDF_MEMORY_KIB = 32 * 1024
+KDF_ITERATIONS = 2
+KDF_PARALLELISM = 4
+KEY_LEN = 32
+
+SEGMENT_TAG_LEN = 32
+
+# 保险库主密钥派生 KEK 的参数，与 FORMAT.json 中的 vault_kdf 一致
+KDF_PASSWORD = 0
+KDF_VAULT = 1
+VAULT_KDF_INFO = b"little-secret vault entry"
+VAULT_MASTER_INFO = b"little-secret vault master"
+VAULT_CONFIG_FILE = ".little_secret.vault"
+
+
+def argon2id(password, salt):
+    try:
+        from cryptography.hazmat.primitives.kdf.argon2 import Argon2id
+        kdf = Argon2id(salt=salt, length=KEY_LEN, iterations=KDF_ITERATIONS, lanes=KDF_PARALLELISM, memory_cost=KDF_MEMORY_KIB)
+        return kdf.derive(password)
+    except ImportError:
+        from argon2.low_level import Type, hash_secret_raw
+        return hash_secret_raw(password, salt, KDF_ITERATIONS, KDF_MEMORY_KIB, KDF_PARALLELISM, KEY_LEN, Type.ID, 19)
+
+
+def hkdf_sha256(key, salt, info, length=KEY_LEN):
+    """HKDF-SHA256 (RFC 5869)"""
+    prk = hmac_sha256(salt, key)
+    out, block = b"", b""
+    for i in range(1, -(-length // 32) + 1):
+        block = hmac_sha256(prk, block, info, bytes([i]))
+        out += block
+    return out[:length]
+
+
+def _rotl(v, n):
+    return ((v << n) & 0xFFFFFFFF) | (v >> (32 - n))
+
+
+def _quarter(s, a, b, c, d):
+    s[a] = (s[a] + s[b]) & 0xFFFFFFFF; s[d] = _rotl(s[d] ^ s[a], 16)
+    s[c] = (s[c] + s[d]) & 0xFFFFFFFF; s[b] = _rotl(s[b] ^ s[c], 12)
+    s[a] = (s[a] + s[b]) & 0xFFFFFFFF; s[d] = _rotl(s[d] ^ s[a], 8)
+    s[c] = (s[c] + s[d]) & 0xFFFFFFFF; s[b] = _rotl(s[b] ^ s[c], 7)
+
+
+def _chacha_block(key, counter, nonce):
+    state = [0x61707865, 0x3320646E, 0x79622D32, 0x6B206574]
+    state += list(struct.unpack("<8I", key)) + [counter] + list(struct.unpack("<3I", nonce))
+    s = state[:]
+    for _ in range(10):
+        _quarter(s, 0, 4, 8, 12); _quarter(s, 1, 5, 9, 13); _quarter(s, 2, 6, 10, 14); _quarter(s, 3, 7, 11, 15)
+        _quarter(s, 0, 5, 10, 15); _quarter(s, 1, 6, 11, 12); _quarter(s, 2, 7, 8, 13); _quarter(s, 3, 4, 9, 14)
+    return struct.pack("<16I", *((s[i] + state[i]) & 0xFFFFFFFF for i in range(16)))
+
+
+def chacha20(key, nonce, data):
+    """ChaCha20 (RFC 8439)，32 位计数器从 0 开始"""
+    try:
+        from cryptography.hazmat.primitives.ciphers import Cipher, algorithms
+        return Cipher(algorithms.ChaCha20(key, b"\0\0\0\0" + nonce), mode=None).encryptor().update(data)
+    except ImportError:
+        out = bytearray(data)
+        for i in range(0, len(out), 64):
+            block = _chacha_block(key, i // 64, nonce)
+            for j in range(min(64, len(out) - i)):
+                out[i + j] ^= block[j]
+        return bytes(out)
+
+
+def hmac_sha256(key, *parts):
+    h = hmac.new(key, digestmod=hashlib.sha256)
+    for p in parts:
+        h.update(p)
+    return h.digest()
+
+
+class Reader:
+    def __init__(self, f):
+        self.f = f
+        self.pos = 0
+
+    def read(self, n):
+        data = self.f.read(n)
+        if len(data) != n:
+            raise ValueError("文件头不完整")
+        self.pos += n
+        return data
+
+    def u8(self):
+        return self.read(1)[0]
+
+    def u16(self):
+        return struct.unpack(">H", self.read(2))[0]
+
+    def u32(self):
+        return struct.unpack(">I", self.read(4))[0]
+
+    def u64(self):
+        return struct.unpack(">Q", self.read(8))[0]
+
+
+def parse_header(f):
+    r = Reader(f)
+    magic = r.read(4)
+    if magic == MAGIC_V1:
+        version = 1
+    elif magic == MAGIC:
+        version = r.u8()
+    else:
+        raise ValueError("不是 .cry 文件")
+    if version > MAX_VERSION:
+        raise ValueError("不支持的文件版本: %d" % version)
+
+    h = {"version": version, "entries": []}
+    for _ in range(r.u16()):
+        entry = {"salt": r.read(16), "kek_nonce": r.read(12), "encrypted_cek": r.read(32), "encrypted_cek_nonce": r.read(12), "kdf": KDF_PASSWORD}
+        if version >= 2:
+            r.u64()     # 创建时间
+            kind = r.u8()
+            label_len = r.u16()
+            if kind == 1:
+                r.read(label_len)
+            elif kind == 2:
+                r.read(12 + label_len)
+        if version >= 8:
+            entry["kdf"] = r.u8()
+        h["entries"].append(entry)
+    h["encrypted_verify_block"] = r.read(128)
+    h["verify_block_hmac"] = r.read(32)
+    h["file_hmac"] = r.read(32)
+    if version == 1:
+        # v1 的元数据长度是可选的
+        data = f.read(4)
+        r.pos += len(data)
+        metadata_len = struct.unpack(">I", data)[0] if len(data) == 4 else 0
+    else:
+        metadata_len = r.u32()
+    h["metadata"] = r.read(metadata_len)
+    h["secret_metadata"] = r.read(r.u32()) if version >= 4 else b""
+    h["compression"] = r.u8() if version >= 5 else 0
+    h["segment_size"], h["segment_count"] = (r.u32(), r.u32()) if version >= 6 else (0, 0)
+    h["size_padding"] = r.u8() if version >= 7 else 0
+    h["segment_base"] = r.u32() if version >= 9 else 0
+    if version >= 3:
+        r.read(r.u32())     # 预留的填充
+    h["header_len"] = r.pos
+    return h
+
+
+def unwrap(h, e, kek):
+    """用 KEK 解密 CEK，用验证块判断 CEK 是否正确"""
+    cek = chacha20(kek, e["kek_nonce"], e["encrypted_cek"])
+    cek_nonce = chacha20(kek, e["kek_nonce"], e["encrypted_cek_nonce"])
+    verify_block = chacha20(cek, cek_nonce, h["encrypted_verify_block"])
+    if hmac.compare_digest(hmac_sha256(cek, verify_block), h["verify_block_hmac"]):
+        return cek, cek_nonce
+    return None
+
+
+def find_vault_config(path):
+    d = os.path.dirname(os.path.abspath(path))
+    while True:
+        config = os.path.join(d, VAULT_CONFIG_FILE)
+        if os.path.exists(config):
+            return config
+        parent = os.path.dirname(d)
+        if parent == d:
+            return None
+        d = parent
+
+
+def unlock(h, password, path):
+    """逐个尝试密码块；保险库密码块先用密码解锁保险库配置文件，主密钥由它的 CEK 派生"""
+    master_key = None
+    for e in h["entries"]:
+        if e["kdf"] == KDF_PASSWORD:
+            kek = argon2id(password, e["salt"])
+        elif e["kdf"] == KDF_VAULT:
+            if master_key is None:
+                config = find_vault_config(path)
+                if config is None:
+                    raise ValueError("找不到保险库配置文件 %s" % VAULT_CONFIG_FILE)
+                with open(config, "rb") as f:
+                    config_cek = unlock(parse_header(f), password, config)[0]
+                master_key = hkdf_sha256(config_cek, bytes(KEY_LEN), VAULT_MASTER_INFO)
+            kek = hkdf_sha256(master_key, e["salt"], VAULT_KDF_INFO)
+        else:
+            continue
+        key = unwrap(h, e, kek)
+        if key:
+            return key
+    raise ValueError("密码不正确")
+
+
+def read_content(path, h, cek):
+    """读取全部密文；分段存储时依次读取 xxx.cry.{segment_base + 1} ... 并校验每段的 HMAC"""
+    if h["segment_count"] == 0:
+        with open(path, "rb") as f:
+            f.seek(h["header_len"])
+            return f.read()
+    out = bytearray()
+    for i in range(1, h["segment_count"] + 1):
+        with open("%s.%06d" % (path, h["segment_base"] + i), "rb") as f:
+            data = f.read()
+        body, tag = data[:-SEGMENT_TAG_LEN], data[-SEGMENT_TAG_LEN:]
+        if not hmac.compare_digest(hmac_sha256(cek, b"WSEG", struct.pack(">Q", i), body), tag):
+            raise ValueError("分段 %d 校验失败" % i)
+        out += body
+    return bytes(out)
+
+
+def decompress(data):
+    """zstd：各块单独压缩后依次拼接，末尾为块索引"""
+    import zstandard
+    block_size, original_len, count = struct.unpack(">IQI", data[-16:])
+    lens = struct.unpack(">%dI" % count, data[-16 - count * 4:-16])
+    out = bytearray()
+    offset = 0
+    for n in lens:
+        out += zstandard.ZstdDecompressor().decompress(data[offset:offset + n], max_output_size=block_size)
+        offset += n
+    if len(out) != original_len:
+        raise ValueError("解压后的长度不正确")
+    return bytes(out)
+
+
+def decrypt(path, password):
+    with open(path, "rb") as f:
+        h = parse_header(f)
+    cek, cek_nonce = unlock(h, password.encode("utf-8"), path)
+    stream = chacha20(cek, cek_nonce, read_content(path, h, cek))
+    # File HMAC 覆盖解密后的全部数据（包括压缩索引、长度填充）
+    if not hmac.compare_digest(hmac_sha256(cek, stream), h["file_hmac"]):
+        raise ValueError("文件完整性校验失败")
+    if h["size_padding"] == 1:
+        # PADMÉ 填充：内容 + 0 填充 + 内容长度(8, BE)
+        stream = stream[:struct.unpack(">Q", stream[-8:])[0]]
+    elif h["size_padding"] != 0:
+        raise ValueError("不支持的长度填充方式: %d" % h["size_padding"])
+    if h["compression"] == 1:
+        stream = decompress(stream)
+    elif h["compression"] != 0:
+        raise ValueError("不支持的压缩方式: %d" % h["compression"])
+    return stream
+
+
+def main():
+    if len(sys.argv) < 3:
+        print("用法: python3 decrypt_cry.py <文件.cry> <输出文件> [密码]")
+        sys.exit(1)
+    password = sys.argv[3] if len(sys.argv) > 3 else getpass.getpass("密码: ")
+    data = decrypt(sys.argv[1], password)
+    with open(sys.argv[2], "wb") as f:
+        f.write(data)
+    print("已解密 %d 字节到 %s" % (len(data), sys.argv[2]))
+
+
+if __name__ == "__main__":
+    main()
@@ -3,7 +3,18 @@ mod utils;
 use std::{fs};
 use crate::utils::encrypt::{decrypt_file, encrypt_file, reset_passwords};
 use crate::utils::cry_info::{print_header_info, update_metadata};
-use crate::utils::folder::{decrypt_file_temp, decrypt_folder, encrypt_folder, read_file_metadata, read_folder, read_img_base64};
+use crate::utils::folder::{decrypt_file_temp, decrypt_folder, encrypt_folder, list_password_entries, read_file_metadata, read_folder, read_file_preview, read_img_base64, regenerate_thumbnails, remove_password_entry, reset_folder_passwords, rotate_folder_keys, thumbnail_protocol, verify_file_segments};
+use crate::utils::listing::list_folder_with_metadata;
+use crate::utils::policy::{load_policy, password_strength, set_password_policy};
+use crate::utils::session::{lock_session, unlock_session};
+use crate::utils::tags::{add_tags, read_annotations, remove_tags, set_note, set_rating};
+use crate::utils::vault::search_vault;
+use crate::utils::vault_key::{change_vault_passwords, create_vault};
+use crate::utils::dedup::find_duplicates;
+use crate::utils::watch::{list_watches, start_watch, stop_watch};
+use crate::utils::archive::{create_archive, extract_archive_entry, list_archive, unpack_archive};
+use crate::utils::exchange::{export_encrypted_archive, import_encrypted_archive};
+use crate::utils::bundle::export_bundle;
 use crate::utils::thumbnail::{make_thumbnail};
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
@@ -20,11 +31,23 @@ fn test() -> String {
 }
 
 
+// 命令行监视文件夹，不启动界面，返回退出码
+pub fn watch_cli(args: &[String]) -> i32 {
+    utils::watch::run_cli(args)
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
+        // 缩略图协议，前端直接用 <img src> 加载二进制图片，不再经过 base64
+        .register_uri_scheme_protocol("thumb", |_ctx, request| thumbnail_protocol(&request))
+        // 读取保存的密码策略
+        .setup(|app| {
+            load_policy(app.handle());
+            Ok(())
+        })
         // 注册 JS 可调用的函数
         .invoke_handler(tauri::generate_handler![
             test,
@@ -34,6 +57,37 @@ pub fn run() {
             read_file_metadata,     // 读取文件元数据
             decrypt_file_temp,      // 解密文件到临时文件
             read_img_base64,        // 将图片解码为base64（注意是图片而不是加密文件）
+            list_password_entries,  // 列出加密文件的密码块
+            remove_password_entry,  // 按标签删除密码块
+            verify_file_segments,   // 校验分段文件
+            rotate_folder_keys,     // 递归轮换文件夹中全部文件的密钥
+            reset_folder_passwords, // 递归修改文件夹中全部文件的密码
+            password_strength,      // 评估密码强度
+            set_password_policy,    // 修改密码策略
+            regenerate_thumbnails,  // 按新的隐私级别重新生成缩略图
+            unlock_session,         // 解锁会话
+            lock_session,           // 锁定会话
+            read_file_preview,      // 读取加密的清晰预览图
+            list_folder_with_metadata, // 分批读取文件夹中全部文件及元数据
+            search_vault,           // 在已解锁的保险库中搜索文件
+            create_vault,           // 为保险库启用主密钥
+            change_vault_passwords, // 修改保险库主密钥的密码
+            find_duplicates,        // 查找、链接或删除原文相同的文件
+            start_watch,            // 开始监视文件夹，自动加密新文件
+            stop_watch,             // 停止监视文件夹
+            list_watches,           // 列出正在监视的文件夹
+            read_annotations,       // 读取标签、备注和评分
+            add_tags,               // 批量添加标签
+            remove_tags,            // 批量移除标签
+            set_note,               // 设置备注
+            set_rating,             // 设置评分
+            create_archive,         // 把文件夹打包为加密容器
+            list_archive,           // 列出容器中的文件
+            extract_archive_entry,  // 取出容器中的单个文件
+            unpack_archive,         // 把容器转换为单独的加密文件
+            export_encrypted_archive, // 导出为加密的 7z / ZIP
+            import_encrypted_archive, // 从加密的 7z / ZIP 导入
+            export_bundle,          // 导出带格式说明和参考解密程序的 ZIP
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
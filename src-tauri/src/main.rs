@@ -2,5 +2,16 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    // little-secret watch <文件夹> [等待秒数]：只监视文件夹并自动加密，不启动界面
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|a| a == "watch") {
+        // 发布版没有控制台窗口，连接到启动它的终端才能输出日志和读取密码
+        #[cfg(all(windows, not(debug_assertions)))]
+        unsafe {
+            use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+            AttachConsole(ATTACH_PARENT_PROCESS);
+        }
+        std::process::exit(little_secret_lib::watch_cli(&args[2..]));
+    }
     little_secret_lib::run()
 }
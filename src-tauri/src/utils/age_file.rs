@@ -0,0 +1,135 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::iter;
+use std::path::Path;
+use age::secrecy::SecretString;
+use age::stream::StreamReader;
+use age::{DecryptError, Decryptor, Encryptor, Identity, Recipient};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::utils::encrypt::EntrySpec;
+
+// 与 age v1 (https://age-encryption.org/v1) 互通：可以选择把文件加密为标准的 age 格式，紧急情况下用 age / rage 即可解密
+// 普通密码作为 scrypt 口令，以 age1 开头的"密码"作为 X25519 公钥；age 规定口令只能有一个，且不能与公钥混用
+// 公开元数据（缩略图）原样保存在旁边的 xxx.age.meta 文件中；age 文件没有加密元数据，清晰预览图不会保存
+
+pub const AGE_EXTENSION: &str = "age";
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+const META_SUFFIX: &str = ".meta";
+const X25519_RECIPIENT_PREFIX: &str = "age1";
+const X25519_IDENTITY_PREFIX: &str = "AGE-SECRET-KEY-1";
+
+// 加密后的文件格式
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+    #[default]
+    Cry,    // 本软件的格式，支持多密码、缩略图、压缩、分段等
+    Age,    // 标准 age 格式
+}
+
+impl FileFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Cry => "cry",
+            FileFormat::Age => AGE_EXTENSION,
+        }
+    }
+}
+
+// 按文件头判断是否为 age 文件
+pub fn is_age_file(path: &str) -> bool {
+    let mut head = [0u8; AGE_MAGIC.len()];
+    File::open(path).and_then(|mut f| f.read_exact(&mut head)).is_ok() && head == AGE_MAGIC
+}
+
+// age 文件的元数据文件路径
+pub fn meta_path(path: &str) -> String {
+    format!("{}{}", path, META_SUFFIX)
+}
+
+// 是否为 .age 文件
+pub fn has_age_extension(path: &Path) -> bool {
+    path.extension().map(|e| e == AGE_EXTENSION).unwrap_or(false)
+}
+
+// 是否为 age 文件的元数据文件，例如 a.jpg.age.meta
+pub fn is_meta_file(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(&format!(".{}{}", AGE_EXTENSION, META_SUFFIX))
+}
+
+// 由密码块生成 age 的接收者
+fn encryptor(specs: &[EntrySpec]) -> Result<Encryptor> {
+    let mut passphrases = Vec::new();
+    let mut keys = Vec::new();
+    for spec in specs {
+        if spec.master_key.is_some() {
+            bail!("age 格式不支持保险库主密钥");
+        }
+        if spec.password.starts_with(X25519_RECIPIENT_PREFIX) {
+            let key: age::x25519::Recipient = spec.password.parse().map_err(|e| anyhow!("无效的 age 公钥 {}: {}", spec.password, e))?;
+            keys.push(key);
+        } else {
+            passphrases.push(spec.password.clone());
+        }
+    }
+    match (passphrases.len(), keys.len()) {
+        (0, 0) => bail!("至少需要一个密码"),
+        (1, 0) => Ok(Encryptor::with_user_passphrase(SecretString::from(passphrases.remove(0)))),
+        (0, _) => Ok(Encryptor::with_recipients(keys.iter().map(|k| k as &dyn Recipient))?),
+        _ => bail!("age 格式只支持一个密码，且不能与公钥混用"),
+    }
+}
+
+// 检查密码块能否用于 age 格式：只能有一个密码，或者全部为 X25519 公钥，不能使用保险库主密钥
+pub fn check_specs(specs: &[EntrySpec]) -> Result<()> {
+    encryptor(specs).map(|_| ())
+}
+
+// 加密为 age 文件，公开元数据写入元数据文件
+pub fn encrypt<R: Read>(input: R, output_path: &str, specs: &[EntrySpec], metadata: Option<&[u8]>) -> Result<()> {
+    let encryptor = encryptor(specs)?;
+    let output = BufWriter::new(File::create(output_path).context("创建输出文件失败")?);
+    let mut writer = encryptor.wrap_output(output).context("写入头部失败")?;
+    io::copy(&mut BufReader::new(input), &mut writer).context("加密失败")?;
+    writer.finish().and_then(|mut w| w.flush()).context("写入输出文件失败")?;
+
+    let meta = meta_path(output_path);
+    match metadata {
+        Some(data) if !data.is_empty() => fs::write(&meta, data).context("写入元数据文件失败")?,
+        _ => if Path::new(&meta).exists() { fs::remove_file(&meta).context("删除旧的元数据文件失败")? },
+    }
+    Ok(())
+}
+
+// 使用口令或 X25519 私钥 (AGE-SECRET-KEY-1...) 打开 age 文件
+fn open(input_path: &str, password: &str) -> Result<StreamReader<BufReader<File>>> {
+    let input = BufReader::new(File::open(input_path).context("打开输入文件失败")?);
+    let decryptor = Decryptor::new_buffered(input).context("解析 age 文件头失败")?;
+    let result = if password.starts_with(X25519_IDENTITY_PREFIX) {
+        let identity: age::x25519::Identity = password.parse().map_err(|e| anyhow!("无效的 age 私钥: {}", e))?;
+        decryptor.decrypt(iter::once(&identity as &dyn Identity))
+    } else {
+        let identity = age::scrypt::Identity::new(SecretString::from(password.to_string()));
+        decryptor.decrypt(iter::once(&identity as &dyn Identity))
+    };
+    match result {
+        Ok(reader) => Ok(reader),
+        Err(DecryptError::NoMatchingKeys) | Err(DecryptError::DecryptionFailed) => bail!("提供的密码不匹配任何加密密钥"),
+        Err(e) => Err(e).context("解密 age 文件失败"),
+    }
+}
+
+// 解密 age 文件，返回元数据文件中的公开元数据；内容经过认证，损坏时返回错误并删除不完整的输出
+pub fn decrypt(input_path: &str, output_path: &str, password: &str) -> Result<Vec<u8>> {
+    // 密码不正确时不创建输出文件
+    let mut reader = open(input_path, password)?;
+    let mut writer = BufWriter::new(File::create(output_path).context("创建输出文件失败")?);
+    let result = io::copy(&mut reader, &mut writer).and_then(|_| writer.flush());
+    if let Err(e) = result {
+        drop(writer);
+        let _ = fs::remove_file(output_path);
+        return Err(e).context("解密失败，文件可能已损坏");
+    }
+    Ok(fs::read(meta_path(input_path)).unwrap_or_default())
+}
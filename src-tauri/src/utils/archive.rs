@@ -0,0 +1,342 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Take, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::utils::compress::Compression;
+use crate::utils::cry_reader::CryReader;
+use crate::utils::encrypt::{decrypt_to_writer, encrypt_stream, read_secret_metadata, EncryptOptions, EntrySpec, FileKey};
+use crate::utils::folder::{collect_files, is_journal_file};
+use crate::utils::index_cache::is_index_file;
+use crate::utils::meta::FileMeta;
+use crate::utils::padding::SizePadding;
+use crate::utils::policy::check_passwords;
+use crate::utils::segment::is_segment_file;
+use crate::utils::vault_key::{is_vault_config, resolve_file_key, vault_specs, KeyResolver};
+use crate::utils::vault::{is_manifest_file, make_stream_record, record_encrypted, ManifestRecord};
+
+// 容器文件：把整个文件夹加密为一个 .crya 文件，不暴露文件夹结构
+// 与 .cry 使用相同的文件头和密码块，密文为各文件内容依次拼接，
+// 文件索引（路径、位置、长度、SHA-256）保存在加密元数据中，列出和取出单个文件时不需要解密全部内容
+pub const ARCHIVE_EXTENSION: &str = "crya";
+
+// 容器中的一个文件
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchiveEntry {
+    pub path: String,       // 相对路径，统一使用 / 分隔
+    pub offset: u64,        // 在容器内容中的起始位置
+    pub size: u64,
+    pub modified: u64,      // 修改时间 (Unix 秒)
+    pub sha256: String,     // 内容的 SHA-256，索引经过认证，取出时据此校验内容
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ArchiveIndex {
+    pub entries: Vec<ArchiveEntry>,
+}
+
+pub fn is_archive_file(path: &Path) -> bool {
+    path.extension().map(|e| e == ARCHIVE_EXTENSION).unwrap_or(false)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 计算写入数据的 SHA-256 和长度
+struct HashWriter {
+    hasher: Sha256,
+    len: u64,
+}
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// 打包的来源：普通文件，或用密码解锁的 .cry 文件
+enum Source {
+    Plain(PathBuf),
+    Cry(PathBuf, FileKey),
+}
+
+impl Source {
+    fn open(&self) -> Result<Box<dyn Read>> {
+        Ok(match self {
+            Source::Plain(path) => Box::new(File::open(path).context("打开文件失败")?),
+            Source::Cry(path, key) => Box::new(CryReader::open(&path.to_string_lossy(), key)?),
+        })
+    }
+
+    // 第一遍读取：计算内容的长度和 SHA-256，.cry 文件同时校验 File HMAC
+    fn hash(&self) -> Result<(u64, String)> {
+        let mut writer = HashWriter { hasher: Sha256::new(), len: 0 };
+        match self {
+            Source::Plain(path) => {
+                io::copy(&mut File::open(path).context("打开文件失败")?, &mut writer)?;
+            }
+            Source::Cry(path, key) => {
+                let (matched, _) = decrypt_to_writer(&path.to_string_lossy(), &mut writer, key)?;
+                if !matched {
+                    bail!("文件完整性校验失败: {}", path.display());
+                }
+            }
+        }
+        Ok((writer.len, to_hex(&writer.hasher.finalize())))
+    }
+}
+
+// 校验内容的读取器：读到结尾时长度和 SHA-256 必须与索引一致，否则返回错误
+struct VerifiedReader<R: Read> {
+    inner: Take<R>,
+    hasher: Sha256,
+    expected: String,
+    path: String,
+}
+
+impl<R: Read> VerifiedReader<R> {
+    fn new(inner: R, entry: &ArchiveEntry) -> VerifiedReader<R> {
+        VerifiedReader { inner: inner.take(entry.size), hasher: Sha256::new(), expected: entry.sha256.clone(), path: entry.path.clone() }
+    }
+}
+
+impl<R: Read> Read for VerifiedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        if n == 0 && !buf.is_empty() {
+            let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+            if self.inner.limit() != 0 {
+                return Err(invalid(format!("文件长度与索引不一致: {}", self.path)));
+            }
+            if to_hex(&self.hasher.clone().finalize()) != self.expected {
+                return Err(invalid(format!("文件内容校验失败: {}", self.path)));
+            }
+        }
+        Ok(n)
+    }
+}
+
+// 打开 .cry 文件的原文用于导出：先完整解密一遍校验 File HMAC，读取时再按第一遍的 SHA-256 校验
+pub(crate) fn open_verified(path: &Path, key: FileKey) -> Result<impl Read> {
+    let source = Source::Cry(path.to_path_buf(), key);
+    let (size, sha256) = source.hash()?;
+    let entry = ArchiveEntry { path: path.to_string_lossy().to_string(), offset: 0, size, modified: 0, sha256 };
+    Ok(VerifiedReader::new(source.open()?, &entry))
+}
+
+// 第二遍读取：把全部来源依次拼接为容器内容，每个文件读完时与第一遍的结果比较，防止打包过程中文件被修改
+struct ConcatReader {
+    items: std::vec::IntoIter<(ArchiveEntry, Source)>,
+    current: Option<VerifiedReader<Box<dyn Read>>>,
+}
+
+impl Read for ConcatReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(reader) = &mut self.current {
+                let n = reader.read(buf)?;
+                if n > 0 || buf.is_empty() {
+                    return Ok(n);
+                }
+                self.current = None;
+            }
+            let Some((entry, source)) = self.items.next() else { return Ok(0) };
+            let reader = source.open().map_err(|e| io::Error::other(e.to_string()))?;
+            self.current = Some(VerifiedReader::new(reader, &entry));
+        }
+    }
+}
+
+// 相对路径，统一使用 / 分隔
+fn relative(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+// 拒绝绝对路径和 ..，避免取出时写到目标文件夹之外（zip slip）
+fn safe_join(root: &Path, entry: &str) -> Result<PathBuf> {
+    let relative = Path::new(entry);
+    if entry.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        bail!("容器中的路径不合法: {}", entry);
+    }
+    Ok(root.join(relative))
+}
+
+fn modified_secs(path: &Path) -> u64 {
+    fs::metadata(path).ok()
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// 把文件夹打包为容器，文件夹中的 .cry 文件会用 passwords 中任意一个密码解锁后以原文打包，返回文件数量
+pub fn pack_folder(folder: &Path, output: &str, passwords: &[&str], specs: &[EntrySpec], options: EncryptOptions) -> Result<usize> {
+    let output_path = Path::new(output);
+    let mut files = Vec::new();
+    collect_files(folder, &|p| {
+        !is_index_file(p) && !is_manifest_file(p) && !is_vault_config(p) && !is_segment_file(p) && !is_archive_file(p) && !is_journal_file(p) && p != output_path
+    }, &mut files);
+    files.sort();
+
+    let resolver = KeyResolver::new(passwords);
+    let mut items = Vec::with_capacity(files.len());
+    let mut offset = 0;
+    for file in files {
+        let mut name = relative(folder, &file);
+        let source = if name.ends_with(".cry") {
+            let path = file.to_string_lossy();
+            let key = resolver.unlock(&path).with_context(|| format!("密码无法解锁: {}", file.display()))?;
+            name.truncate(name.len() - 4);
+            Source::Cry(file.clone(), key)
+        } else {
+            Source::Plain(file.clone())
+        };
+        let (size, sha256) = source.hash()?;
+        items.push((ArchiveEntry { path: name, offset, size, modified: modified_secs(&file), sha256 }, source));
+        offset += size;
+    }
+
+    let index = ArchiveIndex { entries: items.iter().map(|(e, _)| e.clone()).collect() };
+    let count = index.entries.len();
+    let reader = ConcatReader { items: items.into_iter(), current: None };
+    encrypt_stream(reader, output, specs, None, Some(&serde_json::to_vec(&index)?), options)?;
+    Ok(count)
+}
+
+// 读取容器的文件索引
+pub fn read_index(archive: &str, key: &FileKey) -> Result<ArchiveIndex> {
+    let data = read_secret_metadata(archive, key)?;
+    serde_json::from_slice(&data).context("不是容器文件或索引已损坏")
+}
+
+fn open_entry(archive: &str, key: &FileKey, entry: &ArchiveEntry) -> Result<VerifiedReader<CryReader>> {
+    let mut reader = CryReader::open(archive, key)?;
+    reader.seek(SeekFrom::Start(entry.offset))?;
+    Ok(VerifiedReader::new(reader, entry))
+}
+
+// 取出单个文件的原文，只解密该文件所在的部分
+pub fn extract_entry(archive: &str, key: &FileKey, name: &str, output: &str) -> Result<()> {
+    let index = read_index(archive, key)?;
+    let Some(entry) = index.entries.iter().find(|e| e.path == name) else { bail!("容器中没有文件: {}", name) };
+    let mut reader = open_entry(archive, key, entry)?;
+    let mut writer = BufWriter::new(File::create(output).context("创建输出文件失败")?);
+    if let Err(e) = io::copy(&mut reader, &mut writer).and_then(|_| writer.flush()) {
+        drop(writer);
+        let _ = fs::remove_file(output);
+        bail!("取出失败: {}", e);
+    }
+    Ok(())
+}
+
+// 把容器转换为文件夹中单独的 .cry 文件，不会把原文写到磁盘上，返回清单记录
+pub fn unpack_to_cry(archive: &str, key: &FileKey, output_dir: &Path, specs: &[EntrySpec], options: EncryptOptions) -> Result<Vec<ManifestRecord>> {
+    let index = read_index(archive, key)?;
+    let mut records = Vec::with_capacity(index.entries.len());
+    for entry in &index.entries {
+        let reader = open_entry(archive, key, entry)?;
+        let output = encrypt_entry(reader, output_dir, &entry.path, specs, options)?;
+        records.push(make_stream_record(&output, entry.size, entry.modified));
+    }
+    Ok(records)
+}
+
+// original 对应的 .cry 路径；已有同名的加密文件时（压缩包中也可能有重名的条目）改为 “名称 (2).扩展名.cry”，
+// 不覆盖已有的文件和它的分段
+fn unused_cry_path(original: &Path) -> String {
+    let output = format!("{}.cry", original.to_string_lossy());
+    if !Path::new(&output).exists() {
+        return output;
+    }
+    let stem = original.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = original.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (2..).map(|n| format!("{}.cry", original.with_file_name(format!("{} ({}){}", stem, n, ext)).to_string_lossy()))
+        .find(|p| !Path::new(p).exists())
+        .unwrap()
+}
+
+// 把数据流加密为 output_dir 中的 name.cry（重名时见 unused_cry_path），name 为相对路径；失败时删除不完整的输出，返回加密文件的路径
+pub(crate) fn encrypt_entry<R: Read>(reader: R, output_dir: &Path, name: &str, specs: &[EntrySpec], options: EncryptOptions) -> Result<PathBuf> {
+    let original = safe_join(output_dir, name)?;
+    if let Some(parent) = original.parent() {
+        fs::create_dir_all(parent).context("创建文件夹失败")?;
+    }
+    let output = unused_cry_path(&original);
+    let options = EncryptOptions { compression: options.compression.for_name(name), ..options };
+    if let Err(e) = encrypt_stream(reader, &output, specs, Some(&FileMeta::default().to_bytes()), None, options) {
+        let _ = fs::remove_file(&output);
+        return Err(e);
+    }
+    Ok(PathBuf::from(output))
+}
+
+// 把文件夹（可以包含 .cry 文件）打包为一个加密容器
+#[tauri::command]
+pub fn create_archive(path: &str, output: &str, passwords: Vec<&str>, compression: Option<Compression>, segment_size_mb: Option<u32>, padding: Option<SizePadding>) -> String {
+    if let Err(e) = check_passwords(&passwords) { return format!("打包失败: {}", e); }
+    let specs = EntrySpec::from_passwords(&passwords.iter().map(|p| p.to_string()).collect::<Vec<_>>());
+    let options = EncryptOptions {
+        compression: compression.unwrap_or_default(),
+        segment_size: segment_size_mb.unwrap_or(0).saturating_mul(1024 * 1024),
+        padding: padding.unwrap_or_default(),
+        ..Default::default()
+    };
+    match pack_folder(Path::new(path), output, &passwords, &specs, options) {
+        Ok(n) => format!("打包完毕：{} 个文件", n),
+        Err(e) => format!("打包失败: {}", e),
+    }
+}
+
+// 列出容器中的文件
+#[tauri::command]
+pub fn list_archive(path: &str, password: &str) -> String {
+    match resolve_file_key(path, password).and_then(|key| read_index(path, &key)) {
+        Ok(index) => serde_json::to_string(&index.entries).unwrap(),
+        Err(e) => format!("读取失败: {}", e),
+    }
+}
+
+// 取出容器中的单个文件
+#[tauri::command]
+pub fn extract_archive_entry(path: &str, password: &str, entry: &str, output: &str) -> String {
+    match resolve_file_key(path, password).and_then(|key| extract_entry(path, &key, entry, output)) {
+        Ok(()) => "取出完毕".to_string(),
+        Err(e) => format!("取出失败: {}", e),
+    }
+}
+
+// 把容器转换为文件夹中单独的 .cry 文件，不传 passwords 时使用容器的密码
+#[tauri::command]
+pub fn unpack_archive(path: &str, password: &str, output: &str, passwords: Option<Vec<&str>>, compression: Option<Compression>) -> String {
+    let passwords = passwords.filter(|p| !p.is_empty()).unwrap_or_else(|| vec![password]);
+    if let Err(e) = check_passwords(&passwords) { return format!("转换失败: {}", e); }
+    let specs = EntrySpec::from_passwords(&passwords.iter().map(|p| p.to_string()).collect::<Vec<_>>());
+    let specs = match vault_specs(Path::new(output), &passwords, specs) {
+        Ok(specs) => specs,
+        Err(e) => return format!("转换失败: {}", e),
+    };
+    let options = EncryptOptions { compression: compression.unwrap_or_default(), ..Default::default() };
+    let result = resolve_file_key(path, password)
+        .and_then(|key| unpack_to_cry(path, &key, Path::new(output), &specs, options));
+    match result {
+        Ok(records) => {
+            let count = records.len();
+            match record_encrypted(Path::new(output), &specs, records) {
+                Ok(()) => format!("转换完毕：{} 个文件", count),
+                Err(e) => format!("转换完毕：{} 个文件；更新保险库清单失败: {}", count, e),
+            }
+        }
+        Err(e) => format!("转换失败: {}", e),
+    }
+}
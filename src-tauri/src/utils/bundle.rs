@@ -0,0 +1,277 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+use crate::utils::compress::{COMPRESSION_NONE, COMPRESSION_ZSTD};
+use crate::utils::cry_info::{parse_header, ENTRY_KDF_VAULT, HEADER_MAGIC, HEADER_VERSION};
+use crate::utils::encrypt::{KDF_ITERATIONS, KDF_MEMORY_KIB, KDF_PARALLELISM, VAULT_KDF_INFO, VAULT_MASTER_INFO};
+use crate::utils::padding::{SIZE_PADDING_NONE, SIZE_PADDING_PADME};
+use crate::utils::segment::header_segment_path;
+use crate::utils::vault_key::{find_vault_config, VAULT_CONFIG_FILE};
+
+// 自描述的导出包：一个不压缩的 ZIP，包含原样复制的 .cry 文件（及分段）、机器可读的格式说明和参考解密程序，
+// 即使本软件不再可用，对方也能按说明恢复数据。.cry 文件本身已经加密，导出时不需要密码
+
+const FORMAT_FILE: &str = "FORMAT.json";
+const DECRYPTER_FILE: &str = "decrypt_cry.py";
+const README_FILE: &str = "README.txt";
+const FILES_DIR: &str = "files";
+
+const REFERENCE_DECRYPTER: &str = include_str!("../../resources/decrypt_cry.py");
+const README: &str = "\
+本压缩包由 little-secret 导出，files 目录中的 .cry 文件使用密码加密。
+
+FORMAT.json 是机器可读的格式说明：文件头布局、Argon2id 参数、ChaCha20 和 HMAC-SHA256 的用法以及解密步骤。
+decrypt_cry.py 是独立的参考解密程序，不依赖 little-secret：
+
+    pip install cryptography zstandard
+    python3 decrypt_cry.py files/<文件名>.cry <输出文件>
+
+分段存储的文件（<文件名>.cry.000001 ...）需要与 .cry 放在同一目录。
+使用保险库主密钥加密的文件需要 files/.little_secret.vault，参考解密程序会自动查找，输入保险库的密码即可。
+";
+
+// 文件头中的一个字段
+#[derive(Serialize, Debug)]
+struct Field {
+    name: &'static str,
+    size: &'static str,     // 字节数，N 表示变长
+    since: u8,              // 从哪个版本开始存在
+    description: &'static str,
+}
+
+const fn field(name: &'static str, size: &'static str, since: u8, description: &'static str) -> Field {
+    Field { name, size, since, description }
+}
+
+const HEADER_FIELDS: &[Field] = &[
+    field("magic", "4", 1, "WCRX；v1 为 WCRY，且没有 version"),
+    field("version", "1", 2, "格式版本"),
+    field("password_count", "2", 1, "密码块数量"),
+    field("entries", "N", 1, "password_count 个密码块，见 entry"),
+    field("encrypted_verify_block", "128", 1, "ChaCha20(CEK, CEK Nonce) 加密的随机验证块"),
+    field("verify_block_hmac", "32", 1, "HMAC-SHA256(CEK, 验证块原文)"),
+    field("file_hmac", "32", 1, "HMAC-SHA256(CEK, 解密后的全部密文)"),
+    field("metadata_length", "4", 1, "公开元数据的长度；v1 可能缺失，视为 0"),
+    field("metadata", "N", 1, "公开元数据（WMT1 容器或旧的 JSON），存放模糊缩略图"),
+    field("secret_metadata_length", "4", 4, "加密元数据的长度"),
+    field("secret_metadata", "N", 4, "Nonce(12) + ChaCha20(CEK, Nonce) 密文 + HMAC-SHA256(CEK, Nonce + 密文)"),
+    field("compression", "1", 5, "0 不压缩，1 zstd 分块压缩"),
+    field("segment_size", "4", 6, "分段存储时每段密文的长度，0 表示不分段"),
+    field("segment_count", "4", 6, "分段文件的数量"),
+    field("size_padding", "1", 7, "0 不填充，1 PADMÉ 长度填充"),
+    field("segment_base", "4", 9, "分段文件编号的起点"),
+    field("padding_length", "4", 3, "预留填充的长度"),
+    field("padding", "N", 3, "全部为 0，直接跳过"),
+];
+
+const ENTRY_FIELDS: &[Field] = &[
+    field("salt", "16", 1, "Argon2id 的盐"),
+    field("kek_nonce", "12", 1, "KEK 对应的 Nonce"),
+    field("encrypted_cek", "32", 1, "ChaCha20(KEK, KEK Nonce) 加密的 CEK"),
+    field("encrypted_cek_nonce", "12", 1, "ChaCha20(KEK, KEK Nonce) 加密的 CEK Nonce，计数器同样从 0 开始"),
+    field("created_at", "8", 2, "创建时间 (Unix 秒)"),
+    field("label_kind", "1", 2, "0 无标签，1 明文标签，2 使用 CEK 加密的标签"),
+    field("label_length", "2", 2, "标签长度"),
+    field("label_nonce", "12", 2, "仅 label_kind 为 2 时存在"),
+    field("label", "N", 2, "标签"),
+    field("kdf", "1", 8, "0 由密码派生 KEK，1 由保险库主密钥派生 KEK"),
+];
+
+const STEPS: &[&str] = &[
+    "按 header 的顺序读取文件头，整数均为大端序；since 大于文件版本的字段不存在",
+    "对每个密码块：KEK = Argon2id(密码的 UTF-8 字节, salt)，用 ChaCha20(KEK, kek_nonce) 分别解密 encrypted_cek 和 encrypted_cek_nonce",
+    "kdf 为 1 的密码块：先用密码按同样的方法解锁 vault_kdf.config 指向的保险库配置文件，主密钥 = HKDF-SHA256(配置文件的 CEK, 32 字节 0, vault_kdf.master_info)，KEK = HKDF-SHA256(主密钥, salt, vault_kdf.info)",
+    "用 ChaCha20(CEK, CEK Nonce) 解密验证块，HMAC-SHA256(CEK, 验证块) 等于 verify_block_hmac 时 CEK 正确，否则尝试下一个密码块",
+    "密文紧跟在文件头之后；segment_count 不为 0 时密文依次存放在 <文件>.{segment_base + 1}、<文件>.{segment_base + 2} ……（编号至少 6 位，不足时补 0，例如 <文件>.000001），每个分段为 密文 + HMAC-SHA256(CEK, \"WSEG\" + 分段序号(8, 从 1 开始) + 密文)",
+    "用 ChaCha20(CEK, CEK Nonce) 解密全部密文，HMAC-SHA256(CEK, 解密后的数据) 应等于 file_hmac",
+    "size_padding 为 1 时，解密后数据的最后 8 字节为内容长度，只保留前面的内容",
+    "compression 为 1 时，内容为各块 zstd 数据依次拼接，末尾为块索引：每块压缩后的长度(4) × N + 块大小(4) + 原文长度(8) + 块数 N(4)",
+];
+
+#[derive(Serialize, Debug)]
+struct KdfSpec {
+    algorithm: &'static str,
+    version: u32,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    salt_len: usize,
+    output_len: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct VaultKdfSpec {
+    algorithm: &'static str,
+    master_info: String,
+    info: String,
+    config: String,         // 保险库配置文件在导出包中的路径，没有使用主密钥的文件时为空
+    output_len: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct CipherSpec {
+    algorithm: &'static str,
+    key_len: usize,
+    nonce_len: usize,
+    initial_counter: u32,
+}
+
+// 导出包中的一个文件
+#[derive(Serialize, Debug)]
+struct BundleFile {
+    path: String,
+    version: u8,
+    compression: &'static str,
+    size_padding: &'static str,
+    segments: Vec<String>,
+    vault: bool,            // 是否需要保险库配置文件
+}
+
+#[derive(Serialize, Debug)]
+struct FormatSpec {
+    format: &'static str,
+    magic: String,
+    version: u8,
+    byte_order: &'static str,
+    kdf: KdfSpec,
+    vault_kdf: VaultKdfSpec,
+    cipher: CipherSpec,
+    mac: &'static str,
+    header: &'static [Field],
+    entry: &'static [Field],
+    steps: &'static [&'static str],
+    reference_decrypter: &'static str,
+    files: Vec<BundleFile>,
+}
+
+fn compression_name(compression: u8) -> &'static str {
+    match compression {
+        COMPRESSION_NONE => "none",
+        COMPRESSION_ZSTD => "zstd",
+        _ => "unknown",
+    }
+}
+
+fn padding_name(size_padding: u8) -> &'static str {
+    match size_padding {
+        SIZE_PADDING_NONE => "none",
+        SIZE_PADDING_PADME => "padme",
+        _ => "unknown",
+    }
+}
+
+// 需要复制的文件：(源文件路径, 导出包中的路径)
+type CopyList = Vec<(String, String)>;
+
+// 读取每个文件的格式信息，返回需要复制的文件和格式说明中的文件列表；使用主密钥的文件连同保险库配置文件一起复制
+fn describe(paths: &[&str]) -> Result<(CopyList, Vec<BundleFile>)> {
+    let mut names = HashSet::new();
+    let mut copies = Vec::new();
+    let mut files = Vec::new();
+    let mut vault_config = None;
+    for path in paths {
+        let header = parse_header(path).with_context(|| format!("不是有效的加密文件: {}", path))?;
+        let name = Path::new(path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if !names.insert(name.clone()) {
+            bail!("文件名重复: {}", name);
+        }
+        let target = format!("{}/{}", FILES_DIR, name);
+        let segments: Vec<String> = (1..=header.segment_count).map(|i| header_segment_path(&target, &header, i)).collect();
+        let vault = header.entries.iter().any(|e| e.kdf == ENTRY_KDF_VAULT);
+        if vault {
+            let Some(config) = find_vault_config(Path::new(path)) else { bail!("找不到保险库配置文件: {}", path) };
+            if vault_config.get_or_insert_with(|| config.clone()) != &config {
+                bail!("文件来自不同的保险库: {}", path);
+            }
+        }
+        copies.push((path.to_string(), target.clone()));
+        for i in 1..=header.segment_count {
+            copies.push((header_segment_path(path, &header, i), header_segment_path(&target, &header, i)));
+        }
+        files.push(BundleFile {
+            path: target,
+            version: header.version,
+            compression: compression_name(header.compression),
+            size_padding: padding_name(header.size_padding),
+            segments,
+            vault,
+        });
+    }
+    if let Some(config) = vault_config {
+        copies.push((config.to_string_lossy().to_string(), format!("{}/{}", FILES_DIR, VAULT_CONFIG_FILE)));
+    }
+    Ok((copies, files))
+}
+
+fn format_spec(files: Vec<BundleFile>) -> FormatSpec {
+    let has_vault = files.iter().any(|f| f.vault);
+    FormatSpec {
+        format: "little-secret .cry",
+        magic: String::from_utf8_lossy(HEADER_MAGIC).to_string(),
+        version: HEADER_VERSION,
+        byte_order: "big-endian",
+        kdf: KdfSpec {
+            algorithm: "argon2id",
+            version: 0x13,
+            memory_kib: KDF_MEMORY_KIB,
+            iterations: KDF_ITERATIONS,
+            parallelism: KDF_PARALLELISM,
+            salt_len: 16,
+            output_len: 32,
+        },
+        vault_kdf: VaultKdfSpec {
+            algorithm: "hkdf-sha256",
+            master_info: String::from_utf8_lossy(VAULT_MASTER_INFO).to_string(),
+            info: String::from_utf8_lossy(VAULT_KDF_INFO).to_string(),
+            config: if has_vault { format!("{}/{}", FILES_DIR, VAULT_CONFIG_FILE) } else { String::new() },
+            output_len: 32,
+        },
+        cipher: CipherSpec { algorithm: "chacha20 (RFC 8439)", key_len: 32, nonce_len: 12, initial_counter: 0 },
+        mac: "hmac-sha256",
+        header: HEADER_FIELDS,
+        entry: ENTRY_FIELDS,
+        steps: STEPS,
+        reference_decrypter: DECRYPTER_FILE,
+        files,
+    }
+}
+
+// 把 .cry 文件原样打包为自描述的导出包，返回文件数量
+pub fn export(paths: &[&str], output: &str) -> Result<usize> {
+    let (copies, files) = describe(paths)?;
+    let count = files.len();
+    let spec = serde_json::to_vec_pretty(&format_spec(files))?;
+
+    // 内容已经加密，压缩没有意义
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored).large_file(true);
+    let mut writer = ZipWriter::new(File::create(output).context("创建导出包失败")?);
+    for (name, data) in [(README_FILE, README.as_bytes()), (FORMAT_FILE, &spec[..]), (DECRYPTER_FILE, REFERENCE_DECRYPTER.as_bytes())] {
+        writer.start_file(name, options).context("写入导出包失败")?;
+        writer.write_all(data).context("写入导出包失败")?;
+    }
+    for (source, target) in &copies {
+        let mut file = File::open(source).with_context(|| format!("打开文件失败: {}", source))?;
+        writer.start_file(target.as_str(), options).context("写入导出包失败")?;
+        io::copy(&mut file, &mut writer).with_context(|| format!("写入导出包失败: {}", source))?;
+    }
+    writer.finish().context("写入导出包失败")?;
+    Ok(count)
+}
+
+// 把选中的 .cry 文件连同格式说明和参考解密程序导出为 ZIP
+#[tauri::command]
+pub fn export_bundle(paths: Vec<&str>, output: &str) -> String {
+    if paths.is_empty() { return "导出失败: 没有选择文件！".to_string(); }
+    match export(&paths, output) {
+        Ok(n) => format!("导出完毕：{} 个文件", n),
+        Err(e) => {
+            let _ = std::fs::remove_file(output);
+            format!("导出失败: {:#}", e)
+        }
+    }
+}
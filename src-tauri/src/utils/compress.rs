@@ -0,0 +1,293 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use chacha20::ChaCha20;
+use cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use hmac::Mac;
+use serde::{Deserialize, Serialize};
+
+// 文件头中的压缩方式
+pub const COMPRESSION_NONE: u8 = 0;
+pub const COMPRESSION_ZSTD: u8 = 1;
+
+// 每块原文的大小，每块单独压缩，便于随机读取
+pub const BLOCK_SIZE: u32 = 1024 * 1024;
+const ZSTD_LEVEL: i32 = 3;
+// 索引末尾的固定部分：块大小(4) + 原文长度(8) + 块数(4)
+const TRAILER_TAIL: u64 = 16;
+
+// 已经压缩过的格式，自动模式下不再压缩
+const COMPRESSED_EXTENSIONS: [&str; 34] = [
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "heif", "avif",
+    "mp4", "m4v", "mov", "mkv", "webm", "avi", "3gp",
+    "mp3", "m4a", "aac", "ogg", "opus", "flac",
+    "zip", "gz", "tgz", "7z", "rar", "xz", "bz2", "zst",
+    "docx", "xlsx", "pptx", "odt", "cry",
+];
+
+// 加密前是否压缩
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,   // 不压缩
+    Zstd,   // 总是压缩
+    Auto,   // 跳过 JPEG、MP4、ZIP 等已压缩的格式
+}
+
+impl Compression {
+    // 决定某个文件实际使用的压缩方式
+    pub fn resolve(&self, path: &str) -> u8 {
+        match self {
+            Compression::None => COMPRESSION_NONE,
+            Compression::Zstd => COMPRESSION_ZSTD,
+            Compression::Auto if already_compressed(path) => COMPRESSION_NONE,
+            Compression::Auto => COMPRESSION_ZSTD,
+        }
+    }
+
+    // 只能按文件名判断时使用（例如加密从容器中取出的数据流），自动模式下跳过已压缩的扩展名
+    pub fn for_name(&self, name: &str) -> Compression {
+        match self {
+            Compression::Auto if compressed_extension(name) => Compression::None,
+            other => *other,
+        }
+    }
+}
+
+fn compressed_extension(path: &str) -> bool {
+    let ext = Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    COMPRESSED_EXTENSIONS.contains(&ext.as_str())
+}
+
+// 按扩展名和文件头判断是否为已压缩的格式
+fn already_compressed(path: &str) -> bool {
+    if compressed_extension(path) {
+        return true;
+    }
+    let mut head = [0u8; 12];
+    let Ok(n) = File::open(path).and_then(|mut f| f.read(&mut head)) else { return false };
+    let head = &head[..n];
+    let magics: [&[u8]; 12] = [
+        b"\xFF\xD8\xFF", b"\x89PNG", b"GIF8", b"PK\x03\x04", b"\x1F\x8B", b"7z\xBC\xAF",
+        b"\x28\xB5\x2F\xFD", b"\xFD7zXZ", b"BZh", b"Rar!", b"OggS", b"\x1A\x45\xDF\xA3",
+    ];
+    magics.iter().any(|m| head.starts_with(m))
+        || (head.len() >= 8 && &head[4..8] == b"ftyp")
+        || (head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP")
+}
+
+// 压缩块的索引，存放在密文末尾，与压缩数据一起加密并计入 File HMAC
+// 格式：每块压缩后的长度(4, BE) × N + 块大小(4, BE) + 原文长度(8, BE) + 块数 N(4, BE)
+#[derive(Debug, Clone)]
+pub struct BlockIndex {
+    pub block_size: u32,
+    pub original_len: u64,
+    pub lens: Vec<u32>,
+    pub offsets: Vec<u64>,      // 每块在压缩数据中的起始位置
+}
+
+impl BlockIndex {
+    fn new(block_size: u32, original_len: u64, lens: Vec<u32>) -> BlockIndex {
+        let mut offsets = Vec::with_capacity(lens.len());
+        let mut offset = 0;
+        for len in &lens {
+            offsets.push(offset);
+            offset += *len as u64;
+        }
+        BlockIndex { block_size, original_len, lens, offsets }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.lens.len() * 4 + TRAILER_TAIL as usize);
+        for len in &self.lens {
+            out.write_u32::<BigEndian>(*len).unwrap();
+        }
+        out.write_u32::<BigEndian>(self.block_size).unwrap();
+        out.write_u64::<BigEndian>(self.original_len).unwrap();
+        out.write_u32::<BigEndian>(self.lens.len() as u32).unwrap();
+        out
+    }
+
+    // 压缩数据的总长度（不含索引）
+    pub fn data_len(&self) -> u64 {
+        self.lens.iter().map(|l| *l as u64).sum()
+    }
+
+    // 第 i 块原文的长度
+    pub fn block_len(&self, i: usize) -> usize {
+        let start = i as u64 * self.block_size as u64;
+        (self.original_len - start).min(self.block_size as u64) as usize
+    }
+
+    // 从压缩数据末尾读取索引，content_start 为密文在文件中的起始位置，content_len 为压缩数据和索引的总长度（不含长度填充）
+    pub fn read<R: Read + Seek>(reader: &mut R, content_start: u64, content_len: u64, cek: &[u8; 32], cek_nonce: &[u8; 12]) -> Result<BlockIndex> {
+        if content_len < TRAILER_TAIL {
+            bail!("压缩索引已损坏");
+        }
+        let mut cipher = ChaCha20::new(&(*cek).into(), &(*cek_nonce).into());
+        let mut decrypt_at = |reader: &mut R, pos: u64, len: usize| -> Result<Vec<u8>> {
+            let mut buf = vec![0u8; len];
+            reader.seek(SeekFrom::Start(content_start + pos))?;
+            reader.read_exact(&mut buf)?;
+            cipher.seek(pos);
+            cipher.apply_keystream(&mut buf);
+            Ok(buf)
+        };
+
+        let mut tail = &decrypt_at(reader, content_len - TRAILER_TAIL, TRAILER_TAIL as usize)?[..];
+        let block_size = tail.read_u32::<BigEndian>()?;
+        let original_len = tail.read_u64::<BigEndian>()?;
+        let count = tail.read_u32::<BigEndian>()? as u64;
+        let index_len = count * 4 + TRAILER_TAIL;
+        // 块大小固定为 BLOCK_SIZE，不接受其它值，避免按损坏或伪造的块大小分配内存
+        if block_size != BLOCK_SIZE || index_len > content_len || original_len.div_ceil(block_size as u64) != count {
+            bail!("压缩索引已损坏");
+        }
+        let mut lens_bytes = &decrypt_at(reader, content_len - index_len, count as usize * 4)?[..];
+        let lens = (0..count).map(|_| lens_bytes.read_u32::<BigEndian>()).collect::<std::io::Result<Vec<u32>>>()?;
+        if lens.iter().any(|len| *len as usize > zstd::zstd_safe::compress_bound(BLOCK_SIZE as usize)) {
+            bail!("压缩索引已损坏");
+        }
+        let index = BlockIndex::new(block_size, original_len, lens);
+        if index.data_len() + index_len != content_len {
+            bail!("压缩索引与数据长度不一致");
+        }
+        Ok(index)
+    }
+}
+
+// 读满 buf，直到文件结尾
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+// 分块压缩并加密，最后写入索引；hmac 计算的是加密前的压缩数据和索引，返回写入的总长度
+pub fn compress_stream<R: Read, W: Write, M: Mac>(reader: &mut R, writer: &mut W, cipher: &mut ChaCha20, hmac: &mut M) -> Result<u64> {
+    let mut buf = vec![0u8; BLOCK_SIZE as usize];
+    let mut lens = Vec::new();
+    let mut original_len = 0u64;
+    loop {
+        let n = read_full(reader, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let mut block = zstd::bulk::compress(&buf[..n], ZSTD_LEVEL).context("压缩失败")?;
+        hmac.update(&block);
+        cipher.apply_keystream(&mut block);
+        writer.write_all(&block)?;
+        lens.push(block.len() as u32);
+        original_len += n as u64;
+    }
+    let index = BlockIndex::new(BLOCK_SIZE, original_len, lens);
+    let mut trailer = index.to_bytes();
+    hmac.update(&trailer);
+    cipher.apply_keystream(&mut trailer);
+    writer.write_all(&trailer)?;
+    Ok(index.data_len() + trailer.len() as u64)
+}
+
+// 顺序解密并解压全部数据，同时计算 hmac；reader 需位于密文开头，content_len 为压缩数据和索引的总长度
+pub fn decompress_stream<R: Read + Seek, W: Write, M: Mac>(reader: &mut R, writer: &mut W, content_len: u64, cek: &[u8; 32], cek_nonce: &[u8; 12], hmac: &mut M) -> Result<()> {
+    let content_start = reader.stream_position()?;
+    let index = BlockIndex::read(reader, content_start, content_len, cek, cek_nonce)?;
+    reader.seek(SeekFrom::Start(content_start))?;
+    let mut cipher = ChaCha20::new(&(*cek).into(), &(*cek_nonce).into());
+    for (i, len) in index.lens.iter().enumerate() {
+        let mut block = vec![0u8; *len as usize];
+        reader.read_exact(&mut block)?;
+        cipher.apply_keystream(&mut block);
+        hmac.update(&block);
+        let data = zstd::bulk::decompress(&block, BLOCK_SIZE as usize).context("解压失败")?;
+        if data.len() != index.block_len(i) {
+            bail!("解压后的长度不正确");
+        }
+        writer.write_all(&data)?;
+    }
+    let mut trailer = vec![0u8; (content_len - index.data_len()) as usize];
+    reader.read_exact(&mut trailer)?;
+    cipher.apply_keystream(&mut trailer);
+    hmac.update(&trailer);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use hmac::Hmac;
+    use sha2::Sha256;
+
+    type HmacSha256 = Hmac<Sha256>;
+
+    const CEK: [u8; 32] = [3; 32];
+    const NONCE: [u8; 12] = [5; 12];
+
+    // 有重复也有变化的数据，压缩后每块长度不同
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i * 31 % 251) ^ (i >> 13)) as u8).collect()
+    }
+
+    // 压缩并加密，返回密文和 HMAC
+    fn compress(data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut cipher = ChaCha20::new(&CEK.into(), &NONCE.into());
+        let mut hmac = HmacSha256::new_from_slice(&CEK).unwrap();
+        let mut out = Vec::new();
+        let written = compress_stream(&mut Cursor::new(data), &mut out, &mut cipher, &mut hmac).unwrap();
+        assert_eq!(written, out.len() as u64);
+        (out, hmac.finalize().into_bytes().to_vec())
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        let block = BLOCK_SIZE as usize;
+        for len in [0, 1, block - 1, block, block + 1, 2 * block + 123] {
+            let data = sample(len);
+            let (content, expected) = compress(&data);
+
+            let index = BlockIndex::read(&mut Cursor::new(&content), 0, content.len() as u64, &CEK, &NONCE).unwrap();
+            assert_eq!(index.original_len, len as u64);
+            assert_eq!(index.lens.len(), len.div_ceil(block));
+
+            let mut out = Vec::new();
+            let mut hmac = HmacSha256::new_from_slice(&CEK).unwrap();
+            decompress_stream(&mut Cursor::new(&content), &mut out, content.len() as u64, &CEK, &NONCE, &mut hmac).unwrap();
+            assert!(out == data, "长度 {} 解压结果不同", len);
+            assert_eq!(hmac.finalize().into_bytes().to_vec(), expected);
+        }
+    }
+
+    #[test]
+    fn block_index_rejects_corruption() {
+        let (content, _) = compress(&sample(BLOCK_SIZE as usize + 10));
+        let read = |content: &[u8]| BlockIndex::read(&mut Cursor::new(content), 0, content.len() as u64, &CEK, &NONCE);
+        assert!(read(&content).is_ok());
+
+        // 流加密，修改密文的某一位即修改索引中对应的位
+        let tail = content.len() - TRAILER_TAIL as usize;
+        for (pos, bit) in [
+            (tail, 0x01),       // 块大小
+            (tail + 3, 0x01),
+            (tail + 4, 0x80),   // 原文长度
+            (tail + 12, 0x01),  // 块数
+            (tail - 4, 0x10),   // 最后一块的长度超过 compress_bound
+            (tail - 1, 0x01),   // 最后一块的长度与数据长度不一致
+        ] {
+            let mut bad = content.clone();
+            bad[pos] ^= bit;
+            assert!(read(&bad).is_err(), "位置 {} 的损坏没有被发现", pos);
+        }
+        assert!(read(&content[..TRAILER_TAIL as usize - 1]).is_err());
+        assert!(read(&content[1..]).is_err());
+    }
+}
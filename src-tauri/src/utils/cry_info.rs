@@ -1,19 +1,50 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::fs::{File, OpenOptions};
-use std::io::{Read, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::fs::{self, rename, File, OpenOptions};
+use std::io::{copy, sink, Read, BufReader, BufWriter, Seek, SeekFrom, Write};
 
-#[derive(Debug)]
+// 旧格式 (v1)：Magic 后直接是密码数量
+pub const HEADER_MAGIC_V1: &[u8; 4] = b"WCRY";
+// 新格式：Magic 后跟 1 字节版本号
+pub const HEADER_MAGIC: &[u8; 4] = b"WCRX";
+pub const HEADER_VERSION: u8 = 9;
+// 新文件头末尾预留的填充字节，修改密码或元数据时可原地覆盖头部
+pub const HEADER_PADDING: u32 = 1024;
+// 公开元数据和加密元数据的长度上限，超过时视为文件头已损坏
+const MAX_METADATA_LEN: usize = 64 * 1024 * 1024;
+
+// 密码块中标签的类型
+const LABEL_KIND_NONE: u8 = 0;
+const LABEL_KIND_PUBLIC: u8 = 1;
+const LABEL_KIND_ENCRYPTED: u8 = 2;
+
+// 密码块的 KEK 派生方式
+pub const ENTRY_KDF_PASSWORD: u8 = 0;  // KEK = Argon2id(密码, Salt)
+pub const ENTRY_KDF_VAULT: u8 = 1;     // KEK = HKDF-SHA256(保险库主密钥, Salt)，见 vault_key.rs
+
+// 密码块的标签
+#[derive(Debug, Clone)]
+pub enum EntryLabel {
+    None,
+    Public(String),                                  // 明文标签，任何人可见
+    Encrypted { nonce: [u8; 12], data: Vec<u8> },    // 使用 CEK 加密的标签，解锁后可见
+}
+
+#[derive(Debug, Clone)]
 pub struct Entry {
     pub salt: [u8; 16],
     pub kek_nonce: [u8; 12],
     pub encrypted_cek: [u8; 32],
     pub encrypted_cek_nonce: [u8; 12],
+    pub created_at: u64,    // 创建时间 (Unix 秒)，v1 格式为 0
+    pub label: EntryLabel,
+    pub kdf: u8,            // KEK 的派生方式，v8 之前为 ENTRY_KDF_PASSWORD
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FileHeader {
     pub magic: [u8; 4],
+    pub version: u8,
     pub password_count: u16,
     pub entries: Vec<Entry>,
     pub encrypted_verify_block: Vec<u8>, // 128B
@@ -21,139 +52,388 @@ pub struct FileHeader {
     pub file_hmac: [u8; 32],
     pub metadata_len: u32,
     pub metadata: Vec<u8>,
+    pub secret_metadata: Vec<u8>,   // 使用 CEK 加密的元数据 (Nonce + 密文 + HMAC)，v4 之前为空
+    pub compression: u8,    // 内容的压缩方式，见 compress.rs，v5 之前为 0（不压缩）
+    pub segment_size: u32,  // 分段存储时每段密文的长度，0 表示密文紧跟在头部之后，v6 之前为 0
+    pub segment_count: u32, // 分段文件的数量
+    pub size_padding: u8,   // 密文的长度填充方式，见 padding.rs，v7 之前为 0（不填充）
+    pub segment_base: u32,  // 分段文件编号的起点，第 i 段为 <文件>.{segment_base + i}，v9 之前为 0
+    pub padding_len: u32,   // 预留的填充长度，v3 之前为 0
+    pub header_len: u64,    // 磁盘上头部的总长度，密文从这里开始
 }
 
-pub fn parse_header(path: &str) -> Result<FileHeader> {
-    let mut file = BufReader::new(File::open(path).context("打开文件失败")?);
+impl FileHeader {
+    // 从当前位置读取文件头，读取完成后 reader 恰好位于密文开头
+    pub fn read_from<R: Read>(file: &mut R) -> Result<FileHeader> {
+        // 1. Magic (4B) 与版本号
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic).context("读取固定头部失败")?;
+        let version = if &magic == HEADER_MAGIC_V1 {
+            1
+        } else if &magic == HEADER_MAGIC {
+            file.read_u8()?
+        } else {
+            bail!("无效的文件头部，非 WCRY 格式");
+        };
+        if version > HEADER_VERSION {
+            bail!("不支持的文件版本: {}", version);
+        }
+        let mut header_len = if version == 1 { 4 } else { 5 };
 
-    // 1. Magic (4B)
-    let mut magic = [0u8; 4];
-    file.read_exact(&mut magic)?;
+        // 2. Password count (2B, BE)
+        let password_count = file.read_u16::<BigEndian>()?;
+        header_len += 2;
 
-    // 2. Password count (2B, BE)
-    let password_count = file.read_u16::<BigEndian>()?;
+        // 3. Entries
+        let mut entries = Vec::with_capacity(password_count as usize);
+        for _ in 0..password_count {
+            let mut salt = [0u8; 16];
+            let mut kek_nonce = [0u8; 12];
+            let mut encrypted_cek = [0u8; 32];
+            let mut encrypted_cek_nonce = [0u8; 12];
 
-    // 3. Entries
-    let mut entries = Vec::with_capacity(password_count as usize);
-    for _ in 0..password_count {
-        let mut salt = [0u8; 16];
-        let mut kek_nonce = [0u8; 12];
-        let mut encrypted_cek = [0u8; 32];
-        let mut encrypted_cek_nonce = [0u8; 12];
-
-        file.read_exact(&mut salt)?;
-        file.read_exact(&mut kek_nonce)?;
-        file.read_exact(&mut encrypted_cek)?;
-        file.read_exact(&mut encrypted_cek_nonce)?;
-
-        entries.push(Entry {
-            salt,
-            kek_nonce,
-            encrypted_cek,
-            encrypted_cek_nonce,
-        });
+            file.read_exact(&mut salt).context("读取密码条目失败")?;
+            file.read_exact(&mut kek_nonce)?;
+            file.read_exact(&mut encrypted_cek)?;
+            file.read_exact(&mut encrypted_cek_nonce)?;
+            header_len += 72;
+
+            let mut created_at = 0;
+            let mut label = EntryLabel::None;
+            if version >= 2 {
+                created_at = file.read_u64::<BigEndian>()?;
+                let kind = file.read_u8()?;
+                let label_len = file.read_u16::<BigEndian>()? as usize;
+                header_len += 8 + 1 + 2;
+                label = match kind {
+                    LABEL_KIND_NONE => EntryLabel::None,
+                    LABEL_KIND_PUBLIC => {
+                        let mut data = vec![0u8; label_len];
+                        file.read_exact(&mut data).context("读取密码标签失败")?;
+                        header_len += label_len as u64;
+                        EntryLabel::Public(String::from_utf8_lossy(&data).to_string())
+                    }
+                    LABEL_KIND_ENCRYPTED => {
+                        let mut nonce = [0u8; 12];
+                        let mut data = vec![0u8; label_len];
+                        file.read_exact(&mut nonce).context("读取密码标签失败")?;
+                        file.read_exact(&mut data).context("读取密码标签失败")?;
+                        header_len += 12 + label_len as u64;
+                        EntryLabel::Encrypted { nonce, data }
+                    }
+                    _ => bail!("未知的密码标签类型: {}", kind),
+                };
+            }
+
+            let mut kdf = ENTRY_KDF_PASSWORD;
+            if version >= 8 {
+                kdf = file.read_u8().context("读取密码块类型失败")?;
+                header_len += 1;
+            }
+
+            entries.push(Entry {
+                salt,
+                kek_nonce,
+                encrypted_cek,
+                encrypted_cek_nonce,
+                created_at,
+                label,
+                kdf,
+            });
+        }
+
+        // 4. Encrypted Verify Block (128B)
+        let mut encrypted_verify_block = vec![0u8; 128];
+        file.read_exact(&mut encrypted_verify_block).context("读取验证块失败")?;
+
+        // 5. Verify Block HMAC (32B)
+        let mut verify_block_hmac = [0u8; 32];
+        file.read_exact(&mut verify_block_hmac)?;
+
+        // 6. File HMAC (32B)
+        let mut file_hmac = [0u8; 32];
+        file.read_exact(&mut file_hmac)?;
+        header_len += 128 + 32 + 32;
+
+        // 7. Metadata Length (4B, BE) and Metadata
+        let mut metadata_len_buf = [0u8; 4];
+        let metadata_len = match file.read_exact(&mut metadata_len_buf) {
+            Ok(()) => {
+                header_len += 4;
+                u32::from_be_bytes(metadata_len_buf)
+            }
+            Err(_) if version == 1 => 0, // 向后兼容：旧格式没有元数据
+            Err(e) => return Err(e).context("读取元数据长度失败"),
+        };
+        let metadata = read_metadata(file, metadata_len, "元数据")?;
+        header_len += metadata_len as u64;
+
+        // 8. Secret Metadata Length (4B, BE) and Secret Metadata
+        let mut secret_metadata = Vec::new();
+        if version >= 4 {
+            let secret_len = file.read_u32::<BigEndian>().context("读取加密元数据长度失败")?;
+            secret_metadata = read_metadata(file, secret_len, "加密元数据")?;
+            header_len += 4 + secret_len as u64;
+        }
+
+        // 9. Compression (1B)
+        let mut compression = 0;
+        if version >= 5 {
+            compression = file.read_u8().context("读取压缩方式失败")?;
+            header_len += 1;
+        }
+
+        // 10. Segment Size (4B, BE) and Segment Count (4B, BE)
+        let mut segment_size = 0;
+        let mut segment_count = 0;
+        if version >= 6 {
+            segment_size = file.read_u32::<BigEndian>().context("读取分段信息失败")?;
+            segment_count = file.read_u32::<BigEndian>().context("读取分段信息失败")?;
+            header_len += 8;
+        }
+
+        // 11. Size Padding (1B)
+        let mut size_padding = 0;
+        if version >= 7 {
+            size_padding = file.read_u8().context("读取长度填充方式失败")?;
+            header_len += 1;
+        }
+
+        // 12. Segment Base (4B, BE)
+        let mut segment_base = 0;
+        if version >= 9 {
+            segment_base = file.read_u32::<BigEndian>().context("读取分段信息失败")?;
+            header_len += 4;
+        }
+        if segment_base.checked_add(segment_count).is_none() {
+            bail!("分段信息已损坏");
+        }
+
+        // 13. Padding Length (4B, BE) and Padding
+        let mut padding_len = 0;
+        if version >= 3 {
+            padding_len = file.read_u32::<BigEndian>().context("读取填充长度失败")?;
+            let skipped = copy(&mut file.by_ref().take(padding_len as u64), &mut sink())?;
+            if skipped != padding_len as u64 {
+                bail!("读取填充失败");
+            }
+            header_len += 4 + padding_len as u64;
+        }
+
+        Ok(FileHeader {
+            magic,
+            version,
+            password_count,
+            entries,
+            encrypted_verify_block,
+            verify_block_hmac,
+            file_hmac,
+            metadata_len,
+            metadata,
+            secret_metadata,
+            compression,
+            segment_size,
+            segment_count,
+            size_padding,
+            segment_base,
+            padding_len,
+            header_len,
+        })
     }
 
-    // 4. Encrypted Verify Block (128B)
-    let mut encrypted_verify_block = vec![0u8; 128];
-    file.read_exact(&mut encrypted_verify_block)?;
+    // 序列化文件头，总是写出当前版本的格式
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if self.entries.is_empty() {
+            bail!("至少需要一个密码");
+        }
+        if self.entries.len() > 65535 {
+            bail!("密码数量过多，最多支持 65535 个");
+        }
+        if self.metadata.len() > MAX_METADATA_LEN || self.secret_metadata.len() > MAX_METADATA_LEN {
+            bail!("元数据过长，最多支持 {} MB", MAX_METADATA_LEN >> 20);
+        }
+        let mut header = Vec::with_capacity(7 + self.entries.len() * 96 + 128 + 32 + 32 + 26 + self.metadata.len() + self.secret_metadata.len() + self.padding_len as usize);
+        // Magic (4B) + Version (1B)
+        header.extend_from_slice(HEADER_MAGIC);
+        header.write_u8(HEADER_VERSION)?;
+        // Password Count (2B, BE)
+        header.write_u16::<BigEndian>(self.entries.len() as u16)?;
+        // Entries
+        for entry in &self.entries {
+            header.extend_from_slice(&entry.salt);
+            header.extend_from_slice(&entry.kek_nonce);
+            header.extend_from_slice(&entry.encrypted_cek);
+            header.extend_from_slice(&entry.encrypted_cek_nonce);
+            header.write_u64::<BigEndian>(entry.created_at)?;
+            match &entry.label {
+                EntryLabel::None => {
+                    header.write_u8(LABEL_KIND_NONE)?;
+                    header.write_u16::<BigEndian>(0)?;
+                }
+                EntryLabel::Public(label) => {
+                    if label.len() > 65535 {
+                        bail!("密码标签过长");
+                    }
+                    header.write_u8(LABEL_KIND_PUBLIC)?;
+                    header.write_u16::<BigEndian>(label.len() as u16)?;
+                    header.extend_from_slice(label.as_bytes());
+                }
+                EntryLabel::Encrypted { nonce, data } => {
+                    if data.len() > 65535 {
+                        bail!("密码标签过长");
+                    }
+                    header.write_u8(LABEL_KIND_ENCRYPTED)?;
+                    header.write_u16::<BigEndian>(data.len() as u16)?;
+                    header.extend_from_slice(nonce);
+                    header.extend_from_slice(data);
+                }
+            }
+            header.write_u8(entry.kdf)?;
+        }
+        // Encrypted Verify Block (128B)
+        header.extend_from_slice(&self.encrypted_verify_block);
+        // Verify Block HMAC (32B)
+        header.extend_from_slice(&self.verify_block_hmac);
+        // File HMAC (32B)
+        header.extend_from_slice(&self.file_hmac);
+        // Metadata Length (4B, BE) + Metadata
+        header.write_u32::<BigEndian>(self.metadata.len() as u32)?;
+        header.extend_from_slice(&self.metadata);
+        // Secret Metadata Length (4B, BE) + Secret Metadata
+        header.write_u32::<BigEndian>(self.secret_metadata.len() as u32)?;
+        header.extend_from_slice(&self.secret_metadata);
+        // Compression (1B)
+        header.write_u8(self.compression)?;
+        // Segment Size (4B, BE) + Segment Count (4B, BE)
+        header.write_u32::<BigEndian>(self.segment_size)?;
+        header.write_u32::<BigEndian>(self.segment_count)?;
+        // Size Padding (1B)
+        header.write_u8(self.size_padding)?;
+        // Segment Base (4B, BE)
+        header.write_u32::<BigEndian>(self.segment_base)?;
+        // Padding Length (4B, BE) + Padding
+        header.write_u32::<BigEndian>(self.padding_len)?;
+        header.resize(header.len() + self.padding_len as usize, 0);
+        Ok(header)
+    }
 
-    // 5. Verify Block HMAC (32B)
-    let mut verify_block_hmac = [0u8; 32];
-    file.read_exact(&mut verify_block_hmac)?;
+    // 写回文件头：预留空间足够时原地覆盖并 fsync，否则流式复制到临时文件再原子替换
+    // header_len 需为文件当前头部的长度，写入后更新为新的长度
+    pub fn write_to_file(&mut self, path: &str) -> Result<()> {
+        let old_header_len = self.header_len;
+        self.padding_len = 0;
+        let unpadded_len = self.to_bytes()?.len() as u64;
 
-    // 6. File HMAC (32B)
-    let mut file_hmac = [0u8; 32];
-    file.read_exact(&mut file_hmac)?;
+        // 有多个硬链接（例如去重后）的文件不原地修改，而是复制后替换，避免同时修改其它路径的文件
+        if unpadded_len <= old_header_len && !is_linked(path) {
+            self.padding_len = (old_header_len - unpadded_len) as u32;
+            let bytes = self.to_bytes()?;
+            let mut file = OpenOptions::new().write(true).open(path).context("打开文件以写入失败")?;
+            file.write_all(&bytes).context("写入文件头失败")?;
+            file.sync_all().context("同步文件失败")?;
+            return Ok(());
+        }
 
-    // 7. Metadata Length (4B, BE) and Metadata
-    let mut metadata_len_buf = [0u8; 4];
-    let metadata_len = match file.read_exact(&mut metadata_len_buf) {
-        Ok(()) => u32::from_be_bytes(metadata_len_buf),
-        Err(_) => 0, // 向后兼容：旧格式没有元数据
-    };
-    let mut metadata = vec![0u8; metadata_len as usize];
-    if metadata_len > 0 {
-        file.read_exact(&mut metadata).context("读取元数据失败")?;
+        self.padding_len = HEADER_PADDING;
+        let bytes = self.to_bytes()?;
+        let mut file = File::open(path).context("打开文件失败")?;
+        file.seek(SeekFrom::Start(old_header_len)).context("设置文件指针失败")?;
+        let tmp_path = format!("{}.tmp", path);
+        let mut tmp_file = BufWriter::new(File::create(&tmp_path).context("创建临时文件失败")?);
+        tmp_file.write_all(&bytes).context("写入文件头失败")?;
+        copy(&mut file, &mut tmp_file).context("复制加密数据失败")?;
+        let tmp_file = tmp_file.into_inner().map_err(|e| e.into_error()).context("写入临时文件失败")?;
+        tmp_file.sync_all().context("同步文件失败")?;
+        rename(&tmp_path, path).context("替换文件失败")?;
+        self.header_len = bytes.len() as u64;
+        Ok(())
     }
+}
 
-    Ok(FileHeader {
-        magic,
-        password_count,
-        entries,
-        encrypted_verify_block,
-        verify_block_hmac,
-        file_hmac,
-        metadata_len,
-        metadata,
-    })
+// 文件是否有多个硬链接；Windows 上稳定版 Rust 无法读取链接数，视为没有
+#[cfg(unix)]
+fn is_linked(path: &str) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).map(|m| m.nlink() > 1).unwrap_or(false)
 }
 
-pub fn print_header_info(path: &str) -> Result<()> {
+#[cfg(not(unix))]
+fn is_linked(_path: &str) -> bool {
+    false
+}
+
+// 读取长度为 len 的元数据；长度来自文件，先检查上限，再按实际读到的数据分配内存
+fn read_metadata<R: Read>(file: &mut R, len: u32, name: &str) -> Result<Vec<u8>> {
+    if len as usize > MAX_METADATA_LEN {
+        bail!("{}过长（{} 字节），文件头可能已损坏", name, len);
+    }
+    let mut data = Vec::new();
+    file.by_ref().take(len as u64).read_to_end(&mut data).with_context(|| format!("读取{}失败", name))?;
+    if data.len() != len as usize {
+        bail!("读取{}失败: 文件已截断", name);
+    }
+    Ok(data)
+}
+
+pub fn parse_header(path: &str) -> Result<FileHeader> {
     let mut file = BufReader::new(File::open(path).context("打开文件失败")?);
+    FileHeader::read_from(&mut file)
+}
+
+pub fn print_header_info(path: &str) -> Result<()> {
+    let header = parse_header(path)?;
 
     // 1. Magic (4B)
-    let mut magic = [0u8; 4];
-    file.read_exact(&mut magic)?;
-    println!("Magic: {:?}", String::from_utf8_lossy(&magic));
+    println!("Magic: {:?}", String::from_utf8_lossy(&header.magic));
+    println!("Version: {}", header.version);
 
     // 2. Password count (2B, BE)
-    let pw_count = file.read_u16::<BigEndian>()?;
-    println!("Password Count: {}", pw_count);
+    println!("Password Count: {}", header.password_count);
 
     // 3. Entries
-    for i in 0..pw_count {
-        let mut salt = [0u8; 16];
-        let mut kek_nonce = [0u8; 12];
-        let mut enc_cek = [0u8; 32];
-        let mut enc_cek_nonce = [0u8; 12];
-
-        file.read_exact(&mut salt)?;
-        file.read_exact(&mut kek_nonce)?;
-        file.read_exact(&mut enc_cek)?;
-        file.read_exact(&mut enc_cek_nonce)?;
-
+    for (i, entry) in header.entries.iter().enumerate() {
         println!("--- Entry {} ---", i);
-        println!("Salt           : {:02X?}", salt);
-        println!("KEK Nonce      : {:02X?}", kek_nonce);
-        println!("Encrypted CEK  : {:02X?}", enc_cek);
-        println!("Enc CEK Nonce  : {:02X?}", enc_cek_nonce);
+        println!("Salt           : {:02X?}", entry.salt);
+        println!("KEK Nonce      : {:02X?}", entry.kek_nonce);
+        println!("Encrypted CEK  : {:02X?}", entry.encrypted_cek);
+        println!("Enc CEK Nonce  : {:02X?}", entry.encrypted_cek_nonce);
+        println!("Created At     : {}", entry.created_at);
+        match &entry.label {
+            EntryLabel::None => println!("Label          : None"),
+            EntryLabel::Public(label) => println!("Label          : {}", label),
+            EntryLabel::Encrypted { .. } => println!("Label          : (加密)"),
+        }
+        println!("KDF            : {}", entry.kdf);
     }
 
     // 4. Encrypted Verify Block (128B)
-    let mut encrypted_verify_block = vec![0u8; 128];
-    file.read_exact(&mut encrypted_verify_block)?;
-    println!("Encrypted Verify Block (128B): {:02X?}", &encrypted_verify_block[..16]); // 只打印前16字节
+    println!("Encrypted Verify Block (128B): {:02X?}", &header.encrypted_verify_block[..16]); // 只打印前16字节
 
     // 5. Verify Block HMAC (32B)
-    let mut verify_block_hmac = [0u8; 32];
-    file.read_exact(&mut verify_block_hmac)?;
-    println!("Verify Block HMAC: {:02X?}", verify_block_hmac);
+    println!("Verify Block HMAC: {:02X?}", header.verify_block_hmac);
 
     // 6. File HMAC (32B)
-    let mut file_hmac = [0u8; 32];
-    file.read_exact(&mut file_hmac)?;
-    println!("File HMAC: {:02X?}", file_hmac);
+    println!("File HMAC: {:02X?}", header.file_hmac);
 
     // 7. Metadata Length (4B, BE) and Metadata
-    let mut metadata_len_buf = [0u8; 4];
-    let metadata_len = match file.read_exact(&mut metadata_len_buf) {
-        Ok(()) => u32::from_be_bytes(metadata_len_buf),
-        Err(_) => 0, // 向后兼容：旧格式没有元数据
-    };
-    println!("Metadata Length: {}", metadata_len);
-    if metadata_len > 0 {
-        let mut metadata = vec![0u8; metadata_len as usize];
-        file.read_exact(&mut metadata).context("读取元数据失败")?;
+    println!("Metadata Length: {}", header.metadata_len);
+    if header.metadata_len > 0 {
         // 尝试将元数据作为 UTF-8 打印，若失败则打印十六进制
-        match String::from_utf8(metadata.clone()) {
+        match String::from_utf8(header.metadata.clone()) {
             Ok(s) => println!("Metadata (UTF-8): {}", s),
-            Err(_) => println!("Metadata (Hex): {:02X?}", metadata),
+            Err(_) => println!("Metadata (Hex): {:02X?}", header.metadata),
         }
     } else {
         println!("Metadata: None");
     }
 
+    println!("Secret Metadata Length: {}", header.secret_metadata.len());
+    println!("Compression: {}", header.compression);
+    println!("Segment Size: {}, Segment Count: {}", header.segment_size, header.segment_count);
+    println!("Size Padding: {}", header.size_padding);
+    println!("Segment Base: {}", header.segment_base);
+    println!("Padding Length: {}", header.padding_len);
+
     println!("(剩余部分为加密后的文件数据)");
 
     Ok(())
@@ -161,50 +441,210 @@ pub fn print_header_info(path: &str) -> Result<()> {
 
 pub fn update_metadata(path: &str, new_metadata: Vec<u8>) -> Result<()> {
     // 1. 解析原始文件头
-    let header = parse_header(path).context("解析文件头失败")?;
+    let mut header = parse_header(path).context("解析文件头失败")?;
 
-    // 2. 读取文件剩余数据（加密文件内容）
-    let mut file = BufReader::new(File::open(path).context("打开文件失败")?);
-    let header_len = 4 + 2 + (header.password_count as usize * (16 + 12 + 32 + 12)) + 128 + 32 + 32 + 4 + header.metadata_len as usize;
-    file.seek(SeekFrom::Start(header_len as u64))?;
-    let mut remaining_data = Vec::new();
-    file.read_to_end(&mut remaining_data).context("读取剩余文件数据失败")?;
-
-    // 3. 打开文件以写入，覆盖原文件
-    let mut file = BufWriter::new(
-        OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(path)
-            .context("打开文件以写入失败")?,
-    );
-
-    // 4. 写入更新后的文件头
-    // Magic (4B)
-    file.write_all(&header.magic)?;
-    // Password Count (2B, BE)
-    file.write_u16::<BigEndian>(header.password_count)?;
-    // Entries
-    for entry in &header.entries {
-        file.write_all(&entry.salt)?;
-        file.write_all(&entry.kek_nonce)?;
-        file.write_all(&entry.encrypted_cek)?;
-        file.write_all(&entry.encrypted_cek_nonce)?;
+    // 2. 替换元数据并写回，加密内容保持不变
+    header.metadata_len = new_metadata.len() as u32;
+    header.metadata = new_metadata;
+    header.write_to_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_header() -> FileHeader {
+        let entry = |i: u8, label: EntryLabel, kdf: u8| Entry {
+            salt: [i; 16],
+            kek_nonce: [i + 1; 12],
+            encrypted_cek: [i + 2; 32],
+            encrypted_cek_nonce: [i + 3; 12],
+            created_at: 1_700_000_000 + i as u64,
+            label,
+            kdf,
+        };
+        FileHeader {
+            magic: *HEADER_MAGIC,
+            version: HEADER_VERSION,
+            password_count: 3,
+            entries: vec![
+                entry(10, EntryLabel::Public("张三".to_string()), ENTRY_KDF_PASSWORD),
+                entry(20, EntryLabel::Encrypted { nonce: [7; 12], data: vec![1, 2, 3, 4] }, ENTRY_KDF_PASSWORD),
+                entry(30, EntryLabel::None, ENTRY_KDF_VAULT),
+            ],
+            encrypted_verify_block: (0..128).collect(),
+            verify_block_hmac: [0xaa; 32],
+            file_hmac: [0xbb; 32],
+            metadata_len: 5,
+            metadata: b"hello".to_vec(),
+            secret_metadata: vec![0xcc; 40],
+            compression: 1,
+            segment_size: 1024 * 1024,
+            segment_count: 3,
+            size_padding: 1,
+            segment_base: 6,
+            padding_len: 16,
+            header_len: 0,
+        }
     }
-    // Encrypted Verify Block (128B)
-    file.write_all(&header.encrypted_verify_block)?;
-    // Verify Block HMAC (32B)
-    file.write_all(&header.verify_block_hmac)?;
-    // File HMAC (32B)
-    file.write_all(&header.file_hmac)?;
-    // Metadata Length (4B, BE)
-    file.write_u32::<BigEndian>(new_metadata.len() as u32)?;
-    // Metadata
-    file.write_all(&new_metadata)?;
-
-    // 5. 写入剩余的加密文件数据
-    file.write_all(&remaining_data)?;
-    file.flush().context("写入文件失败")?;
 
-    Ok(())
-}
\ No newline at end of file
+    // 按旧版本的布局写出文件头，该版本没有的字段不写
+    fn bytes_for_version(h: &FileHeader, version: u8) -> Vec<u8> {
+        let mut out = Vec::new();
+        if version == 1 {
+            out.extend_from_slice(HEADER_MAGIC_V1);
+        } else {
+            out.extend_from_slice(HEADER_MAGIC);
+            out.push(version);
+        }
+        out.write_u16::<BigEndian>(h.entries.len() as u16).unwrap();
+        for e in &h.entries {
+            out.extend_from_slice(&e.salt);
+            out.extend_from_slice(&e.kek_nonce);
+            out.extend_from_slice(&e.encrypted_cek);
+            out.extend_from_slice(&e.encrypted_cek_nonce);
+            if version >= 2 {
+                out.write_u64::<BigEndian>(e.created_at).unwrap();
+                match &e.label {
+                    EntryLabel::None => out.extend_from_slice(&[LABEL_KIND_NONE, 0, 0]),
+                    EntryLabel::Public(label) => {
+                        out.push(LABEL_KIND_PUBLIC);
+                        out.write_u16::<BigEndian>(label.len() as u16).unwrap();
+                        out.extend_from_slice(label.as_bytes());
+                    }
+                    EntryLabel::Encrypted { nonce, data } => {
+                        out.push(LABEL_KIND_ENCRYPTED);
+                        out.write_u16::<BigEndian>(data.len() as u16).unwrap();
+                        out.extend_from_slice(nonce);
+                        out.extend_from_slice(data);
+                    }
+                }
+            }
+            if version >= 8 {
+                out.push(e.kdf);
+            }
+        }
+        out.extend_from_slice(&h.encrypted_verify_block);
+        out.extend_from_slice(&h.verify_block_hmac);
+        out.extend_from_slice(&h.file_hmac);
+        out.write_u32::<BigEndian>(h.metadata.len() as u32).unwrap();
+        out.extend_from_slice(&h.metadata);
+        if version >= 4 {
+            out.write_u32::<BigEndian>(h.secret_metadata.len() as u32).unwrap();
+            out.extend_from_slice(&h.secret_metadata);
+        }
+        if version >= 5 {
+            out.push(h.compression);
+        }
+        if version >= 6 {
+            out.write_u32::<BigEndian>(h.segment_size).unwrap();
+            out.write_u32::<BigEndian>(h.segment_count).unwrap();
+        }
+        if version >= 7 {
+            out.push(h.size_padding);
+        }
+        if version >= 9 {
+            out.write_u32::<BigEndian>(h.segment_base).unwrap();
+        }
+        if version >= 3 {
+            out.write_u32::<BigEndian>(h.padding_len).unwrap();
+            out.resize(out.len() + h.padding_len as usize, 0);
+        }
+        out
+    }
+
+    fn label_of(label: &EntryLabel) -> String {
+        format!("{:?}", label)
+    }
+
+    #[test]
+    fn header_current_version_round_trip() {
+        let header = sample_header();
+        let bytes = header.to_bytes().unwrap();
+        assert_eq!(bytes, bytes_for_version(&header, HEADER_VERSION));
+
+        // 密文紧跟在头部之后，读取后恰好停在密文开头
+        let mut data = bytes.clone();
+        data.extend_from_slice(b"ciphertext");
+        let mut reader = Cursor::new(data);
+        let parsed = FileHeader::read_from(&mut reader).unwrap();
+        assert_eq!(parsed.header_len, bytes.len() as u64);
+        assert_eq!(reader.position(), bytes.len() as u64);
+        assert_eq!(parsed.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn header_reads_every_version() {
+        let header = sample_header();
+        for version in 1..=HEADER_VERSION {
+            let bytes = bytes_for_version(&header, version);
+            let parsed = FileHeader::read_from(&mut Cursor::new(&bytes)).unwrap_or_else(|e| panic!("版本 {}: {}", version, e));
+            assert_eq!(parsed.version, version);
+            assert_eq!(parsed.header_len, bytes.len() as u64, "版本 {}", version);
+            assert_eq!(parsed.entries.len(), header.entries.len());
+            for (p, e) in parsed.entries.iter().zip(&header.entries) {
+                assert_eq!((p.salt, p.kek_nonce, p.encrypted_cek, p.encrypted_cek_nonce), (e.salt, e.kek_nonce, e.encrypted_cek, e.encrypted_cek_nonce));
+                assert_eq!(p.created_at, if version >= 2 { e.created_at } else { 0 });
+                assert_eq!(label_of(&p.label), if version >= 2 { label_of(&e.label) } else { label_of(&EntryLabel::None) });
+                assert_eq!(p.kdf, if version >= 8 { e.kdf } else { ENTRY_KDF_PASSWORD });
+            }
+            assert_eq!(parsed.encrypted_verify_block, header.encrypted_verify_block);
+            assert_eq!(parsed.metadata, header.metadata);
+            assert_eq!(parsed.secret_metadata, if version >= 4 { header.secret_metadata.clone() } else { Vec::new() });
+            assert_eq!(parsed.compression, if version >= 5 { header.compression } else { 0 });
+            assert_eq!((parsed.segment_size, parsed.segment_count), if version >= 6 { (header.segment_size, header.segment_count) } else { (0, 0) });
+            assert_eq!(parsed.size_padding, if version >= 7 { header.size_padding } else { 0 });
+            assert_eq!(parsed.segment_base, if version >= 9 { header.segment_base } else { 0 });
+            assert_eq!(parsed.padding_len, if version >= 3 { header.padding_len } else { 0 });
+
+            // 旧版本的文件头写回时升级为当前版本，再读取得到相同的字段
+            let upgraded = FileHeader::read_from(&mut Cursor::new(parsed.to_bytes().unwrap())).unwrap();
+            assert_eq!(upgraded.version, HEADER_VERSION);
+            assert_eq!(upgraded.entries.len(), parsed.entries.len());
+            assert_eq!(upgraded.secret_metadata, parsed.secret_metadata);
+            assert_eq!(upgraded.segment_count, parsed.segment_count);
+            assert_eq!(upgraded.segment_base, parsed.segment_base);
+        }
+    }
+
+    #[test]
+    fn header_v1_without_metadata() {
+        // v1 的文件头可以在文件 HMAC 之后直接结束
+        let header = sample_header();
+        let mut bytes = bytes_for_version(&header, 1);
+        bytes.truncate(bytes.len() - 4 - header.metadata.len());
+        let parsed = FileHeader::read_from(&mut Cursor::new(&bytes)).unwrap();
+        assert!(parsed.metadata.is_empty());
+        assert_eq!(parsed.header_len, bytes.len() as u64);
+    }
+
+    #[test]
+    fn header_rejects_invalid() {
+        let bytes = sample_header().to_bytes().unwrap();
+        let mut newer = bytes.clone();
+        newer[4] = HEADER_VERSION + 1;
+        assert!(FileHeader::read_from(&mut Cursor::new(newer)).is_err());
+        assert!(FileHeader::read_from(&mut Cursor::new(b"ABCD\x08".to_vec())).is_err());
+        for len in [3, 10, 100, bytes.len() - 1] {
+            assert!(FileHeader::read_from(&mut Cursor::new(&bytes[..len])).is_err(), "截断到 {} 没有报错", len);
+        }
+        let mut no_entries = sample_header();
+        no_entries.entries.clear();
+        assert!(no_entries.to_bytes().is_err());
+        let mut overflow = sample_header();
+        overflow.segment_base = u32::MAX;
+        assert!(FileHeader::read_from(&mut Cursor::new(overflow.to_bytes().unwrap())).is_err());
+
+        // 损坏的元数据长度超过上限或文件长度时报错，不会预先分配内存
+        let at = bytes.windows(9).position(|w| w == b"\0\0\0\x05hello").unwrap();
+        for len in [u32::MAX, 1_000_000] {
+            let mut corrupt = bytes.clone();
+            corrupt[at..at + 4].copy_from_slice(&len.to_be_bytes());
+            assert!(FileHeader::read_from(&mut Cursor::new(&corrupt)).is_err(), "元数据长度 {} 没有报错", len);
+        }
+        let mut huge = sample_header();
+        huge.metadata = vec![0; MAX_METADATA_LEN + 1];
+        assert!(huge.to_bytes().is_err());
+    }
+}
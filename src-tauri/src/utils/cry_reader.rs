@@ -0,0 +1,188 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use anyhow::{bail, Context, Result};
+use chacha20::ChaCha20;
+use cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use crate::utils::compress::{BlockIndex, BLOCK_SIZE, COMPRESSION_NONE, COMPRESSION_ZSTD};
+use crate::utils::cry_info::FileHeader;
+use crate::utils::encrypt::{verify_cek, FileKey};
+use crate::utils::padding::content_len;
+use crate::utils::segment::ContentReader;
+
+// 按原文位置随机读取加密文件，不需要先解密到临时文件
+// 未压缩的文件直接定位密钥流；压缩的文件按块索引找到所在的块，解压后缓存
+// 有长度填充时只读取填充前的内容
+// 注意：随机读取不校验 File HMAC，需要完整性保证时请使用 decrypt_file
+pub struct CryReader {
+    content: ContentReader,             // 密文，头部之后或分段文件
+    cipher: ChaCha20,
+    index: Option<BlockIndex>,
+    len: u64,                           // 原文长度
+    pos: u64,                           // 当前的原文位置
+    block: Option<(usize, Vec<u8>)>,    // 最近解压的块
+}
+
+impl CryReader {
+    pub fn open(path: &str, key: &FileKey) -> Result<CryReader> {
+        let mut file = BufReader::new(File::open(path).context("打开加密文件失败")?);
+        let header = FileHeader::read_from(&mut file)?;
+        if !verify_cek(&header, &key.cek, &key.cek_nonce) {
+            bail!("文件密钥不正确");
+        }
+        let mut content = ContentReader::new(file, path, &header, &key.cek)?;
+        let data_len = content_len(&mut content, header.size_padding, &key.cek, &key.cek_nonce)?;
+        let (index, len) = match header.compression {
+            COMPRESSION_NONE => (None, data_len),
+            COMPRESSION_ZSTD => {
+                let index = BlockIndex::read(&mut content, 0, data_len, &key.cek, &key.cek_nonce)?;
+                let len = index.original_len;
+                (Some(index), len)
+            }
+            other => bail!("不支持的压缩方式: {}", other),
+        };
+        Ok(CryReader {
+            content,
+            cipher: ChaCha20::new(&key.cek.into(), &key.cek_nonce.into()),
+            index,
+            len,
+            pos: 0,
+            block: None,
+        })
+    }
+
+    // 原文长度
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 读取并解密密文中 [offset, offset + buf.len()) 的内容
+    fn read_plain_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.content.seek(SeekFrom::Start(offset))?;
+        self.content.read_exact(buf)?;
+        self.cipher.seek(offset);
+        self.cipher.apply_keystream(buf);
+        Ok(())
+    }
+
+    // 解压第 i 块
+    fn load_block(&mut self, i: usize) -> io::Result<()> {
+        if matches!(&self.block, Some((cached, _)) if *cached == i) {
+            return Ok(());
+        }
+        let index = self.index.as_ref().unwrap();
+        let (offset, len, expected) = (index.offsets[i], index.lens[i], index.block_len(i));
+        let mut compressed = vec![0u8; len as usize];
+        self.read_plain_at(offset, &mut compressed)?;
+        let data = zstd::bulk::decompress(&compressed, BLOCK_SIZE as usize)?;
+        if data.len() != expected {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "解压后的长度不正确"));
+        }
+        self.block = Some((i, data));
+        Ok(())
+    }
+}
+
+impl Read for CryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let n = match &self.index {
+            None => {
+                let n = (buf.len() as u64).min(self.len - self.pos) as usize;
+                self.read_plain_at(self.pos, &mut buf[..n])?;
+                n
+            }
+            Some(index) => {
+                let block_size = index.block_size as u64;
+                let i = (self.pos / block_size) as usize;
+                self.load_block(i)?;
+                let data = &self.block.as_ref().unwrap().1;
+                let start = (self.pos - i as u64 * block_size) as usize;
+                let n = buf.len().min(data.len() - start);
+                buf[..n].copy_from_slice(&data[start..start + n]);
+                n
+            }
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for CryReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => p as i128,
+            SeekFrom::End(p) => self.len as i128 + p as i128,
+            SeekFrom::Current(p) => self.pos as i128 + p as i128,
+        };
+        if target < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "不能定位到文件开头之前"));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::utils::compress::Compression;
+    use crate::utils::encrypt::{encrypt_stream, unlock_file_with_master, EncryptOptions, EntrySpec, MasterKey};
+    use crate::utils::padding::SizePadding;
+    use crate::utils::segment::MIN_SEGMENT_SIZE;
+
+    const BLOCK: u64 = BLOCK_SIZE as u64;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i * 31 % 251) ^ (i >> 13)) as u8).collect()
+    }
+
+    #[test]
+    fn reader_seeks_across_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = sample(2 * BLOCK as usize + 777);
+        let master_key = MasterKey([2; 32]);
+        let cases = [
+            EncryptOptions::default(),
+            EncryptOptions { compression: Compression::Zstd, ..Default::default() },
+            EncryptOptions { compression: Compression::Zstd, segment_size: MIN_SEGMENT_SIZE, padding: SizePadding::Padme, ..Default::default() },
+        ];
+        for (i, options) in cases.into_iter().enumerate() {
+            let path = dir.path().join(format!("{}.cry", i)).to_string_lossy().to_string();
+            encrypt_stream(Cursor::new(&data), &path, &[EntrySpec::from_master_key(&master_key)], None, None, options).unwrap();
+            let key = unlock_file_with_master(&path, &master_key).unwrap();
+            let mut reader = CryReader::open(&path, &key).unwrap();
+            assert_eq!(reader.len(), data.len() as u64);
+
+            // 跨过块边界读取，包括倒退到前一块
+            for start in [BLOCK - 10, 0, 2 * BLOCK - 1, BLOCK, 5, 2 * BLOCK + 700] {
+                let mut buf = vec![0u8; 70];
+                reader.seek(SeekFrom::Start(start)).unwrap();
+                reader.read_exact(&mut buf).unwrap();
+                assert_eq!(buf, data[start as usize..start as usize + 70], "情况 {} 位置 {}", i, start);
+            }
+
+            // 一次读取跨越多个块
+            let mut buf = vec![0u8; BLOCK as usize + 20];
+            reader.seek(SeekFrom::Start(BLOCK / 2)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+            assert!(buf == data[BLOCK as usize / 2..BLOCK as usize / 2 + buf.len()], "情况 {}", i);
+
+            // 相对位置和末尾
+            assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), data.len() as u64 - 3);
+            let mut tail = Vec::new();
+            reader.read_to_end(&mut tail).unwrap();
+            assert_eq!(tail, data[data.len() - 3..]);
+            assert_eq!(reader.seek(SeekFrom::Current(-(BLOCK as i64))).unwrap(), data.len() as u64 - BLOCK);
+            assert!(reader.seek(SeekFrom::Current(-(data.len() as i64) - 1)).is_err());
+            reader.seek(SeekFrom::Start(data.len() as u64 + 10)).unwrap();
+            assert_eq!(reader.read(&mut [0u8; 8]).unwrap(), 0);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use crate::utils::cry_info::{parse_header, FileHeader, ENTRY_KDF_PASSWORD, ENTRY_KDF_VAULT};
+use crate::utils::encrypt::read_secret_metadata;
+use crate::utils::meta::FileMeta;
+use crate::utils::segment::{header_segment_path, remove_segments, rename_segments};
+use crate::utils::session;
+use crate::utils::vault::{session_manifest, update_records, ManifestRecord};
+use crate::utils::vault_key::find_vault_config;
+
+// 重复文件检测：加密时可以选择用保险库的去重密钥计算原文的 HMAC-SHA256，保存在加密元数据和保险库清单中，
+// 不需要解密文件内容就能找出原文相同的文件。去重密钥只保存在加密的清单中，没有密钥无法通过已知原文确认文件内容
+
+type HmacSha256 = Hmac<Sha256>;
+
+// 流式计算原文的内容哈希
+pub fn content_hash(path: &str, dedup_key: &[u8; 32]) -> Result<Vec<u8>> {
+    let mut reader = BufReader::new(File::open(path).context("打开文件失败")?);
+    let mut mac = HmacSha256::new_from_slice(dedup_key).expect("HMAC 初始化失败");
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        mac.update(&buf[..n]);
+    }
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+// 清单中保存的内容哈希
+pub fn encode_hash(hash: &[u8]) -> String {
+    general_purpose::STANDARD.encode(hash)
+}
+
+// 对重复文件的处理方式
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAction {
+    #[default]
+    List,   // 只列出
+    Link,   // 把重复的文件替换为保留文件的硬链接，使用保留文件的密码、缩略图和标签
+    Delete, // 删除重复的文件
+}
+
+// 一组原文相同的文件，保留最早加密的一个
+#[derive(Serialize, Debug)]
+struct DuplicateGroup {
+    keep: String,
+    duplicates: Vec<String>,
+    size: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct DuplicateReport {
+    groups: Vec<DuplicateGroup>,
+    processed: usize,
+    errors: Vec<String>,
+}
+
+// 按清单中的内容哈希分组，路径为完整路径
+fn group(root: &Path, files: Vec<ManifestRecord>) -> Vec<DuplicateGroup> {
+    let mut by_hash: BTreeMap<String, Vec<ManifestRecord>> = BTreeMap::new();
+    for record in files {
+        if let Some(hash) = record.content_hash.clone() {
+            by_hash.entry(hash).or_default().push(record);
+        }
+    }
+    by_hash.into_values()
+        .filter(|records| records.len() > 1)
+        .map(|mut records| {
+            records.sort_by(|a, b| (a.encrypted_at, &a.path).cmp(&(b.encrypted_at, &b.path)));
+            let full = |r: &ManifestRecord| root.join(&r.path).to_string_lossy().to_string();
+            DuplicateGroup {
+                keep: full(&records[0]),
+                duplicates: records[1..].iter().map(full).collect(),
+                size: records[0].size,
+            }
+        })
+        .collect()
+}
+
+// 修改文件前用加密元数据再确认一次，避免清单过期时误删
+fn verify(path: &str, expected: &str) -> Result<()> {
+    let key = session::file_key(path)?;
+    let meta = FileMeta::parse(&read_secret_metadata(path, &key)?)?;
+    if meta.content_hash.as_deref().map(encode_hash).as_deref() != Some(expected) {
+        bail!("内容哈希与清单不一致: {}", path);
+    }
+    Ok(())
+}
+
+// 链接后重复的文件使用保留文件的密码块，只有能确认两者可以用相同的密码打开时才允许：
+// 都只有保险库密码块且在同一个保险库中，或者都只有一个密码块（verify 已确认会话密码能打开两者）
+fn check_same_access(keep: &str, duplicate: &str) -> Result<()> {
+    let (keep_header, duplicate_header) = (parse_header(keep)?, parse_header(duplicate)?);
+    let vault_only = |h: &FileHeader| h.entries.iter().all(|e| e.kdf == ENTRY_KDF_VAULT);
+    let single_password = |h: &FileHeader| h.entries.len() == 1 && h.entries[0].kdf == ENTRY_KDF_PASSWORD;
+    if vault_only(&keep_header) && vault_only(&duplicate_header) && find_vault_config(Path::new(keep)) == find_vault_config(Path::new(duplicate)) {
+        return Ok(());
+    }
+    if single_password(&keep_header) && single_password(&duplicate_header) {
+        return Ok(());
+    }
+    bail!("两个文件的密码块不同，替换为硬链接后重复文件原来的密码可能无法打开它")
+}
+
+// 先在临时路径创建硬链接再替换，失败时重复的文件不受影响
+fn link(keep: &str, duplicate: &str) -> Result<()> {
+    check_same_access(keep, duplicate)?;
+    let keep_header = parse_header(keep).context("解析文件头失败")?;
+    let old_header = parse_header(duplicate).ok();
+    let tmp_path = format!("{}.tmp", duplicate);
+    fs::hard_link(keep, &tmp_path).context("创建硬链接失败，两个文件需要在同一个磁盘上")?;
+    for i in 1..=keep_header.segment_count {
+        if let Err(e) = fs::hard_link(header_segment_path(keep, &keep_header, i), header_segment_path(&tmp_path, &keep_header, i)) {
+            let _ = remove_segments(&tmp_path, &keep_header);
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e).context("创建硬链接失败");
+        }
+    }
+    if let Some(old_header) = &old_header {
+        remove_segments(duplicate, old_header)?;
+    }
+    rename_segments(&tmp_path, duplicate, &keep_header)?;
+    fs::rename(&tmp_path, duplicate).context("替换文件失败")?;
+    session::forget_key(duplicate);
+    Ok(())
+}
+
+fn delete(duplicate: &str) -> Result<()> {
+    let header = parse_header(duplicate).ok();
+    fs::remove_file(duplicate).context("删除文件失败")?;
+    if let Some(header) = &header {
+        remove_segments(duplicate, header)?;
+    }
+    session::forget_key(duplicate);
+    Ok(())
+}
+
+fn find(path: &Path, action: DuplicateAction) -> Result<DuplicateReport> {
+    let (root, manifest) = session_manifest(path)?;
+    let hashes: BTreeMap<String, String> = manifest.files.values()
+        .filter_map(|r| r.content_hash.clone().map(|h| (root.join(&r.path).to_string_lossy().to_string(), h)))
+        .collect();
+    let groups = group(&root, manifest.files.into_values().collect());
+    let mut report = DuplicateReport { groups: Vec::new(), processed: 0, errors: Vec::new() };
+    if action == DuplicateAction::List {
+        report.groups = groups;
+        return Ok(report);
+    }
+
+    let mut deleted = Vec::new();
+    for group in &groups {
+        if let Err(e) = verify(&group.keep, &hashes[&group.keep]) {
+            report.errors.push(format!("{:#}", e));
+            continue;
+        }
+        for duplicate in &group.duplicates {
+            let result = verify(duplicate, &hashes[duplicate]).and_then(|_| match action {
+                DuplicateAction::Link => link(&group.keep, duplicate),
+                _ => delete(duplicate),
+            });
+            match result {
+                Ok(()) => {
+                    report.processed += 1;
+                    if action == DuplicateAction::Delete {
+                        deleted.push(duplicate.clone());
+                    }
+                }
+                Err(e) => report.errors.push(format!("{}: {:#}", duplicate, e)),
+            }
+        }
+    }
+    report.groups = groups;
+
+    // 删除的文件从清单中移除
+    if !deleted.is_empty() {
+        update_records(&root, |manifest| {
+            manifest.files.retain(|path, _| !deleted.contains(&root.join(path).to_string_lossy().to_string()));
+        })?;
+    }
+    Ok(report)
+}
+
+// 在已解锁的保险库中查找原文相同的文件，可以同时替换为硬链接或删除，需要先调用 unlock_session
+#[tauri::command]
+pub fn find_duplicates(path: &str, action: Option<DuplicateAction>) -> String {
+    match find(Path::new(path), action.unwrap_or_default()) {
+        Ok(report) => serde_json::to_string(&report).unwrap(),
+        Err(e) => format!("查找失败: {}", e),
+    }
+}
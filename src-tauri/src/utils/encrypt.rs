@@ -1,36 +1,121 @@
 use anyhow::{bail, Context, Result};
 use argon2::{Argon2, Params, PasswordHasher};
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use chacha20::{ChaCha20, Key, Nonce};
+use hkdf::Hkdf;
 use hmac::{Hmac, Mac};
 use rand::RngCore;
+use serde::Serialize;
 use sha2::Sha256;
-use std::fs::{rename, File, OpenOptions};
+use std::fs::{remove_file, rename, File};
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 use cipher::{KeyIvInit, StreamCipher};
 use subtle::ConstantTimeEq;
+use crate::utils::age_file::{self, is_age_file, FileFormat};
+use crate::utils::compress::{compress_stream, decompress_stream, Compression, COMPRESSION_NONE, COMPRESSION_ZSTD};
+use crate::utils::cry_info::{parse_header, Entry, EntryLabel, FileHeader, ENTRY_KDF_PASSWORD, ENTRY_KDF_VAULT, HEADER_MAGIC, HEADER_PADDING, HEADER_VERSION};
+use crate::utils::padding::{content_len, hash_padding, write_padding, SizePadding, SIZE_PADDING_NONE};
+use crate::utils::segment::{remove_segments, ContentReader, ContentWriter, MAX_SEGMENT_SIZE, MIN_SEGMENT_SIZE};
 
 type HmacSha256 = Hmac<Sha256>;
 
-const HEADER_MAGIC: &[u8; 4] = b"WCRY";
 const VERIFY_BLOCK_SIZE: usize = 128;
 const ENTRY_SALT_LEN: usize = 16;
 const ENTRY_KEK_NONCE_LEN: usize = 12;
 const CEK_LEN: usize = 32;
 const CEK_NONCE_LEN: usize = 12;
-const ENTRY_ENCRYPTED_CEK_LEN: usize = CEK_LEN;
-const ENTRY_ENCRYPTED_CEK_NONCE_LEN: usize = CEK_NONCE_LEN;
-const ENTRY_SIZE: usize =
-    ENTRY_SALT_LEN + ENTRY_KEK_NONCE_LEN + ENTRY_ENCRYPTED_CEK_LEN + ENTRY_ENCRYPTED_CEK_NONCE_LEN; // 72
+// Argon2id 参数，写入导出包的格式说明，修改后旧文件将无法解密
+pub const KDF_MEMORY_KIB: u32 = 32 * 1024;
+pub const KDF_ITERATIONS: u32 = 2;
+pub const KDF_PARALLELISM: u32 = 4;
+// 由保险库主密钥派生 KEK 时 HKDF 的 info
+pub const VAULT_KDF_INFO: &[u8] = b"little-secret vault entry";
+// 由保险库配置文件的 CEK 派生主密钥时 HKDF 的 info
+pub const VAULT_MASTER_INFO: &[u8] = b"little-secret vault master";
+
+// 保险库主密钥，见 vault_key.rs
+#[derive(Clone)]
+pub struct MasterKey(pub(crate) [u8; CEK_LEN]);
+
+impl MasterKey {
+    // 主密钥由配置文件的 CEK 派生，不直接使用 CEK，两者用途分开
+    pub(crate) fn from_config_key(key: &FileKey) -> MasterKey {
+        let mut out = [0u8; CEK_LEN];
+        Hkdf::<Sha256>::new(None, &key.cek).expand(VAULT_MASTER_INFO, &mut out).expect("HKDF 输出长度不正确");
+        MasterKey(out)
+    }
+}
+
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MasterKey(..)")
+    }
+}
+
+// 新建密码块时的参数
+#[derive(Debug, Clone)]
+pub struct EntrySpec {
+    pub password: String,
+    pub label: Option<String>,  // 标签，例如使用者的名字
+    pub encrypt_label: bool,    // 是否使用 CEK 加密标签，加密后只有解锁文件才能看到
+    pub master_key: Option<MasterKey>,  // 使用保险库主密钥代替密码，不需要运行 Argon2
+}
+
+// 加密内容时的选项
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EncryptOptions {
+    pub compression: Compression,
+    pub segment_size: u32,      // 分段存储时每段密文的长度，0 表示不分段
+    pub padding: SizePadding,   // 是否填充密文长度
+    pub format: FileFormat,     // 输出格式，age 格式不支持分段和长度填充
+}
+
+impl EntrySpec {
+    // 由纯密码列表生成，不带标签
+    pub fn from_passwords(passwords: &[String]) -> Vec<EntrySpec> {
+        passwords.iter().map(|pw| EntrySpec { password: pw.clone(), label: None, encrypt_label: false, master_key: None }).collect()
+    }
+
+    // 由密码列表生成，标签与密码按顺序一一对应，空标签表示没有标签
+    pub fn from_labeled(passwords: &[&str], labels: Option<&[&str]>, encrypt_label: bool) -> Vec<EntrySpec> {
+        passwords.iter().enumerate().map(|(i, pw)| EntrySpec {
+            password: pw.to_string(),
+            label: labels.and_then(|l| l.get(i)).filter(|l| !l.is_empty()).map(|l| l.to_string()),
+            encrypt_label,
+            master_key: None,
+        }).collect()
+    }
+
+    // 由保险库主密钥生成
+    pub fn from_master_key(master_key: &MasterKey) -> EntrySpec {
+        EntrySpec { password: String::new(), label: None, encrypt_label: false, master_key: Some(master_key.clone()) }
+    }
+}
+
+// 密码块的可读信息，供 list_entries 返回
+#[derive(Serialize, Debug)]
+pub struct EntryInfo {
+    pub index: usize,
+    pub label: Option<String>,     // 加密标签在未提供正确密码时为 None
+    pub label_encrypted: bool,
+    pub created_at: Option<u64>,   // v1 格式没有创建时间
+    pub vault: bool,               // 是否由保险库主密钥解锁
+}
 
 fn derive_kek(password: &[u8], salt: &[u8]) -> Result<[u8; CEK_LEN]> {
-    let params = Params::new(32 * 1024, 2, 4, Some(CEK_LEN as u32 as usize)).unwrap();
+    let params = Params::new(KDF_MEMORY_KIB, KDF_ITERATIONS, KDF_PARALLELISM, Some(CEK_LEN)).unwrap();
     let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
     let mut out = [0u8; CEK_LEN];
     argon2.hash_password_into(password, salt, &mut out).unwrap();
     Ok(out)
 }
 
+fn derive_vault_kek(master_key: &MasterKey, salt: &[u8]) -> [u8; CEK_LEN] {
+    let mut out = [0u8; CEK_LEN];
+    Hkdf::<Sha256>::new(Some(salt), &master_key.0).expand(VAULT_KDF_INFO, &mut out).expect("HKDF 输出长度不正确");
+    out
+}
+
 fn gen_nonce() -> [u8; CEK_NONCE_LEN] {
     let mut n = [0u8; CEK_NONCE_LEN];
     rand::thread_rng().fill_bytes(&mut n);
@@ -52,15 +137,225 @@ fn chacha_xor(key: &[u8], nonce: &[u8], content: &[u8]) -> Result<Vec<u8>> {
     Ok(out)
 }
 
+fn now_unix() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// 使用密码（或保险库主密钥）派生 KEK，加密 CEK 生成一个密码块
+fn build_entry(spec: &EntrySpec, cek: &[u8; CEK_LEN], cek_nonce: &[u8; CEK_NONCE_LEN]) -> Result<Entry> {
+    let mut salt = [0u8; ENTRY_SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let kek_nonce = gen_nonce();
+    let (kek, kdf) = match &spec.master_key {
+        Some(master_key) => (derive_vault_kek(master_key, &salt), ENTRY_KDF_VAULT),
+        None => (derive_kek(spec.password.as_bytes(), &salt)?, ENTRY_KDF_PASSWORD),
+    };
+    let mut encrypted_cek = [0u8; CEK_LEN];
+    let mut encrypted_cek_nonce = [0u8; CEK_NONCE_LEN];
+    encrypted_cek.copy_from_slice(&chacha_xor(&kek, &kek_nonce, cek)?);
+    encrypted_cek_nonce.copy_from_slice(&chacha_xor(&kek, &kek_nonce, cek_nonce)?);
+    let label = match &spec.label {
+        None => EntryLabel::None,
+        Some(label) if spec.encrypt_label => {
+            let nonce = gen_nonce();
+            EntryLabel::Encrypted { nonce, data: chacha_xor(cek, &nonce, label.as_bytes())? }
+        }
+        Some(label) => EntryLabel::Public(label.clone()),
+    };
+    Ok(Entry {
+        salt,
+        kek_nonce,
+        encrypted_cek,
+        encrypted_cek_nonce,
+        created_at: now_unix(),
+        label,
+        kdf,
+    })
+}
+
+// 读取密码块的标签，加密标签需要 CEK
+fn read_label(label: &EntryLabel, cek: Option<&[u8; CEK_LEN]>) -> Option<String> {
+    match label {
+        EntryLabel::None => None,
+        EntryLabel::Public(label) => Some(label.clone()),
+        EntryLabel::Encrypted { nonce, data } => {
+            let plain = chacha_xor(cek?, nonce, data).ok()?;
+            Some(String::from_utf8_lossy(&plain).to_string())
+        }
+    }
+}
+
+// 用验证块判断 CEK 是否正确
+pub(crate) fn verify_cek(header: &FileHeader, cek: &[u8], cek_nonce: &[u8]) -> bool {
+    let verify_block_candidate = match chacha_xor(cek, cek_nonce, &header.encrypted_verify_block) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    let mut h = HmacSha256::new_from_slice(cek).expect("HMAC 初始化失败");
+    h.update(&verify_block_candidate);
+    let sum = h.finalize().into_bytes();
+    sum.ct_eq(&header.verify_block_hmac).unwrap_u8() == 1
+}
+
+// 使用密码逐个尝试密码块，成功则返回 (CEK, CEK Nonce)；跳过保险库密码块
+fn unlock(header: &FileHeader, password: &str) -> Option<([u8; CEK_LEN], [u8; CEK_NONCE_LEN])> {
+    let pw_bytes = password.as_bytes();
+    header.entries.iter()
+        .filter(|entry| entry.kdf == ENTRY_KDF_PASSWORD)
+        .find_map(|entry| derive_kek(pw_bytes, &entry.salt).ok().and_then(|kek| unwrap_cek(header, entry, &kek)))
+}
+
+// 使用保险库主密钥尝试保险库密码块
+fn unlock_with_master(header: &FileHeader, master_key: &MasterKey) -> Option<([u8; CEK_LEN], [u8; CEK_NONCE_LEN])> {
+    header.entries.iter()
+        .filter(|entry| entry.kdf == ENTRY_KDF_VAULT)
+        .find_map(|entry| unwrap_cek(header, entry, &derive_vault_kek(master_key, &entry.salt)))
+}
+
+// 用 KEK 解密密码块中的 CEK，并用验证块确认
+fn unwrap_cek(header: &FileHeader, entry: &Entry, kek: &[u8; CEK_LEN]) -> Option<([u8; CEK_LEN], [u8; CEK_NONCE_LEN])> {
+    let cek_candidate = chacha_xor(kek, &entry.kek_nonce, &entry.encrypted_cek).ok()?;
+    let cek_nonce_candidate = chacha_xor(kek, &entry.kek_nonce, &entry.encrypted_cek_nonce).ok()?;
+    if !verify_cek(header, &cek_candidate, &cek_nonce_candidate) {
+        return None;
+    }
+    let mut cek = [0u8; CEK_LEN];
+    let mut cek_nonce = [0u8; CEK_NONCE_LEN];
+    cek.copy_from_slice(&cek_candidate[..CEK_LEN]);
+    cek_nonce.copy_from_slice(&cek_nonce_candidate[..CEK_NONCE_LEN]);
+    Some((cek, cek_nonce))
+}
+
+// 使用 CEK 加密一段数据，格式为 Nonce(12) + 密文 + HMAC(32)
+fn seal(cek: &[u8; CEK_LEN], data: &[u8]) -> Result<Vec<u8>> {
+    let nonce = gen_nonce();
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&chacha_xor(cek, &nonce, data)?);
+    let mut h = HmacSha256::new_from_slice(cek).expect("HMAC 初始化失败");
+    h.update(&sealed);
+    sealed.extend_from_slice(&h.finalize().into_bytes());
+    Ok(sealed)
+}
+
+// 解密 seal 生成的数据，HMAC 不匹配时返回错误
+fn open_sealed(cek: &[u8; CEK_LEN], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < CEK_NONCE_LEN + 32 {
+        bail!("加密元数据已损坏");
+    }
+    let (body, mac) = sealed.split_at(sealed.len() - 32);
+    let mut h = HmacSha256::new_from_slice(cek).expect("HMAC 初始化失败");
+    h.update(body);
+    if h.finalize().into_bytes().ct_eq(mac).unwrap_u8() != 1 {
+        bail!("加密元数据校验失败");
+    }
+    chacha_xor(cek, &body[..CEK_NONCE_LEN], &body[CEK_NONCE_LEN..])
+}
+
+// 解锁后的文件密钥，可以缓存起来避免重复运行 Argon2
+#[derive(Clone)]
+pub struct FileKey {
+    pub(crate) cek: [u8; CEK_LEN],
+    pub(crate) cek_nonce: [u8; CEK_NONCE_LEN],
+}
+
+// 使用密码解锁文件，返回文件密钥
+pub fn unlock_file(encrypted_path: &str, password: &str) -> Result<FileKey> {
+    let header = parse_header(encrypted_path).context("解析文件头失败")?;
+    match unlock(&header, password) {
+        Some((cek, cek_nonce)) => Ok(FileKey { cek, cek_nonce }),
+        None => bail!("提供的密码不匹配任何加密密钥"),
+    }
+}
+
+// 使用保险库主密钥解锁文件，不需要运行 Argon2
+pub fn unlock_file_with_master(encrypted_path: &str, master_key: &MasterKey) -> Result<FileKey> {
+    let header = parse_header(encrypted_path).context("解析文件头失败")?;
+    match unlock_with_master(&header, master_key) {
+        Some((cek, cek_nonce)) => Ok(FileKey { cek, cek_nonce }),
+        None => bail!("保险库主密钥不匹配任何加密密钥"),
+    }
+}
+
+// 按密码块参数解锁文件：有主密钥时使用主密钥，否则使用密码
+pub fn unlock_file_with(encrypted_path: &str, spec: &EntrySpec) -> Result<FileKey> {
+    match &spec.master_key {
+        Some(master_key) => unlock_file_with_master(encrypted_path, master_key),
+        None => unlock_file(encrypted_path, &spec.password),
+    }
+}
+
+// 读取加密元数据，文件没有加密元数据时返回空
+pub fn read_secret_metadata(encrypted_path: &str, key: &FileKey) -> Result<Vec<u8>> {
+    let header = parse_header(encrypted_path).context("解析文件头失败")?;
+    if !verify_cek(&header, &key.cek, &key.cek_nonce) {
+        bail!("文件密钥不正确");
+    }
+    if header.secret_metadata.is_empty() {
+        return Ok(Vec::new());
+    }
+    open_sealed(&key.cek, &header.secret_metadata)
+}
+
+// 替换加密元数据
+pub fn update_secret_metadata(encrypted_path: &str, key: &FileKey, data: &[u8]) -> Result<()> {
+    let mut header = parse_header(encrypted_path).context("解析文件头失败")?;
+    if !verify_cek(&header, &key.cek, &key.cek_nonce) {
+        bail!("文件密钥不正确");
+    }
+    header.secret_metadata = if data.is_empty() { Vec::new() } else { seal(&key.cek, data)? };
+    header.write_to_file(encrypted_path)
+}
+
 pub fn encrypt_file(input_path: &str, output_path: &str, passwords: &[String], metadata: Option<&[u8]>) -> Result<()> {
-    if passwords.is_empty() {
+    encrypt_file_with_entries(input_path, output_path, &EntrySpec::from_passwords(passwords), metadata, None, EncryptOptions::default())
+}
+
+// 检查密码块和选项能否用于输出格式，批量加密前调用，避免每个文件都失败
+pub fn check_options(specs: &[EntrySpec], options: &EncryptOptions) -> Result<()> {
+    if options.format == FileFormat::Age {
+        if options.segment_size != 0 || options.padding != SizePadding::None {
+            bail!("age 格式不支持分段存储和长度填充");
+        }
+        age_file::check_specs(specs)?;
+    }
+    Ok(())
+}
+
+// secret_metadata 使用 CEK 加密后存入文件头，只有解锁后才能读取
+// 输出 age 格式时公开元数据写入元数据文件，没有 secret_metadata
+pub fn encrypt_file_with_entries(input_path: &str, output_path: &str, specs: &[EntrySpec], metadata: Option<&[u8]>, secret_metadata: Option<&[u8]>, options: EncryptOptions) -> Result<()> {
+    check_options(specs, &options)?;
+    let input_file = File::open(input_path).context("打开输入文件失败")?;
+    if options.format == FileFormat::Age {
+        return age_file::encrypt(input_file, output_path, specs, metadata);
+    }
+    let compression = options.compression.resolve(input_path);
+    encrypt_with(input_file, output_path, specs, metadata, secret_metadata, compression, &options)
+}
+
+// 加密任意数据流，例如容器文件或导入的压缩包；无法判断数据格式，auto 按 zstd 处理
+pub fn encrypt_stream<R: Read>(input: R, output_path: &str, specs: &[EntrySpec], metadata: Option<&[u8]>, secret_metadata: Option<&[u8]>, options: EncryptOptions) -> Result<()> {
+    let compression = if options.compression == Compression::None { COMPRESSION_NONE } else { COMPRESSION_ZSTD };
+    encrypt_with(input, output_path, specs, metadata, secret_metadata, compression, &options)
+}
+
+// File HMAC 在流式加密时计算（压缩时为压缩后的数据和块索引，填充时还包括填充和内容长度），
+// 与分段数量一起在加密完成后回填，头部长度不变
+fn encrypt_with<R: Read>(input: R, output_path: &str, specs: &[EntrySpec], metadata: Option<&[u8]>, secret_metadata: Option<&[u8]>, compression: u8, options: &EncryptOptions) -> Result<()> {
+    if specs.is_empty() {
         bail!("至少需要一个密码");
     }
-    if passwords.len() > 65535 {
+    if specs.len() > 65535 {
         bail!("密码数量过多，最多支持 65535 个");
     }
+    if options.segment_size != 0 && !(MIN_SEGMENT_SIZE..=MAX_SEGMENT_SIZE).contains(&options.segment_size) {
+        bail!("分段大小必须在 {} MB 到 {} MB 之间", MIN_SEGMENT_SIZE >> 20, MAX_SEGMENT_SIZE >> 20);
+    }
 
-    let mut input_file = File::open(input_path).context("打开输入文件失败")?;
+    // 覆盖已有的分段文件时先删除旧分段，避免残留
+    if let Ok(old) = parse_header(output_path) {
+        remove_segments(output_path, &old)?;
+    }
     let mut output_file = File::create(output_path).context("创建输出文件失败")?;
 
     let (cek, cek_nonce) = gen_cek();
@@ -70,279 +365,361 @@ pub fn encrypt_file(input_path: &str, output_path: &str, passwords: &[String], m
     let mut hmac_verify = HmacSha256::new_from_slice(&cek).expect("HMAC 初始化失败");
     hmac_verify.update(&verify_block);
     let verify_block_hmac = hmac_verify.finalize().into_bytes();
-
     let mut file_hmac_hasher = HmacSha256::new_from_slice(&cek).expect("HMAC 初始化失败");
-    {
-        let mut rdr = BufReader::new(&mut input_file);
+
+    let mut entries = Vec::with_capacity(specs.len());
+    for spec in specs {
+        entries.push(build_entry(spec, &cek, &cek_nonce)?);
+    }
+    let metadata = metadata.map_or(Vec::new(), |m| m.to_vec());
+    let secret_metadata = match secret_metadata {
+        Some(data) if !data.is_empty() => seal(&cek, data)?,
+        _ => Vec::new(),
+    };
+    let mut header = FileHeader {
+        magic: *HEADER_MAGIC,
+        version: HEADER_VERSION,
+        password_count: entries.len() as u16,
+        entries,
+        encrypted_verify_block,
+        verify_block_hmac: verify_block_hmac.into(),
+        file_hmac: [0u8; 32],
+        metadata_len: metadata.len() as u32,
+        metadata,
+        secret_metadata,
+        compression,
+        segment_size: options.segment_size,
+        segment_count: 0,
+        size_padding: options.padding.to_u8(),
+        segment_base: 0,
+        padding_len: HEADER_PADDING,
+        header_len: 0,
+    };
+
+    output_file.write_all(&header.to_bytes()?).context("写入头部失败")?;
+    let mut cipher = ChaCha20::new(&cek.into(), &cek_nonce.into());
+    let mut reader = BufReader::new(input);
+    let mut writer = ContentWriter::new(output_file, output_path, options.segment_size, 0, &cek);
+    let written = if compression == COMPRESSION_ZSTD {
+        compress_stream(&mut reader, &mut writer, &mut cipher, &mut file_hmac_hasher)?
+    } else {
+        let mut written = 0;
         let mut buf = [0u8; 8192];
         loop {
-            let n = rdr.read(&mut buf)?;
+            let n = reader.read(&mut buf)?;
             if n == 0 {
                 break;
             }
-            file_hmac_hasher.update(&buf[..n]);
+            let chunk = &mut buf[..n];
+            file_hmac_hasher.update(chunk);
+            cipher.apply_keystream(chunk);
+            writer.write_all(chunk)?;
+            written += n as u64;
         }
+        written
+    };
+    if options.padding != SizePadding::None {
+        write_padding(&mut writer, written, &mut cipher, &mut file_hmac_hasher)?;
     }
-    let file_hmac = file_hmac_hasher.finalize().into_bytes();
-    input_file.seek(SeekFrom::Start(0)).context("重置输入文件指针失败")?;
+    header.file_hmac = file_hmac_hasher.finalize().into_bytes().into();
+    let (mut output_file, segment_count) = writer.finish()?;
+    header.segment_count = segment_count;
+    output_file.seek(SeekFrom::Start(0))?;
+    output_file.write_all(&header.to_bytes()?).context("回填头部失败")?;
+    Ok(())
+}
 
-    let metadata_len = metadata.map_or(0, |m| m.len());
-    let mut header = Vec::with_capacity(6 + passwords.len() * ENTRY_SIZE + VERIFY_BLOCK_SIZE + 32 + 32 + 4 + metadata_len);
-    header.extend_from_slice(HEADER_MAGIC);
-    header.write_u16::<BigEndian>(passwords.len() as u16)?;
+// 也可以解密 age 文件：password 为口令或 X25519 私钥；age 的内容经过认证，损坏时直接返回错误
+pub fn decrypt_file(input_path: &str, output_path: &str, password: &str) -> Result<(bool, Vec<u8>)> {
+    if is_age_file(input_path) {
+        return Ok((true, age_file::decrypt(input_path, output_path, password)?));
+    }
+    let header = parse_header(input_path).context("解析文件头失败")?;
+    let (cek, cek_nonce) = match unlock(&header, password) {
+        Some(keys) => keys,
+        None => bail!("提供的密码不匹配任何加密密钥"),
+    };
+    decrypt_file_with_key(input_path, output_path, &FileKey { cek, cek_nonce })
+}
 
-    for pw in passwords {
-        let mut salt = [0u8; ENTRY_SALT_LEN];
-        rand::thread_rng().fill_bytes(&mut salt);
-        let kek_nonce = gen_nonce();
-        let kek = derive_kek(pw.as_bytes(), &salt)?;
-        let encrypted_cek = chacha_xor(&kek, &kek_nonce, &cek)?;
-        let encrypted_cek_nonce = chacha_xor(&kek, &kek_nonce, &cek_nonce)?;
-        header.extend_from_slice(&salt);
-        header.extend_from_slice(&kek_nonce);
-        header.extend_from_slice(&encrypted_cek);
-        header.extend_from_slice(&encrypted_cek_nonce);
+// 使用已解锁的文件密钥解密
+pub fn decrypt_file_with_key(input_path: &str, output_path: &str, key: &FileKey) -> Result<(bool, Vec<u8>)> {
+    // 密钥不正确时不创建输出文件
+    let header = parse_header(input_path).context("解析文件头失败")?;
+    if !verify_cek(&header, &key.cek, &key.cek_nonce) {
+        bail!("文件密钥不正确");
     }
+    let mut writer = BufWriter::new(File::create(output_path).context("创建输出文件失败")?);
+    let result = decrypt_to_writer(input_path, &mut writer, key)?;
+    writer.flush()?;
+    Ok(result)
+}
 
-    header.extend_from_slice(&encrypted_verify_block);
-    header.extend_from_slice(&verify_block_hmac);
-    header.extend_from_slice(&file_hmac);
-    header.write_u32::<BigEndian>(metadata_len as u32)?;
-    if let Some(metadata) = metadata {
-        header.extend_from_slice(metadata);
+// 解密到任意输出，例如打包到容器或压缩包中；返回 (File HMAC 是否匹配, 公开元数据)
+pub fn decrypt_to_writer<W: Write>(input_path: &str, writer: &mut W, key: &FileKey) -> Result<(bool, Vec<u8>)> {
+    let mut input_file = BufReader::new(File::open(input_path).context("打开输入文件失败")?);
+    let header = FileHeader::read_from(&mut input_file)?;
+    if !verify_cek(&header, &key.cek, &key.cek_nonce) {
+        bail!("文件密钥不正确");
     }
+    let mut input_file = ContentReader::new(input_file, input_path, &header, &key.cek)?;
+    // 有长度填充时只输出填充前的内容，填充部分只参与校验
+    let len = content_len(&mut input_file, header.size_padding, &key.cek, &key.cek_nonce)?;
 
-    output_file.write_all(&header).context("写入头部失败")?;
-    let mut cipher = ChaCha20::new(&cek.into(), &cek_nonce.into());
-    let mut reader = BufReader::new(input_file);
-    let mut writer = BufWriter::new(output_file);
-    let mut buf = [0u8; 8192];
-    loop {
-        let n = reader.read(&mut buf)?;
-        if n == 0 {
-            break;
+    let mut hmac_hasher = HmacSha256::new_from_slice(&key.cek).expect("HMAC 初始化失败");
+    if header.compression == COMPRESSION_ZSTD {
+        decompress_stream(&mut input_file, writer, len, &key.cek, &key.cek_nonce, &mut hmac_hasher)?;
+    } else if header.compression == COMPRESSION_NONE {
+        let mut cipher = ChaCha20::new(&key.cek.into(), &key.cek_nonce.into());
+        let mut content = (&mut input_file).take(len);
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = content.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            let chunk = &mut buf[..n];
+            cipher.apply_keystream(chunk);
+            writer.write_all(chunk)?;
+            hmac_hasher.update(chunk);
         }
-        let mut chunk = buf[..n].to_vec();
-        cipher.apply_keystream(&mut chunk);
-        writer.write_all(&chunk)?;
+    } else {
+        bail!("不支持的压缩方式: {}", header.compression);
+    }
+    if header.size_padding != SIZE_PADDING_NONE {
+        hash_padding(&mut input_file, len, &key.cek, &key.cek_nonce, &mut hmac_hasher)?;
+    }
+    let computed_hmac = hmac_hasher.finalize().into_bytes();
+    let matched = computed_hmac.ct_eq(&header.file_hmac).unwrap_u8() == 1;
+    Ok((matched, header.metadata))
+}
+
+pub fn reset_passwords(encrypted_path: &str, old_password: &str, new_passwords: &[String]) -> Result<()> {
+    reset_entries(encrypted_path, old_password, &EntrySpec::from_passwords(new_passwords))
+}
+
+// 使用旧密码解锁后，用新的密码块替换全部旧密码块
+pub fn reset_entries(encrypted_path: &str, old_password: &str, specs: &[EntrySpec]) -> Result<()> {
+    if !try_reset_entries(encrypted_path, old_password, specs)? {
+        bail!("提供的旧密码不正确");
     }
-    writer.flush()?;
     Ok(())
 }
 
-pub fn decrypt_file(input_path: &str, output_path: &str, password: &str) -> Result<(bool, Vec<u8>)> {
-    let mut input_file = File::open(input_path).context("打开输入文件失败")?;
-    let mut fixed_header = [0u8; 6];
-    input_file.read_exact(&mut fixed_header).context("读取固定头部失败")?;
-    if &fixed_header[..4] != HEADER_MAGIC {
-        bail!("无效的文件头部，非 WCRY 格式");
-    }
-    let num_passwords = (&fixed_header[4..6]).read_u16::<BigEndian>()? as usize;
-    let mut entries = vec![0u8; num_passwords * ENTRY_SIZE];
-    input_file.read_exact(&mut entries).context("读取密码条目失败")?;
-    let mut vb_and_hmac = vec![0u8; VERIFY_BLOCK_SIZE + 32 + 32];
-    input_file.read_exact(&mut vb_and_hmac).context("读取验证块和 HMAC 失败")?;
-    let encrypted_verify_block = &vb_and_hmac[..VERIFY_BLOCK_SIZE];
-    let verify_block_hmac = &vb_and_hmac[VERIFY_BLOCK_SIZE..VERIFY_BLOCK_SIZE + 32];
-    let original_file_hmac = &vb_and_hmac[VERIFY_BLOCK_SIZE + 32..];
-
-    let mut metadata_len_buf = [0u8; 4];
-    let metadata_len = match input_file.read_exact(&mut metadata_len_buf) {
-        Ok(()) => u32::from_be_bytes(metadata_len_buf) as usize,
-        Err(_) => 0, // 向后兼容：旧格式没有元数据
-    };
-    let mut metadata = vec![0u8; metadata_len];
-    if metadata_len > 0 {
-        input_file.read_exact(&mut metadata).context("读取元数据失败")?;
+// 同 reset_entries，但旧密码不正确时返回 Ok(false)，便于批量处理时区分原因
+pub fn try_reset_entries(encrypted_path: &str, old_password: &str, specs: &[EntrySpec]) -> Result<bool> {
+    let header = parse_header(encrypted_path).context("解析文件头失败")?;
+    match unlock(&header, old_password) {
+        Some((cek, cek_nonce)) => reset_entries_with_key(encrypted_path, &FileKey { cek, cek_nonce }, specs).map(|_| true),
+        None => Ok(false),
     }
+}
 
-    let mut found = false;
-    let mut cek = [0u8; CEK_LEN];
-    let mut cek_nonce = [0u8; CEK_NONCE_LEN];
-    let pw_bytes = password.as_bytes();
+// 使用已解锁的文件密钥，用新的密码块替换全部旧密码块
+pub fn reset_entries_with_key(encrypted_path: &str, key: &FileKey, specs: &[EntrySpec]) -> Result<()> {
+    if specs.is_empty() {
+        bail!("至少需要一个新密码");
+    }
+    if specs.len() > 65535 {
+        bail!("新密码数量过多，最多支持 65535 个");
+    }
 
-    for i in 0..num_passwords {
-        let off = i * ENTRY_SIZE;
-        let salt = &entries[off..off + ENTRY_SALT_LEN];
-        let kek_nonce = &entries[off + ENTRY_SALT_LEN..off + ENTRY_SALT_LEN + ENTRY_KEK_NONCE_LEN];
-        let encrypted_cek = &entries[off + ENTRY_SALT_LEN + ENTRY_KEK_NONCE_LEN
-            ..off + ENTRY_SALT_LEN + ENTRY_KEK_NONCE_LEN + ENTRY_ENCRYPTED_CEK_LEN];
-        let encrypted_cek_nonce = &entries[off + ENTRY_SALT_LEN + ENTRY_KEK_NONCE_LEN + ENTRY_ENCRYPTED_CEK_LEN
-            ..off + ENTRY_SIZE];
-
-        let kek = match derive_kek(pw_bytes, salt) {
-            Ok(k) => k,
-            Err(_) => continue,
-        };
-        let cek_candidate = match chacha_xor(&kek, kek_nonce, encrypted_cek) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-        let cek_nonce_candidate = match chacha_xor(&kek, kek_nonce, encrypted_cek_nonce) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-        if cek_candidate.len() != CEK_LEN || cek_nonce_candidate.len() != CEK_NONCE_LEN {
-            continue;
-        }
-        let verify_block_candidate = match chacha_xor(&cek_candidate, &cek_nonce_candidate, encrypted_verify_block) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-        let mut h = HmacSha256::new_from_slice(&cek_candidate).expect("HMAC 初始化失败");
-        h.update(&verify_block_candidate);
-        let sum = h.finalize().into_bytes();
-        if sum.ct_eq(verify_block_hmac).unwrap_u8() == 1 {
-            cek.copy_from_slice(&cek_candidate[..CEK_LEN]);
-            cek_nonce.copy_from_slice(&cek_nonce_candidate[..CEK_NONCE_LEN]);
-            found = true;
-            break;
-        }
+    let mut header = parse_header(encrypted_path).context("解析文件头失败")?;
+    if !verify_cek(&header, &key.cek, &key.cek_nonce) {
+        bail!("文件密钥不正确");
     }
 
-    if !found {
-        bail!("提供的密码不匹配任何加密密钥");
+    let mut entries = Vec::with_capacity(specs.len());
+    for spec in specs {
+        entries.push(build_entry(spec, &key.cek, &key.cek_nonce)?);
     }
+    header.password_count = entries.len() as u16;
+    header.entries = entries;
+    header.write_to_file(encrypted_path)
+}
 
-    let mut output_file = File::create(output_path).context("创建输出文件失败")?;
-    let mut cipher = ChaCha20::new(&cek.into(), &cek_nonce.into());
-    let mut hmac_hasher = HmacSha256::new_from_slice(&cek).expect("HMAC 初始化失败");
-    let mut buf = [0u8; 8192];
-    loop {
-        let n = input_file.read(&mut buf)?;
-        if n == 0 {
-            break;
-        }
-        let mut chunk = buf[..n].to_vec();
-        cipher.apply_keystream(&mut chunk);
-        output_file.write_all(&chunk)?;
-        hmac_hasher.update(&chunk);
+// 列出全部密码块；提供文件密钥时同时解密加密的标签
+pub fn list_entries(encrypted_path: &str, key: Option<&FileKey>) -> Result<Vec<EntryInfo>> {
+    let header = parse_header(encrypted_path).context("解析文件头失败")?;
+    let cek = match key {
+        Some(key) if verify_cek(&header, &key.cek, &key.cek_nonce) => Some(key.cek),
+        Some(_) => bail!("文件密钥不正确"),
+        None => None,
+    };
+    Ok(header.entries.iter().enumerate().map(|(index, entry)| EntryInfo {
+        index,
+        label: read_label(&entry.label, cek.as_ref()),
+        label_encrypted: matches!(entry.label, EntryLabel::Encrypted { .. }),
+        created_at: if entry.created_at == 0 { None } else { Some(entry.created_at) },
+        vault: entry.kdf == ENTRY_KDF_VAULT,
+    }).collect())
+}
+
+// 删除标签匹配的密码块，返回删除的数量；不允许删除全部密码块
+pub fn remove_entries_by_label(encrypted_path: &str, key: &FileKey, label: &str) -> Result<usize> {
+    let mut header = parse_header(encrypted_path).context("解析文件头失败")?;
+    if !verify_cek(&header, &key.cek, &key.cek_nonce) {
+        bail!("文件密钥不正确");
     }
-    let computed_hmac = hmac_hasher.finalize().into_bytes();
-    let matched = computed_hmac.ct_eq(original_file_hmac).unwrap_u8() == 1;
-    Ok((matched, metadata))
+    let cek = key.cek;
+
+    let old_count = header.entries.len();
+    header.entries.retain(|entry| read_label(&entry.label, Some(&cek)).as_deref() != Some(label));
+    let removed = old_count - header.entries.len();
+    if removed == 0 {
+        bail!("没有标签为 {} 的密码", label);
+    }
+    if header.entries.is_empty() {
+        bail!("不能删除全部密码");
+    }
+    header.password_count = header.entries.len() as u16;
+    header.write_to_file(encrypted_path)?;
+    Ok(removed)
 }
 
-pub fn reset_passwords(encrypted_path: &str, old_password: &str, new_passwords: &[String]) -> Result<()> {
-    if new_passwords.is_empty() {
+// 轮换密钥：使用新的 CEK 和 Nonce 重新加密文件内容，旧 CEK 泄露后也无法再解密
+// 必须重新提供全部密码，因为其它密码块包裹的是旧 CEK
+pub fn rotate_key(encrypted_path: &str, key: &FileKey, specs: &[EntrySpec]) -> Result<()> {
+    let (tmp_path, old_header, header) = write_rotated(encrypted_path, key, specs)?;
+    replace_rotated(encrypted_path, &tmp_path, &old_header, &header)
+}
+
+// 把轮换后的文件头写入临时文件，分段直接写入新编号的分段文件，不覆盖原文件和旧分段
+// 返回临时文件的路径和轮换前后的文件头
+fn write_rotated(encrypted_path: &str, key: &FileKey, specs: &[EntrySpec]) -> Result<(String, FileHeader, FileHeader)> {
+    if specs.is_empty() {
         bail!("至少需要一个新密码");
     }
-    if new_passwords.len() > 65535 {
+    if specs.len() > 65535 {
         bail!("新密码数量过多，最多支持 65535 个");
     }
 
-    let mut file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(encrypted_path)
-        .context("打开加密文件失败")?;
-    let mut fixed_header = [0u8; 6];
-    file.read_exact(&mut fixed_header).context("读取固定头部失败")?;
-    if &fixed_header[..4] != HEADER_MAGIC {
-        bail!("无效的文件头部，非 WCRY 格式");
-    }
-    let old_num_passwords = (&fixed_header[4..6]).read_u16::<BigEndian>()? as usize;
-    let mut old_entries = vec![0u8; old_num_passwords * ENTRY_SIZE];
-    file.read_exact(&mut old_entries).context("读取旧密码条目失败")?;
-    let mut vb_and_hmac = vec![0u8; VERIFY_BLOCK_SIZE + 32 + 32];
-    file.read_exact(&mut vb_and_hmac).context("读取验证块和 HMAC 失败")?;
-    let encrypted_verify_block = &vb_and_hmac[..VERIFY_BLOCK_SIZE];
-    let verify_block_hmac = &vb_and_hmac[VERIFY_BLOCK_SIZE..VERIFY_BLOCK_SIZE + 32];
-    let original_file_hmac = &vb_and_hmac[VERIFY_BLOCK_SIZE + 32..];
-
-    let mut metadata_len_buf = [0u8; 4];
-    let metadata_len = match file.read_exact(&mut metadata_len_buf) {
-        Ok(()) => u32::from_be_bytes(metadata_len_buf) as usize,
-        Err(_) => 0, // 向后兼容：旧格式没有元数据
-    };
-    let mut metadata = vec![0u8; metadata_len];
-    if metadata_len > 0 {
-        file.read_exact(&mut metadata).context("读取元数据失败")?;
+    let mut reader = BufReader::new(File::open(encrypted_path).context("打开加密文件失败")?);
+    let mut header = FileHeader::read_from(&mut reader)?;
+    if !verify_cek(&header, &key.cek, &key.cek_nonce) {
+        bail!("文件密钥不正确");
     }
+    let (old_cek, old_cek_nonce) = (key.cek, key.cek_nonce);
+    let mut reader = ContentReader::new(reader, encrypted_path, &header, &old_cek)?;
+    let old_header = header.clone();
 
-    let mut found = false;
-    let mut cek = [0u8; CEK_LEN];
-    let mut cek_nonce = [0u8; CEK_NONCE_LEN];
-    let pw_bytes = old_password.as_bytes();
-
-    for i in 0..old_num_passwords {
-        let off = i * ENTRY_SIZE;
-        let salt = &old_entries[off..off + ENTRY_SALT_LEN];
-        let kek_nonce = &old_entries[off + ENTRY_SALT_LEN..off + ENTRY_SALT_LEN + ENTRY_KEK_NONCE_LEN];
-        let encrypted_cek = &old_entries[off + ENTRY_SALT_LEN + ENTRY_KEK_NONCE_LEN
-            ..off + ENTRY_SALT_LEN + ENTRY_KEK_NONCE_LEN + ENTRY_ENCRYPTED_CEK_LEN];
-        let encrypted_cek_nonce = &old_entries[off + ENTRY_SALT_LEN + ENTRY_KEK_NONCE_LEN + ENTRY_ENCRYPTED_CEK_LEN
-            ..off + ENTRY_SIZE];
-
-        let kek = match derive_kek(pw_bytes, salt) {
-            Ok(k) => k,
-            Err(_) => continue,
-        };
-        let cek_candidate = match chacha_xor(&kek, kek_nonce, encrypted_cek) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-        let cek_nonce_candidate = match chacha_xor(&kek, kek_nonce, encrypted_cek_nonce) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-        if cek_candidate.len() != CEK_LEN || cek_nonce_candidate.len() != CEK_NONCE_LEN {
-            continue;
-        }
-        let verify_block_candidate = match chacha_xor(&cek_candidate, &cek_nonce_candidate, encrypted_verify_block) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-        let mut h = HmacSha256::new_from_slice(&cek_candidate).expect("HMAC 初始化失败");
-        h.update(&verify_block_candidate);
-        let sum = h.finalize().into_bytes();
-        if sum.ct_eq(verify_block_hmac).unwrap_u8() == 1 {
-            cek.copy_from_slice(&cek_candidate[..CEK_LEN]);
-            cek_nonce.copy_from_slice(&cek_nonce_candidate[..CEK_NONCE_LEN]);
-            found = true;
-            break;
-        }
+    // 生成新的 CEK、验证块和密码块
+    let (cek, cek_nonce) = gen_cek();
+    let mut verify_block = vec![0u8; VERIFY_BLOCK_SIZE];
+    rand::thread_rng().fill_bytes(&mut verify_block);
+    let mut hmac_verify = HmacSha256::new_from_slice(&cek).expect("HMAC 初始化失败");
+    hmac_verify.update(&verify_block);
+    header.encrypted_verify_block = chacha_xor(&cek, &cek_nonce, &verify_block)?;
+    header.verify_block_hmac = hmac_verify.finalize().into_bytes().into();
+    let mut entries = Vec::with_capacity(specs.len());
+    for spec in specs {
+        entries.push(build_entry(spec, &cek, &cek_nonce)?);
     }
-
-    if !found {
-        bail!("提供的旧密码不正确");
+    header.password_count = entries.len() as u16;
+    header.entries = entries;
+    if !header.secret_metadata.is_empty() {
+        let secret_metadata = open_sealed(&old_cek, &header.secret_metadata)?;
+        header.secret_metadata = seal(&cek, &secret_metadata)?;
+    }
+    header.padding_len = HEADER_PADDING;
+    // 新分段的编号接在旧分段之后，两者同时存在，替换头部文件后才删除旧分段
+    if header.segment_count > 0 {
+        header.segment_base = header.segment_base.checked_add(header.segment_count).context("分段文件编号溢出")?;
     }
 
-    let mut new_header = Vec::with_capacity(6 + new_passwords.len() * ENTRY_SIZE + VERIFY_BLOCK_SIZE + 32 + 32 + 4 + metadata_len);
-    new_header.extend_from_slice(HEADER_MAGIC);
-    new_header.write_u16::<BigEndian>(new_passwords.len() as u16)?;
-    for npw in new_passwords {
-        let mut salt = [0u8; ENTRY_SALT_LEN];
-        rand::thread_rng().fill_bytes(&mut salt);
-        let new_kek_nonce = gen_nonce();
-        let new_kek = derive_kek(npw.as_bytes(), &salt)?;
-        let new_encrypted_cek = chacha_xor(&new_kek, &new_kek_nonce, &cek)?;
-        let new_encrypted_cek_nonce = chacha_xor(&new_kek, &new_kek_nonce, &cek_nonce)?;
-        new_header.extend_from_slice(&salt);
-        new_header.extend_from_slice(&new_kek_nonce);
-        new_header.extend_from_slice(&new_encrypted_cek);
-        new_header.extend_from_slice(&new_encrypted_cek_nonce);
-    }
-    new_header.extend_from_slice(encrypted_verify_block);
-    new_header.extend_from_slice(verify_block_hmac);
-    new_header.extend_from_slice(original_file_hmac);
-    new_header.write_u32::<BigEndian>(metadata_len as u32)?;
-    new_header.extend_from_slice(&metadata);
-
-    let old_header_len = (6 + old_num_passwords * ENTRY_SIZE + VERIFY_BLOCK_SIZE + 32 + 32 + 4 + metadata_len) as u64;
+    // 先写入头部占位（长度固定），File HMAC 在流式处理完后回填；分段文件保持原来的分段大小
     let tmp_path = format!("{}.tmp", encrypted_path);
     let mut tmp_file = File::create(&tmp_path).context("创建临时文件失败")?;
-    tmp_file.write_all(&new_header).context("写入新头部失败")?;
-    file.seek(SeekFrom::Start(old_header_len)).context("设置文件指针失败")?;
-    let mut buffer = [0u8; 8192];
+    tmp_file.write_all(&header.to_bytes()?).context("写入头部失败")?;
+    let mut writer = ContentWriter::new(tmp_file, encrypted_path, header.segment_size, header.segment_base, &cek);
+
+    let mut old_cipher = ChaCha20::new(&old_cek.into(), &old_cek_nonce.into());
+    let mut new_cipher = ChaCha20::new(&cek.into(), &cek_nonce.into());
+    let mut old_hmac_hasher = HmacSha256::new_from_slice(&old_cek).expect("HMAC 初始化失败");
+    let mut new_hmac_hasher = HmacSha256::new_from_slice(&cek).expect("HMAC 初始化失败");
+    let mut buf = [0u8; 8192];
     loop {
-        let n = file.read(&mut buffer)?;
+        let n = reader.read(&mut buf)?;
         if n == 0 {
             break;
         }
-        tmp_file.write_all(&buffer[..n])?;
+        let chunk = &mut buf[..n];
+        old_cipher.apply_keystream(chunk);
+        old_hmac_hasher.update(chunk);
+        new_hmac_hasher.update(chunk);
+        new_cipher.apply_keystream(chunk);
+        writer.write_all(chunk)?;
+    }
+
+    // 原文件已损坏时不做轮换，避免把损坏的内容重新签名
+    let (mut tmp_file, segment_count) = writer.finish()?;
+    header.segment_count = segment_count;
+    if old_hmac_hasher.finalize().into_bytes().ct_eq(&header.file_hmac).unwrap_u8() != 1 {
+        drop(tmp_file);
+        let _ = remove_segments(encrypted_path, &header);
+        let _ = remove_file(&tmp_path);
+        bail!("文件完整性校验失败，已取消轮换");
     }
-    tmp_file.flush()?;
-    rename(&tmp_path, encrypted_path).context("替换文件失败")?;
+
+    header.file_hmac = new_hmac_hasher.finalize().into_bytes().into();
+    tmp_file.seek(SeekFrom::Start(0))?;
+    tmp_file.write_all(&header.to_bytes()?).context("回填头部失败")?;
+    tmp_file.sync_all().context("写入临时文件失败")?;
+    drop(tmp_file);
+    Ok((tmp_path, old_header, header))
+}
+
+// 替换头部文件是唯一的提交点：替换之前失败时原文件和旧分段都不受影响，替换之后才删除旧分段
+fn replace_rotated(encrypted_path: &str, tmp_path: &str, old_header: &FileHeader, header: &FileHeader) -> Result<()> {
+    if let Err(e) = rename(tmp_path, encrypted_path) {
+        let _ = remove_segments(encrypted_path, header);
+        let _ = remove_file(tmp_path);
+        return Err(e).context("替换文件失败");
+    }
+    // 轮换已经完成，旧分段删除失败只会留下用不到的文件
+    let _ = remove_segments(encrypted_path, old_header);
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::path::Path;
+    use crate::utils::segment::{header_segment_path, MIN_SEGMENT_SIZE};
+
+    fn decrypts(path: &str, key: &FileKey, data: &[u8]) -> bool {
+        let output = format!("{}.out", path);
+        let result = matches!(decrypt_file_with_key(path, &output, key), Ok((true, _))) && std::fs::read(&output).unwrap() == data;
+        let _ = remove_file(&output);
+        result
+    }
+
+    #[test]
+    fn rotate_keeps_original_until_header_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.cry").to_string_lossy().to_string();
+        let data: Vec<u8> = (0..2 * MIN_SEGMENT_SIZE as usize + 100).map(|i| (i % 251) as u8).collect();
+        let (old_master, new_master) = (MasterKey([1; 32]), MasterKey([2; 32]));
+        let options = EncryptOptions { segment_size: MIN_SEGMENT_SIZE, ..Default::default() };
+        encrypt_stream(Cursor::new(&data), &path, &[EntrySpec::from_master_key(&old_master)], None, None, options).unwrap();
+        let old_key = unlock_file_with_master(&path, &old_master).unwrap();
+        let specs = [EntrySpec::from_master_key(&new_master)];
+
+        // 新分段写完后替换头部文件失败（删除临时文件来模拟），原文件仍然可以解密，新分段被清理
+        let (tmp_path, old_header, header) = write_rotated(&path, &old_key, &specs).unwrap();
+        assert_eq!(header.segment_base, old_header.segment_count);
+        assert!(decrypts(&path, &old_key, &data));
+        remove_file(&tmp_path).unwrap();
+        assert!(replace_rotated(&path, &tmp_path, &old_header, &header).is_err());
+        assert!(decrypts(&path, &old_key, &data));
+        assert!((1..=header.segment_count).all(|i| !Path::new(&header_segment_path(&path, &header, i)).exists()));
+
+        // 替换成功后旧分段才被删除
+        rotate_key(&path, &old_key, &specs).unwrap();
+        let new_key = unlock_file_with_master(&path, &new_master).unwrap();
+        assert!(decrypts(&path, &new_key, &data));
+        assert!(unlock_file_with_master(&path, &old_master).is_err());
+        assert!((1..=old_header.segment_count).all(|i| !Path::new(&header_segment_path(&path, &old_header, i)).exists()));
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sevenz_rust::lzma::LZMA2Options;
+use sevenz_rust::{AesEncoderOptions, Password, SevenZArchiveEntry, SevenZReader, SevenZWriter};
+use zip::write::SimpleFileOptions;
+use zip::{AesMode, CompressionMethod, ZipArchive, ZipWriter};
+use crate::utils::archive::{encrypt_entry, open_verified};
+use crate::utils::compress::Compression;
+use crate::utils::encrypt::{EncryptOptions, EntrySpec, FileKey};
+use crate::utils::policy::check_passwords;
+use crate::utils::vault_key::{vault_specs, KeyResolver};
+use crate::utils::vault::{make_stream_record, record_encrypted, ManifestRecord};
+
+// 与没有安装本软件的人交换文件：导出为加密的 7z / AES-256 ZIP，或直接把这类压缩包导入为 .cry 文件
+// 两个方向都是流式处理，原文不会写到磁盘上
+
+// 压缩包格式
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExchangeFormat {
+    #[serde(rename = "7z")]
+    SevenZ,     // 7z，AES-256 + LZMA2，同时加密文件名
+    Zip,        // ZIP，WinZip AES-256 + Deflate，文件名不加密
+}
+
+// 按文件头判断压缩包格式
+fn detect_format(path: &str) -> Result<ExchangeFormat> {
+    let mut head = [0u8; 6];
+    File::open(path).context("打开压缩包失败")?.read_exact(&mut head).context("不是 7z 或 ZIP 压缩包")?;
+    if head == *b"7z\xBC\xAF\x27\x1C" {
+        Ok(ExchangeFormat::SevenZ)
+    } else if head.starts_with(b"PK\x03\x04") {
+        Ok(ExchangeFormat::Zip)
+    } else {
+        bail!("不是 7z 或 ZIP 压缩包")
+    }
+}
+
+// 要导出的文件：压缩包中的文件名（去掉 .cry）和已解锁的密钥
+fn unlock_all(paths: &[&str], password: &str) -> Result<Vec<(String, String, FileKey)>> {
+    let mut names = HashSet::new();
+    let resolver = KeyResolver::new(&[password]);
+    paths.iter().map(|path| {
+        let key = resolver.unlock(path).with_context(|| format!("密码无法解锁: {}", path))?;
+        let name = Path::new(path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let name = name.strip_suffix(".cry").unwrap_or(&name).to_string();
+        if !names.insert(name.clone()) {
+            bail!("文件名重复: {}", name);
+        }
+        Ok((path.to_string(), name, key))
+    }).collect()
+}
+
+// 把 .cry 文件解密后写入加密的压缩包，返回文件数量
+pub fn export_files(paths: &[&str], password: &str, output: &str, archive_password: &str, format: ExchangeFormat) -> Result<usize> {
+    let files = unlock_all(paths, password)?;
+    match format {
+        ExchangeFormat::SevenZ => {
+            let mut writer = SevenZWriter::create(output).context("创建压缩包失败")?;
+            writer.set_content_methods(vec![
+                AesEncoderOptions::new(Password::from(archive_password)).into(),
+                LZMA2Options::with_preset(6).into(),
+            ]);
+            writer.set_encrypt_header(true);
+            for (path, name, key) in &files {
+                let reader = open_verified(Path::new(path), key.clone())?;
+                writer.push_archive_entry(SevenZArchiveEntry::from_path(path, name.clone()), Some(reader))
+                    .with_context(|| format!("写入压缩包失败: {}", name))?;
+            }
+            writer.finish().context("写入压缩包失败")?;
+        }
+        ExchangeFormat::Zip => {
+            let mut writer = ZipWriter::new(File::create(output).context("创建压缩包失败")?);
+            let options = SimpleFileOptions::default()
+                .compression_method(CompressionMethod::Deflated)
+                .with_aes_encryption(AesMode::Aes256, archive_password);
+            for (path, name, key) in &files {
+                let mut reader = open_verified(Path::new(path), key.clone())?;
+                writer.start_file(name.as_str(), options).context("写入压缩包失败")?;
+                io::copy(&mut reader, &mut writer).with_context(|| format!("写入压缩包失败: {}", name))?;
+            }
+            writer.finish().context("写入压缩包失败")?;
+        }
+    }
+    Ok(files.len())
+}
+
+// 把加密的压缩包直接导入为 output_dir 中的 .cry 文件，保留压缩包中的目录结构，返回清单记录
+pub fn import_archive(path: &str, archive_password: &str, output_dir: &Path, specs: &[EntrySpec], options: EncryptOptions) -> Result<Vec<ManifestRecord>> {
+    let mut records = Vec::new();
+    match detect_format(path)? {
+        ExchangeFormat::SevenZ => {
+            let mut reader = SevenZReader::open(path, Password::from(archive_password)).context("打开压缩包失败，请检查密码")?;
+            let mut failed = None;
+            reader.for_each_entries(|entry, data| {
+                if entry.is_directory() {
+                    return Ok(true);
+                }
+                match encrypt_entry(data, output_dir, entry.name(), specs, options) {
+                    Ok(output) => {
+                        records.push(make_stream_record(&output, entry.size(), 0));
+                        Ok(true)
+                    }
+                    Err(e) => {
+                        failed = Some(e.context(format!("导入失败: {}", entry.name())));
+                        Ok(false)
+                    }
+                }
+            }).context("读取压缩包失败，请检查密码")?;
+            if let Some(e) = failed {
+                return Err(e);
+            }
+        }
+        ExchangeFormat::Zip => {
+            let mut archive = ZipArchive::new(File::open(path).context("打开压缩包失败")?).context("读取压缩包失败")?;
+            for i in 0..archive.len() {
+                let mut file = archive.by_index_decrypt(i, archive_password.as_bytes()).context("读取压缩包失败，请检查密码")?;
+                if file.is_dir() {
+                    continue;
+                }
+                let name = file.name().to_string();
+                let size = file.size();
+                let output = encrypt_entry(&mut file, output_dir, &name, specs, options)
+                    .with_context(|| format!("导入失败: {}", name))?;
+                records.push(make_stream_record(&output, size, 0));
+            }
+        }
+    }
+    Ok(records)
+}
+
+// 把选中的 .cry 文件导出为加密的 7z 或 ZIP，对方用 7-Zip 等常见软件即可打开
+#[tauri::command]
+pub fn export_encrypted_archive(paths: Vec<&str>, password: &str, output: &str, archive_password: &str, format: ExchangeFormat) -> String {
+    if archive_password.is_empty() { return "导出失败: 压缩包密码为空！".to_string(); }
+    match export_files(&paths, password, output, archive_password, format) {
+        Ok(n) => format!("导出完毕：{} 个文件", n),
+        Err(e) => {
+            let _ = std::fs::remove_file(output);
+            format!("导出失败: {:#}", e)
+        }
+    }
+}
+
+// 把加密的 7z 或 ZIP 直接导入为 .cry 文件
+#[tauri::command]
+pub fn import_encrypted_archive(path: &str, archive_password: &str, output: &str, passwords: Vec<&str>, compression: Option<Compression>) -> String {
+    if let Err(e) = check_passwords(&passwords) { return format!("导入失败: {}", e); }
+    let specs = EntrySpec::from_passwords(&passwords.iter().map(|p| p.to_string()).collect::<Vec<_>>());
+    let specs = match vault_specs(Path::new(output), &passwords, specs) {
+        Ok(specs) => specs,
+        Err(e) => return format!("导入失败: {}", e),
+    };
+    let options = EncryptOptions { compression: compression.unwrap_or_default(), ..Default::default() };
+    match import_archive(path, archive_password, Path::new(output), &specs, options) {
+        Ok(records) => {
+            let count = records.len();
+            match record_encrypted(Path::new(output), &specs, records) {
+                Ok(()) => format!("导入完毕：{} 个文件", count),
+                Err(e) => format!("导入完毕：{} 个文件；更新保险库清单失败: {}", count, e),
+            }
+        }
+        Err(e) => format!("导入失败: {:#}", e),
+    }
+}
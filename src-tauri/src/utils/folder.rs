@@ -1,117 +1,268 @@
+use std::collections::HashSet;
 use std::fs;
-use std::fs::{metadata, File};
-use std::io::{BufReader, Cursor};
-use std::path::Path;
+use std::fs::{metadata, File, OpenOptions};
+use std::io::{BufReader, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use base64::Engine;
 use base64::engine::general_purpose;
 use image::imageops::{thumbnail, FilterType};
-use image::{open, ImageFormat, ImageReader};
+use image::{open, DynamicImage, ImageFormat};
+use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use serde::{Deserialize, Serialize};
-use tauri::AppHandle;
+use tauri::{AppHandle, Emitter};
 use tempfile::NamedTempFile;
-use crate::utils::cry_info::parse_header;
-use crate::utils::encrypt::{decrypt_file, encrypt_file};
-use crate::utils::thumbnail::make_thumbnail;
+use crate::utils::age_file::{has_age_extension, is_age_file, is_meta_file, meta_path, FileFormat};
+use crate::utils::archive::is_archive_file;
+use crate::utils::compress::Compression;
+use crate::utils::cry_info::{parse_header, update_metadata};
+use crate::utils::dedup::{content_hash, encode_hash};
+use crate::utils::encrypt::{check_options, decrypt_file, decrypt_file_with_key, encrypt_file_with_entries, list_entries, read_secret_metadata, reset_entries_with_key, unlock_file_with_master, update_secret_metadata, remove_entries_by_label, rotate_key, try_reset_entries, EncryptOptions, EntrySpec, MasterKey};
+use crate::utils::index_cache::{cached_entry, is_index_file};
+use crate::utils::meta::{Blob, FileMeta};
+use crate::utils::padding::SizePadding;
+use crate::utils::policy::check_passwords;
+use crate::utils::segment::{is_segment_file, remove_segments, verify_segments};
+use crate::utils::session;
+use crate::utils::vault_key::{find_vault_config, is_vault_config, resolve_file_key, uses_vault_entry, vault_master_key, vault_specs, KeyResolver};
+use crate::utils::vault::{dedup_key, is_manifest_file, make_record, record_decrypted, record_encrypted, ManifestRecord};
+use crate::utils::thumbnail::{flatten, make_preview, make_thumbnail_with, open_image, ThumbnailPolicy};
 
 
-#[derive(Serialize, Deserialize, Debug)]
-struct MetaData {       // 加密文件的元数据
-    thumbnail: String,  // 缩略图 base64
-    // extension: String,  // 扩展名
+// 生成加密元数据：清晰预览图和内容哈希，都没有时返回 None
+fn secret_metadata(path: &str, preview: bool, content_hash: Option<Vec<u8>>) -> Option<Vec<u8>> {
+    let meta = FileMeta {
+        preview: if preview { make_preview(path).map(|data| Blob { mime: "image/jpeg".to_string(), data }) } else { None },
+        content_hash,
+        ..Default::default()
+    };
+    if meta.preview.is_none() && meta.content_hash.is_none() {
+        return None;
+    }
+    Some(meta.to_bytes())
+}
+
+// 一次加密任务的参数，对文件夹中的每个文件都相同
+pub(crate) struct EncryptJob {
+    pub(crate) specs: Vec<EntrySpec>,
+    pub(crate) thumbnail: ThumbnailPolicy,
+    pub(crate) preview: bool,               // 是否生成清晰预览图
+    pub(crate) options: EncryptOptions,
+    pub(crate) dedup_key: Option<[u8; 32]>, // 保险库的去重密钥，启用去重时计算内容哈希
+}
+
+// 是否为需要加密的文件：不处理文件夹、已加密的文件、分段文件、容器、索引、清单、保险库配置和批量修改密码的日志
+pub(crate) fn should_encrypt(path: &Path) -> bool {
+    let encrypted = path.to_string_lossy().ends_with(".cry") || has_age_extension(path) || is_meta_file(path);
+    !path.is_dir() && !encrypted && !is_segment_file(path) && !is_archive_file(path) && !is_index_file(path) && !is_manifest_file(path) && !is_vault_config(path) && !is_journal_file(path)
+}
+
+// 加密单个文件并删除原文件，返回清单记录
+pub(crate) fn encrypt_one(path: &Path, job: &EncryptJob) -> anyhow::Result<ManifestRecord> {
+    let file = path.to_string_lossy().to_string();
+    // 缩略图
+    let thumbnail_policy = job.thumbnail;
+    let thumbnail_jpeg = make_thumbnail_with(&file, &thumbnail_policy);
+
+    // 构建文件头
+    let metadata = FileMeta {
+        thumbnail: thumbnail_jpeg.map(|data| Blob { mime: "image/jpeg".to_string(), data }),
+        thumbnail_policy: Some(thumbnail_policy),
+        ..Default::default()
+    };
+
+    // 内容哈希
+    let content_hash = job.dedup_key.as_ref().and_then(|key| content_hash(&file, key).ok());
+
+    let output = format!("{}.{}", file, job.options.format.extension());
+    encrypt_file_with_entries(
+        &file,
+        &output,
+        &job.specs,
+        Some(&metadata.to_bytes()),
+        secret_metadata(&file, job.preview, content_hash.clone()).as_deref(),
+        job.options,
+    )?;
+
+    // 删除原文件前记录原文件的信息
+    let mut record = make_record(path, Path::new(&output));
+    record.content_hash = content_hash.map(|h| encode_hash(&h));
+    fs::remove_file(path)?;
+    Ok(record)
 }
 
 // 递归加密文件夹
 #[tauri::command]
-pub fn encrypt_folder(path: &str, passwords: Vec<&str>) -> String {
+pub fn encrypt_folder(path: &str, passwords: Vec<&str>, labels: Option<Vec<&str>>, encrypt_labels: Option<bool>, thumbnail: Option<ThumbnailPolicy>, preview: Option<bool>, compression: Option<Compression>, segment_size_mb: Option<u32>, padding: Option<SizePadding>, format: Option<FileFormat>, dedup: Option<bool>) -> String {
     if path.is_empty() { return "加密失败: 路径为空！".to_string(); }
-    let result = fs::read_dir(path);
-    if result.is_err() { return format!("打开路径失败({})", path) }
+    if let Err(e) = check_passwords(&passwords) { return format!("加密失败: {}", e); }
+    if fs::read_dir(path).is_err() { return format!("打开路径失败({})", path) }
+    // 标签与密码按顺序一一对应
+    let specs = EntrySpec::from_labeled(&passwords, labels.as_deref(), encrypt_labels.unwrap_or(false));
+    // 启用主密钥的保险库中，文件只用主密钥加密，不需要为每个密码运行 Argon2
+    let specs = match vault_specs(Path::new(path), &passwords, specs) {
+        Ok(specs) => specs,
+        Err(e) => return format!("加密失败: {}", e),
+    };
+    // 去重密钥保存在保险库清单中，需要在加密前读取
+    let dedup_key = if dedup.unwrap_or(false) {
+        match dedup_key(Path::new(path), &specs) {
+            Ok(key) => Some(key),
+            Err(e) => return format!("加密失败: {}", e),
+        }
+    } else {
+        None
+    };
+    let job = EncryptJob {
+        specs,
+        thumbnail: thumbnail.unwrap_or_default(),
+        // 清晰预览图，默认生成
+        preview: preview.unwrap_or(true),
+        options: EncryptOptions {
+            compression: compression.unwrap_or_default(),
+            // 分段大小以 MB 为单位，不传或为 0 时不分段
+            segment_size: segment_size_mb.unwrap_or(0).saturating_mul(1024 * 1024),
+            padding: padding.unwrap_or_default(),
+            // 输出 age 格式时，紧急情况下可以用 age / rage 解密
+            format: format.unwrap_or_default(),
+        },
+        dedup_key,
+    };
+    // 例如 age 格式使用了多个密码或保险库主密钥，在处理文件之前报错
+    if let Err(e) = check_options(&job.specs, &job.options) {
+        return format!("加密失败: {}", e);
+    }
+    let records = Mutex::new(Vec::new());
+    let failed = Mutex::new(Vec::new());
+    encrypt_dir(path, &job, &records, &failed);
+
+    // 更新保险库清单，失败时（例如清单由其它密码创建）文件已经加密，需要告诉用户清单没有这些文件
+    let manifest = record_encrypted(Path::new(path), &job.specs, records.into_inner().unwrap());
+
+    let failed = failed.into_inner().unwrap();
+    let mut message = if failed.is_empty() {
+        format!("加密完毕：{}，密码：{:?}", path, passwords)
+    } else {
+        format!("加密完毕：{}，失败 {} 个：{:?}", path, failed.len(), failed)
+    };
+    if let Err(e) = manifest {
+        message.push_str(&format!("；更新保险库清单失败: {}", e));
+    }
+    message
+}
+
+fn encrypt_dir(path: &str, job: &EncryptJob, records: &Mutex<Vec<ManifestRecord>>, failed: &Mutex<Vec<String>>) {
+    let Ok(result) = fs::read_dir(path) else { return };
     // 创建线程池
     let pool = ThreadPoolBuilder::new().num_threads(16).build().unwrap();
     pool.scope(|scope|{
         // 遍历传入的文件夹
-        for entry in result.unwrap() {
+        for entry in result {
             // 分配到线程去做
             scope.spawn(|x| {
                 // 读取单个路径
                 let entry = entry.unwrap();
                 let path = entry.path().to_string_lossy().to_string();
-                // 不处理文件夹和已加密的文件
-                if !entry.path().is_dir() && !path.ends_with(".cry") {
-                    // 缩略图 base64
-                    let thumbnail_base64 = make_thumbnail(&path);
-                    // 扩展名
-                    // let ext = entry.path().extension().unwrap().to_string_lossy().to_string();
-
-                    // 构建文件头
-                    let metadata = MetaData {
-                        thumbnail: thumbnail_base64,
-                        // extension: ext
-                    };
-
-                    // 加密
-                    let vec_passwords: Vec<String> = passwords.iter().map(|s| s.to_string()).collect();
-                    encrypt_file(
-                        &path,
-                        &format!("{}.cry", path).to_string(),
-                        &vec_passwords,
-                        Some(serde_json::to_string(&metadata).unwrap().as_bytes())
-                    ).unwrap();
-
-                    // 删除原文件
-                    fs::remove_file(entry.path()).unwrap();
-
+                if should_encrypt(&entry.path()) {
+                    match encrypt_one(&entry.path(), job) {
+                        Ok(record) => records.lock().unwrap().push(record),
+                        Err(e) => failed.lock().unwrap().push(format!("{}: {:#}", path, e)),
+                    }
                 } else if entry.path().is_dir() {
                     // 递归子文件夹
-                    encrypt_folder(path.as_str(), passwords.clone());
+                    encrypt_dir(path.as_str(), job, records, failed);
                 }
                 // encrypt_file(entry.path()).unwrap()
             });
         }
     });
-
-    return format!("加密完毕：{}，密码：{:?}", path, passwords)
 }
 
 // 递归解密文件夹
 #[tauri::command]
 pub fn decrypt_folder(path: &str, password: &str) -> String {
     if path.is_empty() { return "加密失败: 路径为空！".to_string(); }
-    let result = fs::read_dir(path);
-    if result.is_err() { return format!("打开路径失败({})", path) }
+    if fs::read_dir(path).is_err() { return format!("打开路径失败({})", path) }
+    // 启用主密钥的保险库只需要解锁一次
+    let master_key = match vault_master_key(Path::new(path), &[password]) {
+        Ok(key) => key,
+        Err(e) => return format!("解密失败: {}", e),
+    };
+    let decrypted = Mutex::new(Vec::new());
+    let failed = Mutex::new(Vec::new());
+    decrypt_dir(path, password, master_key.as_ref(), &decrypted, &failed);
+
+    // 从保险库清单中移除
+    let specs = match &master_key {
+        Some(key) => vec![EntrySpec::from_master_key(key)],
+        None => EntrySpec::from_passwords(&[password.to_string()]),
+    };
+    let manifest = record_decrypted(Path::new(path), &specs, &decrypted.into_inner().unwrap());
+
+    let failed = failed.into_inner().unwrap();
+    let mut message = if failed.is_empty() {
+        "解密完毕".to_string()
+    } else {
+        format!("解密完毕，失败 {} 个：{:?}", failed.len(), failed)
+    };
+    if let Err(e) = manifest {
+        message.push_str(&format!("；更新保险库清单失败: {}", e));
+    }
+    message
+}
+
+// 使用主密钥解密，文件没有主密钥密码块（例如启用主密钥之前加密的文件）时返回 None
+fn decrypt_with_master(input: &str, output: &str, master_key: Option<&MasterKey>) -> Option<anyhow::Result<(bool, Vec<u8>)>> {
+    let key = unlock_file_with_master(input, master_key?).ok()?;
+    Some(decrypt_file_with_key(input, output, &key))
+}
+
+fn decrypt_dir(path: &str, password: &str, master_key: Option<&MasterKey>, decrypted: &Mutex<Vec<String>>, failed: &Mutex<Vec<String>>) {
+    let Ok(result) = fs::read_dir(path) else { return };
     // 创建线程池
     let pool = ThreadPoolBuilder::new().num_threads(16).build().unwrap();
     pool.scope(|scope| {
         // 遍历传入的文件夹
-        for entry in result.unwrap() {
+        for entry in result {
             // 分配到线程去做
             scope.spawn(|x| {
                 // 读取单个路径
                 let entry = entry.unwrap();
                 let path = entry.path().to_string_lossy().to_string();
-                // 只处理.cry文件
-                if !entry.path().is_dir() && path.ends_with(".cry") {
-                    let result = decrypt_file(
-                        &path,
-                        format!("{}", path.split_at(path.len()-4).0).as_str(),
-                        password
-                    );
-                    // 解密成功
-                    if result.is_ok() {
-                        fs::remove_file(entry.path()).unwrap();
+                // 只处理 .cry 和 .age 文件
+                let is_age = has_age_extension(&entry.path());
+                if !entry.path().is_dir() && (path.ends_with(".cry") || is_age) {
+                    let header = parse_header(&path).ok();
+                    let output = path.split_at(path.len()-4).0.to_string();
+                    let result = decrypt_with_master(&path, &output, master_key)
+                        .unwrap_or_else(|| decrypt_file(&path, &output, password));
+                    if let Err(e) = result {
+                        failed.lock().unwrap().push(format!("{}: {:#}", path, e));
+                        return;
+                    }
+                    // 解密成功，加密文件删除失败时仍保留在清单中
+                    if let Err(e) = fs::remove_file(entry.path()) {
+                        failed.lock().unwrap().push(format!("{}: 已解密，删除加密文件失败: {}", path, e));
+                        return;
+                    }
+                    if let Some(header) = &header {
+                        if let Err(e) = remove_segments(&path, header) {
+                            failed.lock().unwrap().push(format!("{}: 已解密，{:#}", path, e));
+                        }
                     }
-                    else {
-                        return println!("解密失败！")
+                    if is_age {
+                        let _ = fs::remove_file(meta_path(&path));
                     }
+                    decrypted.lock().unwrap().push(path);
                 }
                 else if entry.path().is_dir() {
-                    decrypt_folder(path.as_str(), password);
+                    decrypt_dir(path.as_str(), password, master_key, decrypted, failed);
                 }
             })
         }
     });
-    return "解密完毕".to_string();
 }
 
 
@@ -130,6 +281,9 @@ pub fn read_folder(path: &str) -> String {
     for entry in result.unwrap() {
         // 读取单个路径
         let entry = entry.unwrap();
+        if is_journal_file(&entry.path()) {
+            continue;
+        }
         let path = entry.path().to_string_lossy().to_string();
         
         files.push(FileItem {
@@ -140,20 +294,83 @@ pub fn read_folder(path: &str) -> String {
     return serde_json::to_string(&files).unwrap()
 }
 
+// 元数据摘要，缩略图本身通过 thumb:// 协议读取
+#[derive(Serialize, Debug)]
+struct MetadataSummary {
+    has_thumbnail: bool,
+    thumbnail_mime: Option<String>,
+    thumbnail_policy: Option<ThumbnailPolicy>,
+}
+
 // 读取文件元数据
 #[tauri::command]
 pub fn read_file_metadata(path: &str) -> String {
     let p = Path::new(path);
-    let ext = p.extension().unwrap().to_string_lossy().to_string();
-    if !p.is_dir() &&  !ext.ends_with(".cry") {
-        let result = parse_header(path);
+    if !p.is_dir() && path.ends_with(".cry") {
+        let result = cached_entry(p).and_then(|entry| FileMeta::parse(&entry.metadata));
         if result.is_err() { return "{}".to_string(); }
-        let header = result.unwrap();
-        return String::from_utf8(header.metadata).unwrap();
+        let meta = result.unwrap();
+        return serde_json::to_string(&MetadataSummary {
+            has_thumbnail: meta.thumbnail.is_some(),
+            thumbnail_mime: meta.thumbnail.map(|t| t.mime),
+            thumbnail_policy: meta.thumbnail_policy,
+        }).unwrap();
     }
     return "{}".to_string();
 }
 
+// 还原 URL 中的百分号编码
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // 按字节判断十六进制数字，% 后面是多字节字符时不会切在字符中间
+        if bytes[i] == b'%' && i + 3 <= bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+            out.push(u8::from_str_radix(hex, 16).unwrap());
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+// 读取缩略图；preview 为 true 时读取加密的清晰预览图，需要先解锁会话
+fn read_image(path: &str, preview: bool) -> anyhow::Result<Blob> {
+    let meta = if preview {
+        let key = session::file_key(path)?;
+        FileMeta::parse(&read_secret_metadata(path, &key)?)?
+    } else {
+        FileMeta::parse(&cached_entry(Path::new(path))?.metadata)?
+    };
+    let image = if preview { meta.preview } else { meta.thumbnail };
+    image.ok_or_else(|| anyhow::anyhow!("没有缩略图"))
+}
+
+// thumb:// 协议，直接返回图片的二进制数据，前端用 convertFileSrc(path, "thumb") 生成地址
+// 例如 thumb://localhost/<编码后的路径>，加上 ?preview 读取清晰预览图
+pub fn thumbnail_protocol(request: &tauri::http::Request<Vec<u8>>) -> tauri::http::Response<Vec<u8>> {
+    let path = percent_decode(request.uri().path().trim_start_matches('/'));
+    let preview = request.uri().query().is_some_and(|q| q.split('&').any(|p| p == "preview"));
+    // 只供本应用的 <img> 使用，不需要跨域访问
+    let response = tauri::http::Response::builder();
+    match read_image(&path, preview) {
+        Ok(image) => response
+            .header("Content-Type", image.mime)
+            .body(image.data)
+            .unwrap(),
+        Err(_) => {
+            if preview {
+                session::forget_key(&path);
+            }
+            response.status(404).body(Vec::new()).unwrap()
+        }
+    }
+}
+
 // 解密文件作为临时文件，返回路径
 #[tauri::command]
 pub fn decrypt_file_temp(path: &str, password: &str) -> String {
@@ -162,11 +379,12 @@ pub fn decrypt_file_temp(path: &str, password: &str) -> String {
     let parent_path = parent.to_string_lossy().to_string();
     let temp_file_path = format!("{}/__temp_file__", &parent_path);
     println!("{}, {}, {}",&path, temp_file_path, password);
-    let result = decrypt_file(
-        &path,
-        temp_file_path.as_str(),
-        password
-    );
+    // 保险库中的文件需要先解锁保险库
+    let result = if is_age_file(path) {
+        decrypt_file(path, temp_file_path.as_str(), password)
+    } else {
+        resolve_file_key(path, password).and_then(|key| decrypt_file_with_key(path, temp_file_path.as_str(), &key))
+    };
     // 解密成功
     if result.is_ok() {
         println!("解密成功！");
@@ -182,8 +400,8 @@ pub fn decrypt_file_temp(path: &str, password: &str) -> String {
 #[tauri::command]
 pub fn read_img_base64(path: &str) -> String {
     println!("尝试打开图片：{}", path);
-    // 尝试作为图片打开
-    let result = ImageReader::new(BufReader::new(File::open(path).unwrap())).with_guessed_format().unwrap().decode();
+    // 尝试作为图片打开，按 EXIF 方向旋转
+    let result = open_image(path);
 
     // 不是图片，返回空
     if result.is_err() {
@@ -192,7 +410,7 @@ pub fn read_img_base64(path: &str) -> String {
     // 是图片，返回base64
     else {
         // 生成预览图
-        let img = result.unwrap();
+        let img = DynamicImage::ImageRgb8(flatten(&result.unwrap()));
         let mut buffer = Vec::new();
         let mut cursor = Cursor::new(&mut buffer);
         img.write_to(&mut cursor, ImageFormat::Jpeg).unwrap();
@@ -201,3 +419,270 @@ pub fn read_img_base64(path: &str) -> String {
     }
 }
 
+
+// 列出加密文件的密码块（标签与创建时间），传入密码时可看到加密的标签
+#[tauri::command]
+pub fn list_password_entries(path: &str, password: Option<&str>) -> String {
+    let key = match password.map(|p| resolve_file_key(path, p)).transpose() {
+        Ok(key) => key,
+        Err(_) => return "[]".to_string(),
+    };
+    let result = list_entries(path, key.as_ref());
+    if result.is_err() { return "[]".to_string() }
+    return serde_json::to_string(&result.unwrap()).unwrap()
+}
+
+// 校验分段存储的加密文件，返回损坏或缺失的分段序号
+#[tauri::command]
+pub fn verify_file_segments(path: &str, password: &str) -> String {
+    match resolve_file_key(path, password).and_then(|key| verify_segments(path, &key)) {
+        Ok(bad) => serde_json::to_string(&bad).unwrap(),
+        Err(e) => format!("校验失败: {}", e),
+    }
+}
+
+// 按标签删除密码块，用于撤销某个人的密码
+#[tauri::command]
+pub fn remove_password_entry(path: &str, password: &str, label: &str) -> String {
+    match resolve_file_key(path, password).and_then(|key| remove_entries_by_label(path, &key, label)) {
+        Ok(n) => format!("已删除 {} 个密码", n),
+        Err(e) => format!("删除失败: {}", e),
+    }
+}
+
+// 递归收集文件夹中满足条件的文件
+pub(crate) fn collect_files(path: &Path, filter: &dyn Fn(&Path) -> bool, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(path) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, filter, files);
+        } else if filter(&path) {
+            files.push(path);
+        }
+    }
+}
+
+// 进度事件
+#[derive(Serialize, Clone, Debug)]
+struct Progress {
+    done: usize,
+    total: usize,
+    path: String,
+    ok: bool,
+}
+
+// 递归轮换文件夹中全部 .cry 文件的密钥，通过 rotate_progress 事件报告进度；
+// 使用保险库主密钥的文件轮换后仍然只有主密钥密码块，其它文件使用新的密码；标签与 encrypt_folder 相同
+#[tauri::command]
+pub fn rotate_folder_keys(app: AppHandle, path: &str, password: &str, passwords: Vec<&str>, labels: Option<Vec<&str>>, encrypt_labels: Option<bool>) -> String {
+    if path.is_empty() { return "轮换失败: 路径为空！".to_string(); }
+    if passwords.is_empty() { return "轮换失败: 至少需要一个新密码".to_string(); }
+    if let Err(e) = check_passwords(&passwords) { return format!("轮换失败: {}", e); }
+    let mut files = Vec::new();
+    // 保险库清单也使用相同的密码
+    collect_files(Path::new(path), &|p| p.to_string_lossy().ends_with(".cry") || is_manifest_file(p), &mut files);
+    let total = files.len();
+    let done = AtomicUsize::new(0);
+    let specs = EntrySpec::from_labeled(&passwords, labels.as_deref(), encrypt_labels.unwrap_or(false));
+    let resolver = KeyResolver::new(&[password]);
+    let pool = ThreadPoolBuilder::new().num_threads(16).build().unwrap();
+    let failed: Vec<String> = pool.install(|| {
+        files.par_iter().filter_map(|file| {
+            let file = file.to_string_lossy().to_string();
+            let result = resolver.unlock(&file).and_then(|key| {
+                match resolver.master_key(Path::new(&file)).filter(|_| uses_vault_entry(&file)) {
+                    Some(master_key) => rotate_key(&file, &key, &[EntrySpec::from_master_key(&master_key)]),
+                    None => rotate_key(&file, &key, &specs),
+                }
+            });
+            session::forget_key(&file);
+            let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+            let _ = app.emit("rotate_progress", Progress { done, total, path: file.clone(), ok: result.is_ok() });
+            match result {
+                Ok(()) => None,
+                Err(e) => Some(format!("{}: {}", file, e)),
+            }
+        }).collect()
+    });
+    if failed.is_empty() {
+        return format!("轮换完毕：{} 个文件", total);
+    }
+    format!("轮换完毕：{} 个文件，失败 {} 个：{:?}", total, failed.len(), failed)
+}
+
+// 批量修改密码时的日志文件，记录已完成的文件，中断后再次执行时跳过其中已经能用新密码解锁的文件
+const RESET_JOURNAL: &str = ".reset_passwords.journal";
+
+pub fn is_journal_file(path: &Path) -> bool {
+    path.file_name().map(|n| n == RESET_JOURNAL).unwrap_or(false)
+}
+
+// 批量修改密码的结果
+#[derive(Serialize, Debug)]
+struct ResetReport {
+    total: usize,
+    reset: usize,
+    skipped: usize,             // 上次中断前已完成、已经可以用新密码解锁的文件
+    wrong_password: Vec<String>, // 旧密码无法解锁的文件
+    errors: Vec<String>,
+}
+
+// 递归修改文件夹中全部 .cry 文件的密码，通过 reset_progress 事件报告进度；
+// 使用保险库主密钥的文件由保险库密码保护，文件夹是保险库根目录时最后修改保险库配置文件的密码；标签与 encrypt_folder 相同
+#[tauri::command]
+pub fn reset_folder_passwords(app: AppHandle, path: &str, old_password: &str, passwords: Vec<&str>, labels: Option<Vec<&str>>, encrypt_labels: Option<bool>) -> String {
+    if path.is_empty() { return "修改失败: 路径为空！".to_string(); }
+    if passwords.is_empty() { return "修改失败: 至少需要一个新密码".to_string(); }
+    if let Err(e) = check_passwords(&passwords) { return format!("修改失败: {}", e); }
+    let mut files = Vec::new();
+    collect_files(Path::new(path), &|p| p.to_string_lossy().ends_with(".cry") || is_manifest_file(p), &mut files);
+
+    // 读取上次中断留下的日志
+    let journal_path = Path::new(path).join(RESET_JOURNAL);
+    let finished: HashSet<String> = fs::read_to_string(&journal_path)
+        .map(|s| s.lines().map(|l| l.to_string()).collect())
+        .unwrap_or_default();
+    let journal = match OpenOptions::new().create(true).append(true).open(&journal_path) {
+        Ok(f) => Mutex::new(f),
+        Err(e) => return format!("修改失败: 无法创建日志文件({})", e),
+    };
+
+    let config = find_vault_config(Path::new(path)).filter(|c| c.parent() == Some(Path::new(path)));
+    let total = files.len() + config.iter().len();
+    let done = AtomicUsize::new(0);
+    let skipped = AtomicUsize::new(0);
+    let specs = EntrySpec::from_labeled(&passwords, labels.as_deref(), encrypt_labels.unwrap_or(false));
+    let resolver = KeyResolver::new(&[old_password]);
+    // 日志可能来自另一次使用不同密码的修改，只有确实能用新密码解锁的文件才跳过
+    let new_resolver = KeyResolver::new(&passwords);
+    let is_finished = |file: &str| finished.contains(file) && new_resolver.unlock(file).is_ok();
+    let pool = ThreadPoolBuilder::new().num_threads(16).build().unwrap();
+    let mut results: Vec<(String, Result<bool, String>)> = pool.install(|| {
+        files.par_iter().filter_map(|file| {
+            let file = file.to_string_lossy().to_string();
+            if is_finished(&file) {
+                done.fetch_add(1, Ordering::SeqCst);
+                skipped.fetch_add(1, Ordering::SeqCst);
+                return None;
+            }
+            let result = reset_file(&file, &resolver, &specs, config.is_some());
+            if let Ok(true) = result {
+                let _ = writeln!(journal.lock().unwrap(), "{}", file);
+            }
+            let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+            let _ = app.emit("reset_progress", Progress { done, total, path: file.clone(), ok: result == Ok(true) });
+            Some((file, result))
+        }).collect()
+    });
+
+    // 保险库配置文件放在最后，修改之前旧密码仍然可以解锁保险库中的文件
+    if let Some(config) = config {
+        let file = config.to_string_lossy().to_string();
+        if is_finished(&file) {
+            skipped.fetch_add(1, Ordering::SeqCst);
+        } else {
+            let result = try_reset_entries(&file, old_password, &specs).map_err(|e| e.to_string());
+            if let Ok(true) = result {
+                session::forget_master_key(&config);
+                let _ = writeln!(journal.lock().unwrap(), "{}", file);
+            }
+            let _ = app.emit("reset_progress", Progress { done: total, total, path: file.clone(), ok: result == Ok(true) });
+            results.push((file, result));
+        }
+    }
+
+    let mut report = ResetReport { total, reset: 0, skipped: skipped.into_inner(), wrong_password: Vec::new(), errors: Vec::new() };
+    for (file, result) in results {
+        match result {
+            Ok(true) => report.reset += 1,
+            Ok(false) => report.wrong_password.push(file),
+            Err(e) => report.errors.push(format!("{}: {}", file, e)),
+        }
+    }
+    // 全部成功才删除日志，否则保留以便下次继续
+    if report.wrong_password.is_empty() && report.errors.is_empty() {
+        drop(journal);
+        let _ = fs::remove_file(&journal_path);
+    }
+    serde_json::to_string(&report).unwrap()
+}
+
+// 修改单个文件的密码，旧密码无法解锁时返回 Ok(false)；使用保险库主密钥的文件只确认能够解锁，
+// 它们的密码随保险库配置文件一起修改，with_config 为 false 时文件夹不是保险库根目录，无法修改
+fn reset_file(file: &str, resolver: &KeyResolver, specs: &[EntrySpec], with_config: bool) -> Result<bool, String> {
+    parse_header(file).map_err(|e| e.to_string())?;
+    let Ok(key) = resolver.unlock(file) else { return Ok(false) };
+    if uses_vault_entry(file) {
+        if !with_config {
+            return Err("文件使用保险库主密钥，请在保险库根目录修改密码或使用 change_vault_passwords".to_string());
+        }
+        return Ok(true);
+    }
+    reset_entries_with_key(file, &key, specs).map(|_| true).map_err(|e| e.to_string())
+}
+
+// 解密到系统临时目录后重新生成缩略图，原来有预览图时同时重新生成预览图，保留元数据中的其它字段
+fn regenerate_thumbnail(path: &Path, resolver: &KeyResolver, policy: &ThumbnailPolicy) -> anyhow::Result<()> {
+    let file = path.to_string_lossy().to_string();
+    // 临时文件不放在保险库中，避免被同步到云盘或被监视模式加密；保留原扩展名，便于判断文件类型
+    let original = Path::new(file.trim_end_matches(".cry"));
+    let suffix = original.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let temp = tempfile::Builder::new()
+        .prefix("__temp_file__")
+        .suffix(&suffix)
+        .tempfile()?;
+    let temp_path = temp.path().to_string_lossy().to_string();
+    let key = resolver.unlock(&file)?;
+    let (matched, metadata) = decrypt_file_with_key(&file, &temp_path, &key)?;
+    if !matched {
+        anyhow::bail!("文件完整性校验失败");
+    }
+
+    let mut meta = FileMeta::parse(&metadata).unwrap_or_default();
+    meta.thumbnail = make_thumbnail_with(&temp_path, policy).map(|data| Blob { mime: "image/jpeg".to_string(), data });
+    meta.thumbnail_policy = Some(*policy);
+    update_metadata(&file, meta.to_bytes())?;
+    // 只替换已有的预览图，加密时没有生成预览图的文件保持不变；保留标签、备注等其他加密元数据
+    let mut secret = FileMeta::parse(&read_secret_metadata(&file, &key)?).unwrap_or_default();
+    if secret.preview.is_none() {
+        return Ok(());
+    }
+    secret.preview = make_preview(&temp_path).map(|data| Blob { mime: "image/jpeg".to_string(), data });
+    update_secret_metadata(&file, &key, &secret.to_bytes())
+}
+
+// 使用新的缩略图策略，递归重新生成文件夹中全部 .cry 文件的缩略图
+#[tauri::command]
+pub fn regenerate_thumbnails(path: &str, password: &str, thumbnail: ThumbnailPolicy) -> String {
+    if path.is_empty() { return "生成失败: 路径为空！".to_string(); }
+    let mut files = Vec::new();
+    collect_files(Path::new(path), &|p| p.to_string_lossy().ends_with(".cry"), &mut files);
+    let resolver = KeyResolver::new(&[password]);
+    let pool = ThreadPoolBuilder::new().num_threads(16).build().unwrap();
+    let failed: Vec<String> = pool.install(|| {
+        files.par_iter().filter_map(|file| {
+            regenerate_thumbnail(file, &resolver, &thumbnail).err().map(|e| format!("{}: {}", file.to_string_lossy(), e))
+        }).collect()
+    });
+    if failed.is_empty() {
+        return format!("生成完毕：{} 个文件", files.len());
+    }
+    format!("生成完毕：{} 个文件，失败 {} 个：{:?}", files.len(), failed.len(), failed)
+}
+
+// 读取清晰预览图 base64，需要先解锁会话；没有预览图或解锁失败时返回空
+#[tauri::command]
+pub fn read_file_preview(path: &str) -> String {
+    let data = match session::file_key(path).and_then(|key| read_secret_metadata(path, &key)) {
+        Ok(data) => data,
+        Err(_) => {
+            session::forget_key(path);
+            return "".to_string();
+        }
+    };
+    FileMeta::parse(&data).ok()
+        .and_then(|m| m.preview)
+        .map(|p| general_purpose::STANDARD.encode(p.data))
+        .unwrap_or_default()
+}
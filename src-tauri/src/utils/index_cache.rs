@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use anyhow::{bail, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crate::utils::cry_info::parse_header;
+use crate::utils::listing::is_cry_file;
+
+// 每个文件夹中的索引文件，只保存文件头中的公开元数据（缩略图等），不含任何密钥材料
+pub const INDEX_FILE: &str = ".little_secret.index";
+const INDEX_MAGIC: &[u8; 4] = b"WIDX";
+const INDEX_VERSION: u8 = 1;
+
+// 索引中的一个文件，size 和 mtime 与文件系统不一致时视为失效
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub size: u64,
+    pub mtime: u64,             // 修改时间 (Unix 纳秒)
+    pub is_cry: bool,
+    pub metadata: Vec<u8>,      // 文件头中的 Metadata 原样保存
+}
+
+// 一个文件夹的索引，按文件名查找
+#[derive(Debug, Default)]
+pub struct FolderIndex {
+    entries: HashMap<String, IndexEntry>,
+    dirty: bool,
+}
+
+// 已加载的索引，避免每次读取元数据都重新读取索引文件；按使用顺序排列，最近使用的在最后
+static INDEXES: Mutex<Vec<(PathBuf, FolderIndex)>> = Mutex::new(Vec::new());
+// 最多保留的索引数量，超过时换出最久没有使用的索引，有修改的先写入磁盘
+const MAX_LOADED_INDEXES: usize = 4;
+
+pub fn is_index_file(path: &Path) -> bool {
+    path.file_name().map(|n| n == INDEX_FILE).unwrap_or(false)
+}
+
+// 文件的大小和修改时间
+pub fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64;
+    Some((metadata.len(), mtime))
+}
+
+impl FolderIndex {
+    // 读取文件夹中的索引，不存在或已损坏时返回空索引
+    pub fn load(folder: &Path) -> FolderIndex {
+        Self::read(&folder.join(INDEX_FILE)).unwrap_or_default()
+    }
+
+    fn read(path: &Path) -> Result<FolderIndex> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC || reader.read_u8()? != INDEX_VERSION {
+            bail!("索引格式不正确");
+        }
+        let count = reader.read_u32::<BigEndian>()?;
+        let mut entries = HashMap::new();
+        for _ in 0..count {
+            let name_len = reader.read_u16::<BigEndian>()? as usize;
+            let mut name = vec![0u8; name_len];
+            reader.read_exact(&mut name)?;
+            let size = reader.read_u64::<BigEndian>()?;
+            let mtime = reader.read_u64::<BigEndian>()?;
+            let is_cry = reader.read_u8()? != 0;
+            let metadata_len = reader.read_u32::<BigEndian>()? as usize;
+            let mut metadata = Vec::new();
+            (&mut reader).take(metadata_len as u64).read_to_end(&mut metadata)?;
+            if metadata.len() != metadata_len {
+                bail!("索引已损坏");
+            }
+            entries.insert(String::from_utf8(name)?, IndexEntry { size, mtime, is_cry, metadata });
+        }
+        Ok(FolderIndex { entries, dirty: false })
+    }
+
+    // 写入索引，先写临时文件再重命名，避免中断后留下半个索引
+    pub fn save(&mut self, folder: &Path) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let path = folder.join(INDEX_FILE);
+        let tmp = folder.join(format!("{}.tmp", INDEX_FILE));
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_u8(INDEX_VERSION)?;
+        writer.write_u32::<BigEndian>(self.entries.len() as u32)?;
+        for (name, entry) in &self.entries {
+            writer.write_u16::<BigEndian>(name.len() as u16)?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_u64::<BigEndian>(entry.size)?;
+            writer.write_u64::<BigEndian>(entry.mtime)?;
+            writer.write_u8(entry.is_cry as u8)?;
+            writer.write_u32::<BigEndian>(entry.metadata.len() as u32)?;
+            writer.write_all(&entry.metadata)?;
+        }
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    // 查找未失效的索引项
+    pub fn get(&self, name: &str, size: u64, mtime: u64) -> Option<&IndexEntry> {
+        self.entries.get(name).filter(|e| e.size == size && e.mtime == mtime)
+    }
+
+    pub fn insert(&mut self, name: String, entry: IndexEntry) {
+        self.entries.insert(name, entry);
+        self.dirty = true;
+    }
+
+    // 删除已经不存在的文件
+    pub fn retain(&mut self, names: &dyn Fn(&str) -> bool) {
+        let before = self.entries.len();
+        self.entries.retain(|name, _| names(name));
+        self.dirty |= self.entries.len() != before;
+    }
+}
+
+// 在已加载的索引上执行操作，第一次使用时从磁盘读取
+pub fn with_index<T>(folder: &Path, f: impl FnOnce(&mut FolderIndex) -> T) -> T {
+    let mut indexes = INDEXES.lock().unwrap();
+    let index = match indexes.iter().position(|(path, _)| path == folder) {
+        Some(i) => indexes.remove(i).1,
+        None => FolderIndex::load(folder),
+    };
+    indexes.push((folder.to_path_buf(), index));
+    if indexes.len() > MAX_LOADED_INDEXES {
+        let (path, mut evicted) = indexes.remove(0);
+        // 索引只是缓存，写入失败时下次重新解析文件头即可
+        let _ = evicted.save(&path);
+    }
+    f(&mut indexes.last_mut().unwrap().1)
+}
+
+// 读取文件的索引项，索引失效时解析文件头并更新内存中的索引；
+// 逐个文件读取时每次都写入会反复重写整个索引，所以不在这里写入磁盘，列出文件夹或索引被换出时再写入
+pub fn cached_entry(path: &Path) -> Result<IndexEntry> {
+    let Some((size, mtime)) = file_stamp(path) else { bail!("无法读取文件信息") };
+    let folder = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    if let Some(entry) = with_index(folder, |index| index.get(&name, size, mtime).cloned()) {
+        return Ok(entry);
+    }
+    let entry = read_entry(path, size, mtime);
+    with_index(folder, |index| index.insert(name, entry.clone()));
+    Ok(entry)
+}
+
+// 解析文件头生成索引项，不是加密文件或文件头损坏时 metadata 为空
+pub fn read_entry(path: &Path, size: u64, mtime: u64) -> IndexEntry {
+    match parse_header(&path.to_string_lossy()) {
+        Ok(header) => IndexEntry { size, mtime, is_cry: true, metadata: header.metadata },
+        Err(_) => IndexEntry { size, mtime, is_cry: is_cry_file(path), metadata: Vec::new() },
+    }
+}
+
+// 把已加载的索引写入磁盘
+pub fn save_index(folder: &Path) -> Result<()> {
+    with_index(folder, |index| index.save(folder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loaded_indexes_are_bounded() {
+        let dirs: Vec<_> = (0..=MAX_LOADED_INDEXES).map(|_| tempfile::tempdir().unwrap()).collect();
+        let file = dirs[0].path().join("a.txt");
+        fs::write(&file, b"hello").unwrap();
+        let (size, mtime) = file_stamp(&file).unwrap();
+
+        // cached_entry 只更新内存中的索引
+        assert!(!cached_entry(&file).unwrap().is_cry);
+        assert!(with_index(dirs[0].path(), |index| index.get("a.txt", size, mtime).is_some()));
+        assert!(!dirs[0].path().join(INDEX_FILE).exists());
+
+        // 使用更多文件夹后最久没有使用的索引被换出，换出前写入磁盘
+        for dir in &dirs[1..] {
+            with_index(dir.path(), |_| ());
+        }
+        let loaded: Vec<PathBuf> = INDEXES.lock().unwrap().iter().map(|(path, _)| path.clone()).collect();
+        assert!(loaded.len() <= MAX_LOADED_INDEXES);
+        assert!(!loaded.iter().any(|path| path == dirs[0].path()));
+        assert!(FolderIndex::load(dirs[0].path()).get("a.txt", size, mtime).is_some());
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use base64::Engine;
+use base64::engine::general_purpose;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use tauri::ipc::Channel;
+use crate::utils::cry_info::{HEADER_MAGIC, HEADER_MAGIC_V1};
+use crate::utils::folder::is_journal_file;
+use crate::utils::index_cache::{is_index_file, read_entry, save_index, with_index, IndexEntry};
+use crate::utils::meta::FileMeta;
+use crate::utils::segment::is_segment_file;
+use crate::utils::thumbnail::ThumbnailPolicy;
+
+// 每批发送的文件数
+const DEFAULT_BATCH_SIZE: usize = 64;
+
+// 排序方式
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+}
+
+// 列表中的一个文件
+#[derive(Serialize, Debug, Clone)]
+pub struct ListedFile {
+    pub path: String,
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: u64,                              // 修改时间 (Unix 秒)
+    pub is_cry: bool,                               // 按文件头判断，而不是扩展名
+    pub thumbnail: Option<String>,                  // 缩略图 base64
+    pub thumbnail_mime: Option<String>,
+    pub thumbnail_policy: Option<ThumbnailPolicy>,
+    #[serde(skip)]
+    mtime: u64,                                     // 修改时间 (Unix 纳秒)，用于检查索引是否失效
+}
+
+// 列表摘要，文件本身通过 Channel 分批发送
+#[derive(Serialize, Debug)]
+struct ListSummary {
+    total: usize,       // 过滤后的文件总数（分页前）
+    offset: usize,
+    count: usize,       // 本次发送的文件数
+}
+
+// 读取文件开头判断是否为加密文件
+pub fn is_cry_file(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path).and_then(|mut f| f.read_exact(&mut magic)).is_ok()
+        && (&magic == HEADER_MAGIC || &magic == HEADER_MAGIC_V1)
+}
+
+// 只读取文件系统信息，不打开文件
+fn stat(path: &Path) -> Option<ListedFile> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    Some(ListedFile {
+        path: path.to_string_lossy().to_string(),
+        name: path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+        is_dir: metadata.is_dir(),
+        size: metadata.len(),
+        modified: mtime / 1_000_000_000,
+        is_cry: false,
+        thumbnail: None,
+        thumbnail_mime: None,
+        thumbnail_policy: None,
+        mtime,
+    })
+}
+
+// 用索引项补全加密文件的信息
+fn fill_metadata(file: &mut ListedFile, entry: &IndexEntry, thumbnails: bool) {
+    file.is_cry = entry.is_cry;
+    let Ok(meta) = FileMeta::parse(&entry.metadata) else { return };
+    file.thumbnail_policy = meta.thumbnail_policy;
+    if let Some(thumbnail) = meta.thumbnail {
+        file.thumbnail_mime = Some(thumbnail.mime);
+        if thumbnails {
+            file.thumbnail = Some(general_purpose::STANDARD.encode(thumbnail.data));
+        }
+    }
+}
+
+// 排序，文件夹始终在前
+fn sort_files(files: &mut [ListedFile], sort_by: SortKey, descending: bool) {
+    files.sort_by(|a, b| {
+        let order = match sort_by {
+            SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+        };
+        let order = if descending { order.reverse() } else { order };
+        b.is_dir.cmp(&a.is_dir).then(order)
+    });
+}
+
+// 一次读取文件夹中全部文件及其元数据，并行解析文件头，按批通过 Channel 发送
+// 先按文件系统信息排序分页，只解析当前页的文件头；大小和修改时间未变的文件直接使用索引
+// 大文件夹需要较长时间，在后台线程运行，不阻塞界面；异步命令的参数不能是引用，所以 path 为 String
+#[tauri::command(async)]
+pub fn list_folder_with_metadata(
+    path: String,
+    on_batch: Channel<Vec<ListedFile>>,
+    sort_by: Option<SortKey>,
+    descending: Option<bool>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    batch_size: Option<usize>,
+    include_hidden: Option<bool>,
+    thumbnails: Option<bool>,
+) -> String {
+    let result = fs::read_dir(&path);
+    if result.is_err() { return "".to_string(); }
+    let include_hidden = include_hidden.unwrap_or(false);
+    let paths: Vec<_> = result.unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| !is_index_file(p) && !is_segment_file(p) && !is_journal_file(p))
+        .filter(|p| include_hidden || !p.file_name().map(|n| n.to_string_lossy().starts_with('.')).unwrap_or(false))
+        .collect();
+    let mut files: Vec<ListedFile> = paths.par_iter().filter_map(|p| stat(p)).collect();
+    sort_files(&mut files, sort_by.unwrap_or_default(), descending.unwrap_or(false));
+
+    // 分页
+    let total = files.len();
+    let offset = offset.unwrap_or(0).min(total);
+    let end = limit.map(|l| (offset + l).min(total)).unwrap_or(total);
+    let mut page = files.drain(offset..end).collect::<Vec<_>>();
+
+    // 按批并行读取文件头，保持排序后的顺序发送
+    let folder = Path::new(&path);
+    let thumbnails = thumbnails.unwrap_or(true);
+    for batch in page.chunks_mut(batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1)) {
+        let cached: Vec<Option<IndexEntry>> = with_index(folder, |index| {
+            batch.iter().map(|f| index.get(&f.name, f.size, f.mtime).cloned()).collect()
+        });
+        let entries: Vec<(IndexEntry, bool)> = batch.par_iter().zip(cached).map(|(f, cached)| match cached {
+            Some(entry) => (entry, false),
+            None if f.is_dir => (IndexEntry { size: f.size, mtime: f.mtime, is_cry: false, metadata: Vec::new() }, false),
+            None => (read_entry(Path::new(&f.path), f.size, f.mtime), true),
+        }).collect();
+        with_index(folder, |index| {
+            for (f, (entry, fresh)) in batch.iter().zip(&entries) {
+                if *fresh {
+                    index.insert(f.name.clone(), entry.clone());
+                }
+            }
+        });
+        for (f, (entry, _)) in batch.iter_mut().zip(&entries) {
+            fill_metadata(f, entry, thumbnails);
+        }
+        if on_batch.send(batch.to_vec()).is_err() {
+            // 前端已经离开页面
+            break;
+        }
+    }
+
+    // 清理已删除的文件并保存索引
+    let names: HashSet<String> = paths.iter().filter_map(|p| p.file_name()).map(|n| n.to_string_lossy().to_string()).collect();
+    with_index(folder, |index| index.retain(&|name| names.contains(name)));
+    let _ = save_index(folder);
+
+    serde_json::to_string(&ListSummary { total, offset, count: end - offset }).unwrap()
+}
@@ -0,0 +1,264 @@
+use anyhow::{bail, Result};
+use base64::Engine;
+use base64::engine::general_purpose;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read};
+use crate::utils::thumbnail::{PrivacyLevel, ThumbnailPolicy};
+
+// 元数据容器的标识，旧文件的元数据是 JSON
+const META_MAGIC: &[u8; 4] = b"WMT1";
+
+// 记录类型
+const TAG_THUMBNAIL: u16 = 1;
+const TAG_THUMBNAIL_MIME: u16 = 2;
+const TAG_THUMBNAIL_POLICY: u16 = 3;
+const TAG_PREVIEW: u16 = 4;
+const TAG_PREVIEW_MIME: u16 = 5;
+const TAG_TAG: u16 = 6;
+const TAG_NOTE: u16 = 7;
+const TAG_RATING: u16 = 8;
+const TAG_CONTENT_HASH: u16 = 9;
+
+// 评分范围
+pub const MAX_RATING: u8 = 5;
+
+// 带 MIME 类型的二进制数据，例如缩略图
+#[derive(Debug, Clone)]
+pub struct Blob {
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+// 文件头中的元数据，公开元数据和加密元数据使用同一种容器
+// 格式：Magic(4) + 多条记录，每条记录为 Tag(2, BE) + Length(4, BE) + Value
+#[derive(Debug, Default, Clone)]
+pub struct FileMeta {
+    pub thumbnail: Option<Blob>,                    // 模糊缩略图（公开）
+    pub thumbnail_policy: Option<ThumbnailPolicy>,  // 生成缩略图时使用的策略（公开）
+    pub preview: Option<Blob>,                      // 清晰预览图（加密）
+    pub tags: Vec<String>,                          // 用户标签，每个标签一条记录（加密）
+    pub note: Option<String>,                       // 备注（加密）
+    pub rating: Option<u8>,                         // 评分 1-5（加密）
+    pub content_hash: Option<Vec<u8>>,              // 使用保险库去重密钥计算的原文 HMAC，见 dedup.rs（加密）
+    pub unknown: Vec<(u16, Vec<u8>)>,               // 不认识的记录，原样保留
+}
+
+fn level_to_u8(level: PrivacyLevel) -> u8 {
+    match level {
+        PrivacyLevel::None => 0,
+        PrivacyLevel::Icon => 1,
+        PrivacyLevel::HeavyBlur => 2,
+        PrivacyLevel::LightBlur => 3,
+        PrivacyLevel::Pixelated => 4,
+    }
+}
+
+fn level_from_u8(level: u8) -> Result<PrivacyLevel> {
+    Ok(match level {
+        0 => PrivacyLevel::None,
+        1 => PrivacyLevel::Icon,
+        2 => PrivacyLevel::HeavyBlur,
+        3 => PrivacyLevel::LightBlur,
+        4 => PrivacyLevel::Pixelated,
+        _ => bail!("未知的隐私级别: {}", level),
+    })
+}
+
+fn write_record(out: &mut Vec<u8>, tag: u16, value: &[u8]) {
+    out.write_u16::<BigEndian>(tag).unwrap();
+    out.write_u32::<BigEndian>(value.len() as u32).unwrap();
+    out.extend_from_slice(value);
+}
+
+impl FileMeta {
+    // 解析元数据，兼容旧的 JSON 格式；空数据返回默认值
+    pub fn parse(bytes: &[u8]) -> Result<FileMeta> {
+        if bytes.is_empty() {
+            return Ok(FileMeta::default());
+        }
+        if !bytes.starts_with(META_MAGIC) {
+            return Self::parse_legacy_json(bytes);
+        }
+
+        let mut meta = FileMeta::default();
+        let mut thumbnail_mime = None;
+        let mut preview_mime = None;
+        let mut reader = Cursor::new(&bytes[META_MAGIC.len()..]);
+        while (reader.position() as usize) < bytes.len() - META_MAGIC.len() {
+            let tag = reader.read_u16::<BigEndian>()?;
+            let len = reader.read_u32::<BigEndian>()? as usize;
+            if len > bytes.len() {
+                bail!("元数据已损坏");
+            }
+            let mut value = vec![0u8; len];
+            reader.read_exact(&mut value)?;
+            match tag {
+                TAG_THUMBNAIL => meta.thumbnail = Some(Blob { mime: String::new(), data: value }),
+                TAG_THUMBNAIL_MIME => thumbnail_mime = Some(String::from_utf8_lossy(&value).to_string()),
+                TAG_THUMBNAIL_POLICY => {
+                    let mut value = Cursor::new(value);
+                    let level = level_from_u8(value.read_u8()?)?;
+                    let size = value.read_u32::<BigEndian>()?;
+                    meta.thumbnail_policy = Some(ThumbnailPolicy { level, size });
+                }
+                TAG_PREVIEW => meta.preview = Some(Blob { mime: String::new(), data: value }),
+                TAG_PREVIEW_MIME => preview_mime = Some(String::from_utf8_lossy(&value).to_string()),
+                TAG_TAG => meta.tags.push(String::from_utf8_lossy(&value).to_string()),
+                TAG_NOTE => meta.note = Some(String::from_utf8_lossy(&value).to_string()),
+                TAG_RATING => meta.rating = value.first().copied(),
+                TAG_CONTENT_HASH => meta.content_hash = Some(value),
+                _ => meta.unknown.push((tag, value)),
+            }
+        }
+        // MIME 缺失时按 JPEG 处理
+        if let Some(thumbnail) = meta.thumbnail.as_mut() {
+            thumbnail.mime = thumbnail_mime.unwrap_or_else(|| "image/jpeg".to_string());
+        }
+        if let Some(preview) = meta.preview.as_mut() {
+            preview.mime = preview_mime.unwrap_or_else(|| "image/jpeg".to_string());
+        }
+        Ok(meta)
+    }
+
+    // 旧格式：{"thumbnail": base64, "thumbnail_policy": {...}, "preview": base64}
+    fn parse_legacy_json(bytes: &[u8]) -> Result<FileMeta> {
+        let value: serde_json::Value = serde_json::from_slice(bytes)?;
+        let blob = |key: &str| {
+            value.get(key)
+                .and_then(|v| v.as_str())
+                .filter(|s| !s.is_empty())
+                .and_then(|s| general_purpose::STANDARD.decode(s).ok())
+                .map(|data| Blob { mime: "image/jpeg".to_string(), data })
+        };
+        Ok(FileMeta {
+            thumbnail: blob("thumbnail"),
+            thumbnail_policy: value.get("thumbnail_policy").and_then(|v| serde_json::from_value(v.clone()).ok()),
+            preview: blob("preview"),
+            ..Default::default()
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = META_MAGIC.to_vec();
+        if let Some(thumbnail) = &self.thumbnail {
+            write_record(&mut out, TAG_THUMBNAIL_MIME, thumbnail.mime.as_bytes());
+            write_record(&mut out, TAG_THUMBNAIL, &thumbnail.data);
+        }
+        if let Some(policy) = &self.thumbnail_policy {
+            let mut value = vec![level_to_u8(policy.level)];
+            value.write_u32::<BigEndian>(policy.size).unwrap();
+            write_record(&mut out, TAG_THUMBNAIL_POLICY, &value);
+        }
+        if let Some(preview) = &self.preview {
+            write_record(&mut out, TAG_PREVIEW_MIME, preview.mime.as_bytes());
+            write_record(&mut out, TAG_PREVIEW, &preview.data);
+        }
+        for tag in &self.tags {
+            write_record(&mut out, TAG_TAG, tag.as_bytes());
+        }
+        if let Some(note) = &self.note {
+            write_record(&mut out, TAG_NOTE, note.as_bytes());
+        }
+        if let Some(rating) = self.rating {
+            write_record(&mut out, TAG_RATING, &[rating]);
+        }
+        if let Some(hash) = &self.content_hash {
+            write_record(&mut out, TAG_CONTENT_HASH, hash);
+        }
+        for (tag, value) in &self.unknown {
+            write_record(&mut out, *tag, value);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_meta() -> FileMeta {
+        FileMeta {
+            thumbnail: Some(Blob { mime: "image/webp".to_string(), data: vec![1, 2, 3] }),
+            thumbnail_policy: Some(ThumbnailPolicy { level: PrivacyLevel::LightBlur, size: 256 }),
+            preview: Some(Blob { mime: "image/jpeg".to_string(), data: vec![4; 1000] }),
+            tags: vec!["家庭".to_string(), "2024".to_string()],
+            note: Some("备注".to_string()),
+            rating: Some(4),
+            content_hash: Some(vec![9; 32]),
+            unknown: vec![(100, vec![7, 7]), (0xffff, Vec::new())],
+        }
+    }
+
+    fn assert_same(a: &FileMeta, b: &FileMeta) {
+        assert_eq!(a.thumbnail.as_ref().map(|t| (&t.mime, &t.data)), b.thumbnail.as_ref().map(|t| (&t.mime, &t.data)));
+        assert_eq!(a.thumbnail_policy.map(|p| (p.level, p.size)), b.thumbnail_policy.map(|p| (p.level, p.size)));
+        assert_eq!(a.preview.as_ref().map(|t| (&t.mime, &t.data)), b.preview.as_ref().map(|t| (&t.mime, &t.data)));
+        assert_eq!(a.tags, b.tags);
+        assert_eq!(a.note, b.note);
+        assert_eq!(a.rating, b.rating);
+        assert_eq!(a.content_hash, b.content_hash);
+        assert_eq!(a.unknown, b.unknown);
+    }
+
+    #[test]
+    fn meta_round_trip() {
+        let meta = full_meta();
+        let bytes = meta.to_bytes();
+        assert_same(&FileMeta::parse(&bytes).unwrap(), &meta);
+        assert_eq!(FileMeta::parse(&bytes).unwrap().to_bytes(), bytes);
+
+        let empty = FileMeta::default();
+        assert_eq!(empty.to_bytes(), META_MAGIC.to_vec());
+        assert_same(&FileMeta::parse(&empty.to_bytes()).unwrap(), &empty);
+        assert_same(&FileMeta::parse(&[]).unwrap(), &empty);
+    }
+
+    #[test]
+    fn meta_keeps_unknown_tags() {
+        // 新版本写入的记录夹在已知记录之间，旧版本读取后再写回不丢失
+        let mut bytes = META_MAGIC.to_vec();
+        write_record(&mut bytes, TAG_TAG, "a".as_bytes());
+        write_record(&mut bytes, 200, &[1, 2, 3]);
+        write_record(&mut bytes, TAG_RATING, &[5]);
+        write_record(&mut bytes, 201, &[]);
+        let meta = FileMeta::parse(&bytes).unwrap();
+        assert_eq!(meta.tags, vec!["a".to_string()]);
+        assert_eq!(meta.rating, Some(5));
+        assert_eq!(meta.unknown, vec![(200, vec![1, 2, 3]), (201, Vec::new())]);
+        assert_eq!(FileMeta::parse(&meta.to_bytes()).unwrap().unknown, meta.unknown);
+    }
+
+    #[test]
+    fn meta_missing_mime_defaults_to_jpeg() {
+        let mut bytes = META_MAGIC.to_vec();
+        write_record(&mut bytes, TAG_THUMBNAIL, &[1]);
+        let meta = FileMeta::parse(&bytes).unwrap();
+        assert_eq!(meta.thumbnail.unwrap().mime, "image/jpeg");
+    }
+
+    #[test]
+    fn meta_rejects_corruption() {
+        let bytes = full_meta().to_bytes();
+        // 截断在记录头或记录值中间
+        for len in [META_MAGIC.len() + 1, META_MAGIC.len() + 5, bytes.len() - 1] {
+            assert!(FileMeta::parse(&bytes[..len]).is_err(), "截断到 {} 没有报错", len);
+        }
+        // 记录长度超过数据长度
+        let mut bytes = META_MAGIC.to_vec();
+        bytes.extend_from_slice(&[0, 1, 0xff, 0xff, 0xff, 0xff]);
+        assert!(FileMeta::parse(&bytes).is_err());
+        // 未知的隐私级别
+        let mut bytes = META_MAGIC.to_vec();
+        write_record(&mut bytes, TAG_THUMBNAIL_POLICY, &[9, 0, 0, 1, 0]);
+        assert!(FileMeta::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn meta_parses_legacy_json() {
+        let json = br#"{"thumbnail": "AQID", "thumbnail_policy": {"level": "heavy_blur", "size": 128}, "preview": ""}"#;
+        let meta = FileMeta::parse(json).unwrap();
+        assert_eq!(meta.thumbnail.map(|t| (t.mime, t.data)), Some(("image/jpeg".to_string(), vec![1, 2, 3])));
+        assert_eq!(meta.thumbnail_policy.map(|p| (p.level, p.size)), Some((PrivacyLevel::HeavyBlur, 128)));
+        assert!(meta.preview.is_none());
+    }
+}
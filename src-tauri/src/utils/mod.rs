@@ -1,7 +1,25 @@
 pub mod encrypt;
+pub mod age_file;
 pub mod cry_info;
+pub mod cry_reader;
+pub mod compress;
+pub mod segment;
+pub mod padding;
 pub mod folder;
+pub mod archive;
+pub mod exchange;
+pub mod bundle;
+pub mod index_cache;
+pub mod listing;
+pub mod meta;
 pub mod thumbnail;
+pub mod policy;
+pub mod session;
+pub mod tags;
+pub mod vault;
+pub mod vault_key;
+pub mod dedup;
+pub mod watch;
 
 
 
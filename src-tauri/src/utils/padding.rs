@@ -0,0 +1,192 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use anyhow::{bail, Result};
+use chacha20::ChaCha20;
+use cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use hmac::Mac;
+use serde::{Deserialize, Serialize};
+
+// 文件头中的长度填充方式
+pub const SIZE_PADDING_NONE: u8 = 0;
+pub const SIZE_PADDING_PADME: u8 = 1;
+
+// 填充后的密文末尾固定为 8 字节的内容长度（加密）
+const LENGTH_TAIL: u64 = 8;
+
+// 填充密文长度，避免通过文件大小识别文件
+// 填充位于密文内部：内容 + 0 填充 + 内容长度(8, BE)，一起加密并计入 File HMAC
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SizePadding {
+    #[default]
+    None,   // 不填充
+    Padme,  // 按 PADMÉ 分桶，最多增加约 12%
+}
+
+impl SizePadding {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            SizePadding::None => SIZE_PADDING_NONE,
+            SizePadding::Padme => SIZE_PADDING_PADME,
+        }
+    }
+}
+
+// PADMÉ：长度 L 只保留 floor(log2(log2(L))) + 1 位有效数字，其余低位向上取整
+pub fn padme_len(len: u64) -> u64 {
+    if len < 2 {
+        return len;
+    }
+    let e = 63 - len.leading_zeros() as u64;
+    let s = 64 - e.leading_zeros() as u64;
+    let mask = (1u64 << (e - s)) - 1;
+    (len + mask) & !mask
+}
+
+// 在内容之后写入填充和内容长度，cipher 与 hmac 需接着内容继续使用，返回写入的长度
+pub fn write_padding<W: Write, M: Mac>(writer: &mut W, content_len: u64, cipher: &mut ChaCha20, hmac: &mut M) -> Result<u64> {
+    let total = padme_len(content_len + LENGTH_TAIL);
+    let mut remaining = total - content_len - LENGTH_TAIL;
+    let mut buf = [0u8; 8192];
+    while remaining > 0 {
+        let n = remaining.min(buf.len() as u64) as usize;
+        let chunk = &mut buf[..n];
+        chunk.fill(0);
+        hmac.update(chunk);
+        cipher.apply_keystream(chunk);
+        writer.write_all(chunk)?;
+        remaining -= n as u64;
+    }
+    let mut tail = content_len.to_be_bytes();
+    hmac.update(&tail);
+    cipher.apply_keystream(&mut tail);
+    writer.write_all(&tail)?;
+    Ok(total - content_len)
+}
+
+// 读取填充前的内容长度，reader 的位置从密文开头算起，stored_len 为密文总长度
+fn read_content_len<R: Read + Seek>(reader: &mut R, stored_len: u64, cek: &[u8; 32], cek_nonce: &[u8; 12]) -> Result<u64> {
+    if stored_len < LENGTH_TAIL {
+        bail!("长度填充已损坏");
+    }
+    let mut tail = [0u8; LENGTH_TAIL as usize];
+    reader.seek(SeekFrom::Start(stored_len - LENGTH_TAIL))?;
+    reader.read_exact(&mut tail)?;
+    let mut cipher = ChaCha20::new(&(*cek).into(), &(*cek_nonce).into());
+    cipher.seek(stored_len - LENGTH_TAIL);
+    cipher.apply_keystream(&mut tail);
+    let content_len = u64::from_be_bytes(tail);
+    if content_len > stored_len - LENGTH_TAIL {
+        bail!("长度填充已损坏");
+    }
+    Ok(content_len)
+}
+
+// 解密内容之后的填充并计入 hmac，reader 需位于内容末尾
+pub fn hash_padding<R: Read, M: Mac>(reader: &mut R, content_len: u64, cek: &[u8; 32], cek_nonce: &[u8; 12], hmac: &mut M) -> Result<()> {
+    let mut cipher = ChaCha20::new(&(*cek).into(), &(*cek_nonce).into());
+    cipher.seek(content_len);
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        cipher.apply_keystream(&mut buf[..n]);
+        hmac.update(&buf[..n]);
+    }
+    Ok(())
+}
+
+// 密文中内容部分的长度；没有填充时就是密文总长度，reader 的位置从密文开头算起
+pub fn content_len<R: Read + Seek>(reader: &mut R, size_padding: u8, cek: &[u8; 32], cek_nonce: &[u8; 12]) -> Result<u64> {
+    let stored_len = reader.seek(SeekFrom::End(0))?;
+    let len = match size_padding {
+        SIZE_PADDING_NONE => stored_len,
+        SIZE_PADDING_PADME => read_content_len(reader, stored_len, cek, cek_nonce)?,
+        other => bail!("不支持的长度填充方式: {}", other),
+    };
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use hmac::Hmac;
+    use sha2::Sha256;
+
+    type HmacSha256 = Hmac<Sha256>;
+
+    const CEK: [u8; 32] = [7; 32];
+    const NONCE: [u8; 12] = [9; 12];
+
+    #[test]
+    fn padme_len_boundaries() {
+        for len in 0..8 {
+            assert_eq!(padme_len(len), len);
+        }
+        assert_eq!(padme_len(9), 10);
+        assert_eq!(padme_len(1000), 1024);
+        assert_eq!(padme_len(1025), 1088);
+        for e in 1..62 {
+            assert_eq!(padme_len(1 << e), 1 << e);
+            assert!(padme_len((1 << e) + 1) > 1 << e);
+        }
+        // 不会变短，重复填充不变，最多增加 1/8
+        let mut last = 0;
+        for len in (2..200_000).chain([u32::MAX as u64, 1 << 40, (1 << 40) + 1, (1 << 61) + 12345]) {
+            let padded = padme_len(len);
+            assert!(padded >= len && padded >= last);
+            assert_eq!(padme_len(padded), padded);
+            assert!((padded - len) * 8 <= len, "{} -> {}", len, padded);
+            last = padded;
+        }
+    }
+
+    // 按 encrypt.rs 的方式加密内容并写入填充，返回密文和写入时的 HMAC
+    fn padded(len: u64) -> (Vec<u8>, Vec<u8>) {
+        let mut cipher = ChaCha20::new(&CEK.into(), &NONCE.into());
+        let mut hmac = HmacSha256::new_from_slice(&CEK).unwrap();
+        let mut out = vec![0x5a; len as usize];
+        hmac.update(&out);
+        cipher.apply_keystream(&mut out);
+        let added = write_padding(&mut out, len, &mut cipher, &mut hmac).unwrap();
+        assert_eq!(len + added, out.len() as u64);
+        (out, hmac.finalize().into_bytes().to_vec())
+    }
+
+    #[test]
+    fn padding_round_trip() {
+        for len in [0, 1, 100, 4096, 70_000] {
+            let (stored, expected) = padded(len);
+            assert_eq!(stored.len() as u64, padme_len(len + LENGTH_TAIL));
+
+            let mut reader = Cursor::new(stored);
+            assert_eq!(content_len(&mut reader, SIZE_PADDING_PADME, &CEK, &NONCE).unwrap(), len);
+            assert_eq!(content_len(&mut reader, SIZE_PADDING_NONE, &CEK, &NONCE).unwrap(), reader.get_ref().len() as u64);
+
+            // 解密内容，再接着计算填充，HMAC 与写入时相同
+            let mut cipher = ChaCha20::new(&CEK.into(), &NONCE.into());
+            let mut hmac = HmacSha256::new_from_slice(&CEK).unwrap();
+            let mut content = vec![0u8; len as usize];
+            reader.read_exact(&mut content).unwrap();
+            cipher.apply_keystream(&mut content);
+            assert!(content.iter().all(|b| *b == 0x5a));
+            hmac.update(&content);
+            hash_padding(&mut reader, len, &CEK, &NONCE, &mut hmac).unwrap();
+            assert_eq!(hmac.finalize().into_bytes().to_vec(), expected);
+        }
+    }
+
+    #[test]
+    fn padding_rejects_corrupt_length() {
+        let (mut stored, _) = padded(100);
+        // 内容长度的最高字节被修改后超过密文长度
+        let tail = stored.len() - LENGTH_TAIL as usize;
+        stored[tail] ^= 0x80;
+        assert!(content_len(&mut Cursor::new(stored), SIZE_PADDING_PADME, &CEK, &NONCE).is_err());
+        assert!(content_len(&mut Cursor::new(vec![0u8; 7]), SIZE_PADDING_PADME, &CEK, &NONCE).is_err());
+        assert!(content_len(&mut Cursor::new(vec![0u8; 64]), 2, &CEK, &NONCE).is_err());
+    }
+}
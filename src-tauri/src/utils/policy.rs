@@ -0,0 +1,227 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+// 内置的常见密码列表，按常见程度排序
+const COMMON_PASSWORDS: &str = include_str!("../../resources/common_passwords.txt");
+
+// 键盘上相邻的按键序列
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+// 密码策略
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PasswordPolicy {
+    pub min_length: usize,      // 最短长度
+    pub min_entropy: f64,       // 最低估计熵（位）
+}
+
+// 默认要求约 10^10 次猜测，相当于 zxcvbn 的 3 分
+const DEFAULT_POLICY: PasswordPolicy = PasswordPolicy { min_length: 8, min_entropy: 33.0 };
+
+static POLICY: RwLock<PasswordPolicy> = RwLock::new(DEFAULT_POLICY);
+
+// 最短长度的下限，与界面的要求一致
+const MIN_POLICY_LENGTH: usize = 6;
+
+// 修改后的密码策略保存在应用配置目录中，启动时读取
+const POLICY_FILE: &str = "password_policy.json";
+
+pub fn current_policy() -> PasswordPolicy {
+    *POLICY.read().unwrap()
+}
+
+// 检查策略的取值，避免设置为不要求密码或无法比较的熵
+fn validate_policy(policy: &PasswordPolicy) -> Result<()> {
+    if policy.min_length < MIN_POLICY_LENGTH {
+        bail!("最短长度不能小于 {} 个字符", MIN_POLICY_LENGTH);
+    }
+    if !policy.min_entropy.is_finite() || policy.min_entropy < 0.0 {
+        bail!("最低熵必须是不小于 0 的数字");
+    }
+    Ok(())
+}
+
+fn policy_path(app: &AppHandle) -> Result<PathBuf> {
+    Ok(app.path().app_config_dir().context("找不到应用配置目录")?.join(POLICY_FILE))
+}
+
+// 读取保存的密码策略，没有保存过、文件已损坏或取值无效时使用默认策略
+pub fn load_policy(app: &AppHandle) {
+    let saved = policy_path(app).ok()
+        .and_then(|path| fs::read(path).ok())
+        .and_then(|data| serde_json::from_slice::<PasswordPolicy>(&data).ok())
+        .filter(|policy| validate_policy(policy).is_ok());
+    if let Some(policy) = saved {
+        *POLICY.write().unwrap() = policy;
+    }
+}
+
+fn save_policy(app: &AppHandle, policy: &PasswordPolicy) -> Result<()> {
+    let path = policy_path(app)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("创建配置目录失败")?;
+    }
+    fs::write(&path, serde_json::to_vec_pretty(policy)?).context("保存密码策略失败")
+}
+
+// 密码强度评估结果
+#[derive(Serialize, Debug)]
+pub struct Strength {
+    pub entropy: f64,          // 估计熵（位）
+    pub common: bool,          // 是否在常见密码列表中
+}
+
+// 还原常见的字母数字替换，例如 p@ssw0rd -> password
+fn unleet(c: char) -> char {
+    match c {
+        '@' | '4' => 'a',
+        '0' => 'o',
+        '1' | '!' => 'i',
+        '3' => 'e',
+        '5' | '$' => 's',
+        '7' => 't',
+        _ => c,
+    }
+}
+
+// 在常见密码列表中查找，返回排名（从 1 开始）
+fn common_rank(word: &str) -> Option<usize> {
+    COMMON_PASSWORDS.lines().position(|l| l == word).map(|i| i + 1)
+}
+
+// 字符集大小
+fn pool_size(password: &str) -> f64 {
+    let mut pool = 0;
+    if password.chars().any(|c| c.is_ascii_lowercase()) { pool += 26; }
+    if password.chars().any(|c| c.is_ascii_uppercase()) { pool += 26; }
+    if password.chars().any(|c| c.is_ascii_digit()) { pool += 10; }
+    if password.chars().any(|c| c.is_ascii_punctuation() || c == ' ') { pool += 33; }
+    if !password.is_ascii() { pool += 100; }
+    pool.max(1) as f64
+}
+
+// 从 chars[start..] 开始的重复、连续或键盘序列长度（至少 3 个字符才算）
+fn pattern_len(chars: &[char], start: usize) -> usize {
+    let mut best = 0;
+    // 重复：aaaa
+    let repeat = chars[start..].iter().take_while(|&&c| c == chars[start]).count();
+    best = best.max(repeat);
+    // 连续：abcd、4321
+    for step in [1i32, -1] {
+        let mut n = 1;
+        while start + n < chars.len() && chars[start + n] as i32 - chars[start + n - 1] as i32 == step {
+            n += 1;
+        }
+        best = best.max(n);
+    }
+    // 键盘序列：qwer、asdf
+    for row in KEYBOARD_ROWS {
+        let row: Vec<char> = row.chars().collect();
+        if let Some(pos) = row.iter().position(|&c| c == chars[start]) {
+            let n = chars[start..].iter().zip(&row[pos..]).take_while(|(a, b)| a == b).count();
+            best = best.max(n);
+        }
+    }
+    if best >= 3 { best } else { 0 }
+}
+
+// 估计密码强度：按常见单词、规律序列和随机字符拆分密码，分别计算猜测难度后相加
+pub fn estimate(password: &str) -> Strength {
+    let lower = password.to_lowercase();
+    let normalized: String = lower.chars().map(unleet).collect();
+    if let Some(rank) = common_rank(&lower).or_else(|| common_rank(&normalized)) {
+        return Strength { entropy: (rank as f64).log2() + 1.0, common: true };
+    }
+
+    // 单词按还原替换后的字符匹配，规律序列按原字符匹配
+    let words: Vec<char> = normalized.chars().collect();
+    let chars: Vec<char> = lower.chars().collect();
+    let char_bits = pool_size(password).log2();
+    let mut entropy = 0.0;
+    let mut i = 0;
+    while i < chars.len() {
+        // 常见单词（至少 4 个字符），取最长的匹配
+        let word = (4..=chars.len() - i).rev().find_map(|len| {
+            let word: String = words[i..i + len].iter().collect();
+            common_rank(&word).map(|rank| (len, rank))
+        });
+        if let Some((len, rank)) = word {
+            // 额外 1 位用于大小写或替换的变化
+            entropy += (rank as f64).log2() + 1.0;
+            i += len;
+            continue;
+        }
+        // 规律序列只计算首字符和长度
+        let len = pattern_len(&chars, i);
+        if len > 0 {
+            entropy += char_bits + (len as f64).log2();
+            i += len;
+            continue;
+        }
+        entropy += char_bits;
+        i += 1;
+    }
+    Strength { entropy, common: false }
+}
+
+// 按当前策略检查密码，不满足时返回原因
+pub fn check_password(password: &str) -> Result<()> {
+    let policy = current_policy();
+    if password.chars().count() < policy.min_length {
+        bail!("密码过短，至少需要 {} 个字符", policy.min_length);
+    }
+    let strength = estimate(password);
+    if strength.common {
+        bail!("密码过于常见，容易被字典攻击");
+    }
+    if strength.entropy < policy.min_entropy {
+        bail!("密码强度不足（估计 {:.0} 位，至少需要 {:.0} 位），请使用更长或更随机的密码", strength.entropy, policy.min_entropy);
+    }
+    Ok(())
+}
+
+// 检查多个密码，错误信息中指明是第几个密码
+pub fn check_passwords(passwords: &[&str]) -> Result<()> {
+    for (i, password) in passwords.iter().enumerate() {
+        if let Err(e) = check_password(password) {
+            bail!("第 {} 个密码不符合要求: {}", i + 1, e);
+        }
+    }
+    Ok(())
+}
+
+// 评估密码强度，供界面实时提示
+#[tauri::command]
+pub fn password_strength(password: &str) -> String {
+    serde_json::to_string(&estimate(password)).unwrap()
+}
+
+// 修改密码策略，保存后重启仍然有效
+#[tauri::command]
+pub fn set_password_policy(app: AppHandle, min_length: usize, min_entropy: f64) -> String {
+    let policy = PasswordPolicy { min_length, min_entropy };
+    if let Err(e) = validate_policy(&policy).and_then(|_| save_policy(&app, &policy)) {
+        return format!("修改失败: {:#}", e);
+    }
+    *POLICY.write().unwrap() = policy;
+    serde_json::to_string(&current_policy()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_rejects_invalid_values() {
+        assert!(validate_policy(&DEFAULT_POLICY).is_ok());
+        assert!(validate_policy(&PasswordPolicy { min_length: MIN_POLICY_LENGTH, min_entropy: 0.0 }).is_ok());
+        assert!(validate_policy(&PasswordPolicy { min_length: 0, min_entropy: 33.0 }).is_err());
+        assert!(validate_policy(&PasswordPolicy { min_length: MIN_POLICY_LENGTH - 1, min_entropy: 33.0 }).is_err());
+        for min_entropy in [-1.0, f64::NAN, f64::INFINITY] {
+            assert!(validate_policy(&PasswordPolicy { min_length: 8, min_entropy }).is_err(), "{}", min_entropy);
+        }
+    }
+}
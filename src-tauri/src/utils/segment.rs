@@ -0,0 +1,357 @@
+use std::fs::{remove_file, rename, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use crate::utils::cry_info::{parse_header, FileHeader};
+use crate::utils::encrypt::{verify_cek, FileKey};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// 分段存储：头部文件 (xxx.cry) 只保存文件头，密文按固定长度拆分为编号的分段文件 (xxx.cry.000001 ...)
+// 每个分段文件为 密文 + HMAC(32)，HMAC = HMAC-SHA256(CEK, "WSEG" + 分段序号(8, BE) + 密文)，可以单独校验
+// 修改密码或元数据时只改写头部文件，云盘只需要重新上传很小的头部文件
+// 第 i 段的文件编号为 segment_base + i，轮换密钥时新分段使用新的编号，替换头部文件之前旧分段保持不变
+
+const SEGMENT_TAG_LEN: usize = 32;
+// 分段长度的范围
+pub const MIN_SEGMENT_SIZE: u32 = 1024 * 1024;
+pub const MAX_SEGMENT_SIZE: u32 = 1024 * 1024 * 1024;
+
+// 编号为 number 的分段文件的路径，编号至少 6 位
+pub fn segment_path(path: &str, number: u32) -> String {
+    format!("{}.{:06}", path, number)
+}
+
+// 文件头中第 index 段（从 1 开始）的路径
+pub fn header_segment_path(path: &str, header: &FileHeader, index: u32) -> String {
+    segment_path(path, header.segment_base + index)
+}
+
+// 是否为分段文件，例如 a.jpg.cry.000001
+pub fn is_segment_file(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    match name.rsplit_once('.') {
+        Some((base, number)) => base.ends_with(".cry") && number.len() >= 6 && number.bytes().all(|b| b.is_ascii_digit()),
+        None => false,
+    }
+}
+
+fn segment_tag(cek: &[u8; 32], index: u32, data: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(cek).expect("HMAC 初始化失败");
+    mac.update(b"WSEG");
+    mac.update(&(index as u64).to_be_bytes());
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+// 删除文件头对应的全部分段文件
+pub fn remove_segments(path: &str, header: &FileHeader) -> Result<()> {
+    for i in 1..=header.segment_count {
+        let segment = header_segment_path(path, header, i);
+        if Path::new(&segment).exists() {
+            remove_file(&segment).with_context(|| format!("删除分段文件失败: {}", segment))?;
+        }
+    }
+    Ok(())
+}
+
+// 把 from 的分段文件重命名为 to 的分段文件，用于替换临时文件
+pub fn rename_segments(from: &str, to: &str, header: &FileHeader) -> Result<()> {
+    for i in 1..=header.segment_count {
+        rename(header_segment_path(from, header, i), header_segment_path(to, header, i)).context("替换分段文件失败")?;
+    }
+    Ok(())
+}
+
+// 逐个校验分段，返回损坏或缺失的分段序号；不需要读取其它分段
+pub fn verify_segments(path: &str, key: &FileKey) -> Result<Vec<u32>> {
+    let header = parse_header(path).context("解析文件头失败")?;
+    if !verify_cek(&header, &key.cek, &key.cek_nonce) {
+        bail!("文件密钥不正确");
+    }
+    Ok((1..=header.segment_count).filter(|i| read_segment(path, header.segment_base, *i, &key.cek).is_err()).collect())
+}
+
+// 读取第 index 段并校验 HMAC，返回密文
+fn read_segment(path: &str, base: u32, index: u32, cek: &[u8; 32]) -> Result<Vec<u8>> {
+    let segment = segment_path(path, base + index);
+    let mut data = Vec::new();
+    File::open(&segment).with_context(|| format!("缺少分段文件: {}", segment))?.read_to_end(&mut data)?;
+    if data.len() < SEGMENT_TAG_LEN {
+        bail!("分段文件已损坏: {}", segment);
+    }
+    let tag = data.split_off(data.len() - SEGMENT_TAG_LEN);
+    if segment_tag(cek, index, &data).ct_eq(&tag).unwrap_u8() != 1 {
+        bail!("分段文件校验失败: {}", segment);
+    }
+    Ok(data)
+}
+
+// 写入密文：普通文件直接写在头部之后，分段文件每写满一段就换下一个文件
+pub enum ContentWriter {
+    File(BufWriter<File>),
+    Segments {
+        header: File,
+        path: String,
+        segment_size: usize,
+        cek: [u8; 32],
+        buffer: Vec<u8>,
+        base: u32,
+        count: u32,
+    },
+}
+
+impl ContentWriter {
+    // file 需位于头部之后；segment_size 为 0 时不分段；分段文件的编号从 base + 1 开始
+    pub fn new(file: File, path: &str, segment_size: u32, base: u32, cek: &[u8; 32]) -> ContentWriter {
+        if segment_size == 0 {
+            return ContentWriter::File(BufWriter::new(file));
+        }
+        ContentWriter::Segments {
+            header: file,
+            path: path.to_string(),
+            segment_size: segment_size as usize,
+            cek: *cek,
+            buffer: Vec::with_capacity(segment_size as usize),
+            base,
+            count: 0,
+        }
+    }
+
+    fn flush_segment(path: &str, cek: &[u8; 32], buffer: &mut Vec<u8>, base: u32, count: &mut u32) -> io::Result<()> {
+        let number = base.checked_add(*count + 1).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "分段文件编号溢出"))?;
+        *count += 1;
+        let tag = segment_tag(cek, *count, buffer);
+        let mut file = BufWriter::new(File::create(segment_path(path, number))?);
+        file.write_all(buffer)?;
+        file.write_all(&tag)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        buffer.clear();
+        Ok(())
+    }
+
+    // 写完最后一段，返回头部文件（用于回填头部）和分段数量（不分段时为 0）
+    pub fn finish(self) -> Result<(File, u32)> {
+        match self {
+            ContentWriter::File(writer) => {
+                let file = writer.into_inner().map_err(|e| e.into_error()).context("写入输出文件失败")?;
+                Ok((file, 0))
+            }
+            ContentWriter::Segments { header, path, cek, mut buffer, base, mut count, .. } => {
+                if !buffer.is_empty() || count == 0 {
+                    Self::flush_segment(&path, &cek, &mut buffer, base, &mut count)?;
+                }
+                Ok((header, count))
+            }
+        }
+    }
+}
+
+impl Write for ContentWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ContentWriter::File(writer) => writer.write(buf),
+            ContentWriter::Segments { path, segment_size, cek, buffer, base, count, .. } => {
+                let n = buf.len().min(*segment_size - buffer.len());
+                buffer.extend_from_slice(&buf[..n]);
+                if buffer.len() == *segment_size {
+                    Self::flush_segment(path, cek, buffer, *base, count)?;
+                }
+                Ok(n)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ContentWriter::File(writer) => writer.flush(),
+            ContentWriter::Segments { .. } => Ok(()),
+        }
+    }
+}
+
+// 读取密文，位置从密文开头算起；分段文件在读取时逐段校验
+pub enum ContentReader {
+    File { file: BufReader<File>, start: u64 },
+    Segments {
+        path: String,
+        segment_size: u64,
+        base: u32,
+        count: u32,
+        len: u64,
+        cek: [u8; 32],
+        pos: u64,
+        current: Option<(u32, Vec<u8>)>,
+    },
+}
+
+impl ContentReader {
+    // file 需位于头部之后
+    pub fn new(file: BufReader<File>, path: &str, header: &FileHeader, cek: &[u8; 32]) -> Result<ContentReader> {
+        if header.segment_count == 0 {
+            let mut file = file;
+            let start = file.stream_position()?;
+            return Ok(ContentReader::File { file, start });
+        }
+        if header.segment_size == 0 {
+            bail!("分段信息已损坏");
+        }
+        // 最后一段的长度不固定，需要读取文件大小
+        let last = header_segment_path(path, header, header.segment_count);
+        let last_len = std::fs::metadata(&last).with_context(|| format!("缺少分段文件: {}", last))?.len();
+        if last_len < SEGMENT_TAG_LEN as u64 {
+            bail!("分段文件已损坏: {}", last);
+        }
+        let len = (header.segment_count as u64 - 1) * header.segment_size as u64 + last_len - SEGMENT_TAG_LEN as u64;
+        Ok(ContentReader::Segments {
+            path: path.to_string(),
+            segment_size: header.segment_size as u64,
+            base: header.segment_base,
+            count: header.segment_count,
+            len,
+            cek: *cek,
+            pos: 0,
+            current: None,
+        })
+    }
+}
+
+impl Read for ContentReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ContentReader::File { file, .. } => file.read(buf),
+            ContentReader::Segments { path, segment_size, base, count, len, cek, pos, current } => {
+                if *pos >= *len || buf.is_empty() {
+                    return Ok(0);
+                }
+                let index = (*pos / *segment_size) as u32 + 1;
+                if index > *count {
+                    return Ok(0);
+                }
+                if !matches!(current, Some((i, _)) if *i == index) {
+                    let data = read_segment(path, *base, index, cek)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                    *current = Some((index, data));
+                }
+                let data = &current.as_ref().unwrap().1;
+                let start = (*pos - (index as u64 - 1) * *segment_size) as usize;
+                if start >= data.len() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "分段文件长度不正确"));
+                }
+                let n = buf.len().min(data.len() - start);
+                buf[..n].copy_from_slice(&data[start..start + n]);
+                *pos += n as u64;
+                Ok(n)
+            }
+        }
+    }
+}
+
+impl Seek for ContentReader {
+    fn seek(&mut self, target: SeekFrom) -> io::Result<u64> {
+        match self {
+            ContentReader::File { file, start } => {
+                let absolute = match target {
+                    SeekFrom::Start(p) => file.seek(SeekFrom::Start(*start + p))?,
+                    other => file.seek(other)?,
+                };
+                if absolute < *start {
+                    file.seek(SeekFrom::Start(*start))?;
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "不能定位到密文开头之前"));
+                }
+                Ok(absolute - *start)
+            }
+            ContentReader::Segments { len, pos, .. } => {
+                let next = match target {
+                    SeekFrom::Start(p) => p as i128,
+                    SeekFrom::End(p) => *len as i128 + p as i128,
+                    SeekFrom::Current(p) => *pos as i128 + p as i128,
+                };
+                if next < 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "不能定位到密文开头之前"));
+                }
+                *pos = next as u64;
+                Ok(*pos)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::utils::compress::Compression;
+    use crate::utils::encrypt::{decrypt_file_with_key, encrypt_stream, unlock_file_with_master, EncryptOptions, EntrySpec, MasterKey};
+    use crate::utils::padding::SizePadding;
+
+    // 超过两段，最后一段不满
+    const LEN: usize = 2 * MIN_SEGMENT_SIZE as usize + 12345;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i * 31 % 251) ^ (i >> 13)) as u8).collect()
+    }
+
+    // 使用保险库主密钥加密，不需要运行 Argon2
+    fn encrypt(path: &str, data: &[u8], options: EncryptOptions) -> FileKey {
+        let master_key = MasterKey([1; 32]);
+        encrypt_stream(Cursor::new(data), path, &[EntrySpec::from_master_key(&master_key)], None, None, options).unwrap();
+        unlock_file_with_master(path, &master_key).unwrap()
+    }
+
+    #[test]
+    fn encrypt_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = sample(LEN);
+        for compression in [Compression::None, Compression::Zstd] {
+            for segment_size in [0, MIN_SEGMENT_SIZE] {
+                for padding in [SizePadding::None, SizePadding::Padme] {
+                    let case = format!("{:?}-{}-{:?}", compression, segment_size, padding);
+                    let path = dir.path().join(format!("{}.cry", case)).to_string_lossy().to_string();
+                    let key = encrypt(&path, &data, EncryptOptions { compression, segment_size, padding, ..Default::default() });
+
+                    let header = parse_header(&path).unwrap();
+                    assert_eq!(header.segment_count > 0, segment_size > 0, "{}", case);
+                    assert!(verify_segments(&path, &key).unwrap().is_empty(), "{}", case);
+
+                    let output = dir.path().join(format!("{}.out", case)).to_string_lossy().to_string();
+                    let (matched, _) = decrypt_file_with_key(&path, &output, &key).unwrap();
+                    assert!(matched, "{}: File HMAC 不匹配", case);
+                    assert!(std::fs::read(&output).unwrap() == data, "{}: 解密结果不同", case);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn segment_corruption_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.cry").to_string_lossy().to_string();
+        let options = EncryptOptions { segment_size: MIN_SEGMENT_SIZE, ..Default::default() };
+        let key = encrypt(&path, &sample(LEN), options);
+        assert_eq!(parse_header(&path).unwrap().segment_count, 3);
+
+        // 修改第 2 段的一个字节，删除第 3 段
+        let second = segment_path(&path, 2);
+        let mut bytes = std::fs::read(&second).unwrap();
+        bytes[100] ^= 1;
+        std::fs::write(&second, bytes).unwrap();
+        remove_file(segment_path(&path, 3)).unwrap();
+        assert_eq!(verify_segments(&path, &key).unwrap(), vec![2, 3]);
+
+        let output = dir.path().join("a.out").to_string_lossy().to_string();
+        assert!(decrypt_file_with_key(&path, &output, &key).is_err());
+    }
+
+    #[test]
+    fn segment_file_names() {
+        assert_eq!(segment_path("a.jpg.cry", 1), "a.jpg.cry.000001");
+        assert!(is_segment_file(Path::new("a.jpg.cry.000001")));
+        assert!(!is_segment_file(Path::new("a.jpg.cry")));
+        assert!(!is_segment_file(Path::new("a.jpg.000001")));
+        assert!(!is_segment_file(Path::new("a.jpg.cry.00001")));
+    }
+}
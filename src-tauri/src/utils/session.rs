@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use anyhow::{bail, Result};
+use crate::utils::encrypt::{unlock_file, unlock_file_with_master, FileKey, MasterKey};
+use crate::utils::vault_key::{find_vault_config, unlock_vault};
+
+// 解锁会话：保存用户输入的密码，并缓存已解锁文件的密钥，避免每次读取都运行 Argon2
+static PASSWORD: RwLock<Option<String>> = RwLock::new(None);
+static KEYS: Mutex<Option<HashMap<String, FileKey>>> = Mutex::new(None);
+// 已解锁的保险库主密钥，键为配置文件路径
+static MASTER_KEYS: Mutex<Option<HashMap<PathBuf, MasterKey>>> = Mutex::new(None);
+
+// 获取保险库主密钥，优先使用缓存
+fn master_key(config: &Path, password: &str) -> Result<MasterKey> {
+    if let Some(key) = MASTER_KEYS.lock().unwrap().as_ref().and_then(|keys| keys.get(config)) {
+        return Ok(key.clone());
+    }
+    let key = unlock_vault(config, &[password])?;
+    MASTER_KEYS.lock().unwrap().get_or_insert_with(HashMap::new).insert(config.to_path_buf(), key.clone());
+    Ok(key)
+}
+
+// 获取文件密钥，优先使用缓存
+pub fn file_key(path: &str) -> Result<FileKey> {
+    if let Some(key) = KEYS.lock().unwrap().as_ref().and_then(|keys| keys.get(path)) {
+        return Ok(key.clone());
+    }
+    let password = match PASSWORD.read().unwrap().clone() {
+        Some(password) => password,
+        None => bail!("会话未解锁"),
+    };
+    // 保险库中的文件优先使用主密钥，启用主密钥之前加密的文件仍然使用密码
+    let vault_key = find_vault_config(Path::new(path))
+        .and_then(|config| master_key(&config, &password).ok())
+        .and_then(|master_key| unlock_file_with_master(path, &master_key).ok());
+    let key = match vault_key {
+        Some(key) => key,
+        None => unlock_file(path, &password)?,
+    };
+    KEYS.lock().unwrap().get_or_insert_with(HashMap::new).insert(path.to_string(), key.clone());
+    Ok(key)
+}
+
+// 文件被修改（例如轮换密钥）后清除缓存的密钥
+pub fn forget_key(path: &str) {
+    if let Some(keys) = KEYS.lock().unwrap().as_mut() {
+        keys.remove(path);
+    }
+}
+
+// 保险库修改密码后清除缓存的主密钥
+pub fn forget_master_key(config: &Path) {
+    if let Some(keys) = MASTER_KEYS.lock().unwrap().as_mut() {
+        keys.remove(config);
+    }
+}
+
+// 解锁会话
+#[tauri::command]
+pub fn unlock_session(password: &str) -> String {
+    *PASSWORD.write().unwrap() = Some(password.to_string());
+    *KEYS.lock().unwrap() = None;
+    *MASTER_KEYS.lock().unwrap() = None;
+    "已解锁".to_string()
+}
+
+// 锁定会话，清除密码和缓存的密钥
+#[tauri::command]
+pub fn lock_session() -> String {
+    *PASSWORD.write().unwrap() = None;
+    *KEYS.lock().unwrap() = None;
+    *MASTER_KEYS.lock().unwrap() = None;
+    "已锁定".to_string()
+}
@@ -0,0 +1,115 @@
+use anyhow::{bail, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use crate::utils::encrypt::{read_secret_metadata, update_secret_metadata};
+use crate::utils::meta::{FileMeta, MAX_RATING};
+use crate::utils::session;
+use crate::utils::vault::sync_tags;
+
+// 标签、备注和评分保存在加密元数据中，修改时不需要解密文件内容，但需要先解锁会话
+
+// 文件的标签、备注和评分
+#[derive(Serialize, Debug)]
+struct Annotations {
+    tags: Vec<String>,
+    note: Option<String>,
+    rating: Option<u8>,
+}
+
+// 批量修改的结果
+#[derive(Serialize, Debug)]
+struct BulkReport {
+    updated: usize,
+    errors: Vec<String>,
+}
+
+// 读取、修改并写回加密元数据，返回修改后的标签
+fn edit(path: &str, f: &(dyn Fn(&mut FileMeta) + Sync)) -> Result<Vec<String>> {
+    let key = session::file_key(path)?;
+    let mut meta = FileMeta::parse(&read_secret_metadata(path, &key)?)?;
+    f(&mut meta);
+    update_secret_metadata(path, &key, &meta.to_bytes())?;
+    Ok(meta.tags)
+}
+
+// 并行修改多个文件，并把标签同步到保险库清单
+fn edit_all(paths: &[&str], f: &(dyn Fn(&mut FileMeta) + Sync)) -> String {
+    let results: Vec<(String, Result<Vec<String>>)> = paths.par_iter()
+        .map(|p| (p.to_string(), edit(p, f)))
+        .collect();
+    let mut report = BulkReport { updated: 0, errors: Vec::new() };
+    let mut tags = Vec::new();
+    for (path, result) in results {
+        match result {
+            Ok(t) => {
+                report.updated += 1;
+                tags.push((path, t));
+            }
+            Err(e) => report.errors.push(format!("{}: {}", path, e)),
+        }
+    }
+    if let Err(e) = sync_tags(&tags) {
+        report.errors.push(format!("更新保险库清单失败: {}", e));
+    }
+    serde_json::to_string(&report).unwrap()
+}
+
+// 去掉首尾空白，忽略空标签
+fn clean_tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect()
+}
+
+fn check_rating(rating: Option<u8>) -> Result<()> {
+    if let Some(r) = rating {
+        if r == 0 || r > MAX_RATING {
+            bail!("评分必须在 1 到 {} 之间", MAX_RATING);
+        }
+    }
+    Ok(())
+}
+
+// 读取文件的标签、备注和评分
+#[tauri::command]
+pub fn read_annotations(path: &str) -> String {
+    let result = session::file_key(path)
+        .and_then(|key| read_secret_metadata(path, &key))
+        .and_then(|data| FileMeta::parse(&data));
+    match result {
+        Ok(meta) => serde_json::to_string(&Annotations { tags: meta.tags, note: meta.note, rating: meta.rating }).unwrap(),
+        Err(e) => format!("读取失败: {}", e),
+    }
+}
+
+// 给多个文件添加标签，已有的标签不会重复添加
+#[tauri::command]
+pub fn add_tags(paths: Vec<&str>, tags: Vec<&str>) -> String {
+    let tags = clean_tags(&tags);
+    edit_all(&paths, &|meta| {
+        for tag in &tags {
+            if !meta.tags.contains(tag) {
+                meta.tags.push(tag.clone());
+            }
+        }
+    })
+}
+
+// 从多个文件中移除标签
+#[tauri::command]
+pub fn remove_tags(paths: Vec<&str>, tags: Vec<&str>) -> String {
+    let tags = clean_tags(&tags);
+    edit_all(&paths, &|meta| meta.tags.retain(|t| !tags.contains(t)))
+}
+
+// 设置备注，传入空值时删除
+#[tauri::command]
+pub fn set_note(paths: Vec<&str>, note: Option<&str>) -> String {
+    let note = note.filter(|n| !n.is_empty()).map(|n| n.to_string());
+    edit_all(&paths, &|meta| meta.note = note.clone())
+}
+
+// 设置评分（1-5），传入空值时清除
+#[tauri::command]
+pub fn set_rating(paths: Vec<&str>, rating: Option<u8>) -> String {
+    if let Err(e) = check_rating(rating) { return format!("设置失败: {}", e); }
+    edit_all(&paths, &|meta| meta.rating = rating)
+}
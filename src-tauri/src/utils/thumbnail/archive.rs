@@ -0,0 +1,101 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use image::DynamicImage;
+use zip::ZipArchive;
+use super::text::text_page;
+use super::ThumbnailGenerator;
+
+// 列表最多显示的条目数，再多也画不下
+const MAX_ENTRIES: usize = 40;
+
+// 压缩包：渲染内容列表
+pub struct ArchiveGenerator;
+
+// Office 文档：提取正文文字渲染
+pub struct DocumentGenerator;
+
+// Office 文档中存放正文的 XML，依次为 docx、pptx、odt/odp
+const DOCUMENT_PARTS: [&str; 3] = ["word/document.xml", "ppt/slides/slide1.xml", "content.xml"];
+
+fn is_zip(head: &[u8]) -> bool {
+    head.starts_with(b"PK\x03\x04")
+}
+
+fn is_gzip(head: &[u8]) -> bool {
+    head.starts_with(&[0x1f, 0x8b])
+}
+
+fn is_tar(head: &[u8]) -> bool {
+    head.len() > 262 && &head[257..262] == b"ustar"
+}
+
+fn tar_entries<R: Read>(reader: R) -> Result<Vec<String>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut names = Vec::new();
+    for entry in archive.entries()?.take(MAX_ENTRIES) {
+        names.push(entry?.path()?.to_string_lossy().to_string());
+    }
+    Ok(names)
+}
+
+impl ThumbnailGenerator for ArchiveGenerator {
+    fn supports(&self, ext: &str, head: &[u8]) -> bool {
+        matches!(ext, "zip" | "tar" | "tgz" | "gz") || is_zip(head) || is_tar(head)
+    }
+
+    fn generate(&self, path: &str) -> Result<DynamicImage> {
+        let mut head = [0u8; 4];
+        File::open(path).context("打开文件失败")?.read_exact(&mut head)?;
+        let file = BufReader::new(File::open(path)?);
+        let names = if is_zip(&head) {
+            let zip = ZipArchive::new(file).context("解析 ZIP 失败")?;
+            zip.file_names().take(MAX_ENTRIES).map(|n| n.to_string()).collect()
+        } else if is_gzip(&head) {
+            tar_entries(GzDecoder::new(file)).context("解析 tar.gz 失败")?
+        } else {
+            tar_entries(file).context("解析 tar 失败")?
+        };
+        Ok(text_page(&names.join("\n")))
+    }
+}
+
+// 去掉 XML 标签，段落和换行标签转为换行
+fn xml_to_text(xml: &str) -> String {
+    let mut text = String::new();
+    let mut tag = String::new();
+    let mut in_tag = false;
+    for c in xml.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                tag.clear();
+            }
+            '>' => {
+                in_tag = false;
+                if tag.starts_with("/w:p") || tag.starts_with("/a:p") || tag.starts_with("/text:p")
+                    || tag.starts_with("/text:h") || tag.starts_with("w:br") {
+                    text.push('\n');
+                }
+            }
+            _ if in_tag => tag.push(c),
+            _ => text.push(c),
+        }
+    }
+    text.replace("&amp;", "&").replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'")
+}
+
+impl ThumbnailGenerator for DocumentGenerator {
+    fn supports(&self, ext: &str, head: &[u8]) -> bool {
+        matches!(ext, "docx" | "pptx" | "odt" | "odp") && is_zip(head)
+    }
+
+    fn generate(&self, path: &str) -> Result<DynamicImage> {
+        let mut zip = ZipArchive::new(BufReader::new(File::open(path).context("打开文件失败")?)).context("解析文档失败")?;
+        let part = DOCUMENT_PARTS.iter().find(|p| zip.index_for_name(p).is_some()).context("找不到文档正文")?;
+        let mut xml = String::new();
+        zip.by_name(part)?.read_to_string(&mut xml)?;
+        Ok(text_page(&xml_to_text(&xml)))
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::io::BufReader;
+use anyhow::Result;
+use image::{DynamicImage, ImageDecoder, ImageReader, Rgb, RgbImage};
+
+// 透明图片铺底使用的中性灰，避免转 JPEG 后透明区域变黑
+const BACKGROUND: Rgb<u8> = Rgb([238, 238, 238]);
+
+// HEIF 容器的品牌：HEIC 照片和 AVIF 图片
+const HEIF_BRANDS: [&[u8; 4]; 8] = [b"heic", b"heix", b"heim", b"heis", b"hevc", b"mif1", b"avif", b"avis"];
+
+// 通过 ftyp 判断是否为 HEIC/AVIF
+pub fn is_heif(head: &[u8]) -> bool {
+    head.len() >= 12 && &head[4..8] == b"ftyp" && HEIF_BRANDS.iter().any(|b| &head[8..12] == *b)
+}
+
+// 解码图片并按 EXIF 方向旋转，HEIC/AVIF 交给 libheif
+pub fn open_image(path: &str) -> Result<DynamicImage> {
+    let mut head = [0u8; 12];
+    let len = std::io::Read::read(&mut File::open(path)?, &mut head)?;
+    if is_heif(&head[..len]) {
+        return open_heif(path);
+    }
+
+    let mut decoder = ImageReader::new(BufReader::new(File::open(path)?)).with_guessed_format()?.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+// libheif 解码时已按 irot/imir 旋转，不需要再处理方向
+#[cfg(feature = "heif")]
+fn open_heif(path: &str) -> Result<DynamicImage> {
+    use anyhow::Context;
+    use image::RgbaImage;
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let ctx = HeifContext::read_from_file(path)?;
+    let handle = ctx.primary_image_handle()?;
+    let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)?;
+    let plane = image.planes().interleaved.context("HEIF 图片没有像素数据")?;
+    let (width, height) = (plane.width, plane.height);
+    // 每行末尾可能有对齐用的字节，逐行复制
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in plane.data.chunks(plane.stride).take(height as usize) {
+        pixels.extend_from_slice(&row[..width as usize * 4]);
+    }
+    let img = RgbaImage::from_raw(width, height, pixels).context("HEIF 图片尺寸错误")?;
+    Ok(DynamicImage::ImageRgba8(img))
+}
+
+#[cfg(not(feature = "heif"))]
+fn open_heif(_path: &str) -> Result<DynamicImage> {
+    anyhow::bail!("未启用 heif 功能，无法解码 HEIC/AVIF")
+}
+
+// 把带透明通道的图片铺在中性灰背景上，转为 RGB
+pub fn flatten(img: &DynamicImage) -> RgbImage {
+    if !img.color().has_alpha() {
+        return img.to_rgb8();
+    }
+    let rgba = img.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let p = rgba.get_pixel(x, y);
+        let alpha = p[3] as u32;
+        let blend = |c: u8, bg: u8| ((c as u32 * alpha + bg as u32 * (255 - alpha) + 127) / 255) as u8;
+        Rgb([blend(p[0], BACKGROUND[0]), blend(p[1], BACKGROUND[1]), blend(p[2], BACKGROUND[2])])
+    })
+}
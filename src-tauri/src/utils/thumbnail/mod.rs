@@ -0,0 +1,207 @@
+mod archive;
+mod decode;
+mod font;
+mod pdf;
+mod text;
+mod video;
+
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use anyhow::Result;
+use image::imageops::{overlay, FilterType};
+use image::codecs::jpeg::JpegEncoder;
+use image::{load_from_memory, DynamicImage, ImageFormat, Rgba, RgbaImage};
+use base64::{engine::general_purpose, Engine};
+use serde::{Deserialize, Serialize};
+use archive::{ArchiveGenerator, DocumentGenerator};
+pub use decode::{flatten, open_image};
+use font::{char_advance, draw_text, line_height};
+use pdf::PdfGenerator;
+use text::TextGenerator;
+use video::VideoGenerator;
+
+// 嵌入图片 硬编码
+const LOCK_FILE_H_PNG: &[u8] = include_bytes!("../../../resources/lock_file_h.png");
+const LOCK_PNG: &[u8] = include_bytes!("../../../resources/lock.png");
+
+// 用于判断文件类型的文件头长度
+const HEAD_LEN: usize = 8192;
+// 加密预览图的最长边和 JPEG 质量
+const PREVIEW_SIZE: u32 = 512;
+const PREVIEW_QUALITY: u8 = 85;
+// 缩略图尺寸范围
+const MIN_SIZE: u32 = 32;
+const MAX_SIZE: u32 = 1024;
+
+// 缩略图的隐私级别
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivacyLevel {
+    None,       // 不生成缩略图
+    Icon,       // 只显示文件类型图标
+    HeavyBlur,  // 重度模糊（默认）
+    LightBlur,  // 轻度模糊
+    Pixelated,  // 马赛克
+}
+
+// 缩略图策略，每次加密时选择，并记录在元数据中
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ThumbnailPolicy {
+    pub level: PrivacyLevel,
+    #[serde(default = "default_size")]
+    pub size: u32,      // 最长边的像素数
+}
+
+fn default_size() -> u32 {
+    150
+}
+
+impl Default for ThumbnailPolicy {
+    fn default() -> Self {
+        ThumbnailPolicy { level: PrivacyLevel::HeavyBlur, size: default_size() }
+    }
+}
+
+// 缩略图生成器：把某类文件画成一张预览图，之后统一缩放、模糊并叠加图标
+pub trait ThumbnailGenerator: Sync {
+    // 是否能处理该文件，ext 为小写扩展名，head 为文件开头的若干字节
+    fn supports(&self, ext: &str, head: &[u8]) -> bool;
+    // 生成未模糊的预览图
+    fn generate(&self, path: &str) -> Result<DynamicImage>;
+}
+
+// 普通图片：使用 image 库解码（包括 WebP），HEIC/AVIF 使用 libheif，并按 EXIF 方向旋转
+struct RasterGenerator;
+
+impl ThumbnailGenerator for RasterGenerator {
+    fn supports(&self, _ext: &str, head: &[u8]) -> bool {
+        image::guess_format(head).is_ok() || decode::is_heif(head)
+    }
+
+    fn generate(&self, path: &str) -> Result<DynamicImage> {
+        open_image(path)
+    }
+}
+
+// 按顺序尝试的生成器，前面的优先
+static GENERATORS: [&dyn ThumbnailGenerator; 6] = [
+    &RasterGenerator,
+    &VideoGenerator,
+    &PdfGenerator,
+    &DocumentGenerator,
+    &ArchiveGenerator,
+    &TextGenerator,
+];
+
+// 在右下角绘制文件类型角标，例如 PDF、TXT
+fn draw_badge(img: &mut RgbaImage, label: &str) {
+    let label: String = label.chars().take(4).collect::<String>().to_uppercase();
+    if label.is_empty() {
+        return;
+    }
+    let scale = 2;
+    let padding = 3;
+    let w = label.chars().count() as u32 * char_advance(scale) + padding * 2 - scale;
+    let h = line_height(scale) - 3 * scale + padding * 2;
+    if w + 4 > img.width() || h + 4 > img.height() {
+        return;
+    }
+    let x = img.width() - w - 4;
+    let y = img.height() - h - 4;
+    for py in y..y + h {
+        for px in x..x + w {
+            img.put_pixel(px, py, Rgba([40, 40, 40, 220]));
+        }
+    }
+    draw_text(img, x + padding, y + padding, &label, scale, Rgba([255, 255, 255, 255]));
+}
+
+fn encode_jpeg(img: &DynamicImage) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let mut cursor = Cursor::new(&mut buffer);
+    DynamicImage::ImageRgb8(flatten(img)).write_to(&mut cursor, ImageFormat::Jpeg).unwrap();
+    buffer
+}
+
+// 依次尝试各个生成器，生成未处理的预览图；透明区域先铺底，避免模糊时混入黑色
+fn generate_preview(path: &str, ext: &str) -> Option<DynamicImage> {
+    let mut head = Vec::new();
+    if let Ok(file) = File::open(path) {
+        let _ = file.take(HEAD_LEN as u64).read_to_end(&mut head);
+    }
+    GENERATORS.iter()
+        .filter(|g| g.supports(ext, &head))
+        .find_map(|g| g.generate(path).ok())
+        .map(|img| DynamicImage::ImageRgb8(flatten(&img)))
+}
+
+// 制作清晰的预览图（JPEG 字节），存入加密元数据；无法预览时返回 None
+pub fn make_preview(path: &str) -> Option<Vec<u8>> {
+    let ext = Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let img = generate_preview(path, &ext)?;
+    let img = if img.width() > PREVIEW_SIZE || img.height() > PREVIEW_SIZE {
+        img.resize(PREVIEW_SIZE, PREVIEW_SIZE, FilterType::Triangle)
+    } else {
+        img
+    };
+    let mut buffer = Vec::new();
+    JpegEncoder::new_with_quality(&mut buffer, PREVIEW_QUALITY).encode_image(&DynamicImage::ImageRgb8(flatten(&img))).ok()?;
+    Some(buffer)
+}
+
+// 预制的文件图标，缩放到指定尺寸
+fn icon(size: u32) -> RgbaImage {
+    load_from_memory(LOCK_FILE_H_PNG).unwrap().resize(size, size, FilterType::Triangle).to_rgba8()
+}
+
+// 按隐私级别处理预览图
+fn obscure(img: DynamicImage, policy: &ThumbnailPolicy) -> RgbaImage {
+    let size = policy.size;
+    let resized = img.resize(size, size, FilterType::Nearest);
+    // 模糊半径随尺寸等比缩放，保证不同尺寸下模糊程度一致
+    let scale = size as f32 / 150.0;
+    match policy.level {
+        PrivacyLevel::LightBlur => resized.blur(4.0 * scale).to_rgba8(),
+        PrivacyLevel::Pixelated => {
+            let (w, h) = (resized.width(), resized.height());
+            resized.resize_exact((w / 10).max(1), (h / 10).max(1), FilterType::Triangle)
+                .resize_exact(w, h, FilterType::Nearest)
+                .to_rgba8()
+        }
+        _ => resized.blur(20.0 * scale).to_rgba8(),
+    }
+}
+
+// 制作缩略图，传入路径，返回base64
+#[tauri::command]
+pub fn make_thumbnail(path: &String) -> String {
+    let thumbnail = make_thumbnail_with(path, &ThumbnailPolicy::default()).unwrap_or_default();
+    // 转为base64
+    general_purpose::STANDARD.encode(thumbnail)
+}
+
+// 按策略制作缩略图，返回 JPEG 字节；策略为不生成时返回 None
+pub fn make_thumbnail_with(path: &str, policy: &ThumbnailPolicy) -> Option<Vec<u8>> {
+    let policy = ThumbnailPolicy { level: policy.level, size: policy.size.clamp(MIN_SIZE, MAX_SIZE) };
+    let ext = Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let preview = match policy.level {
+        PrivacyLevel::None => return None,
+        PrivacyLevel::Icon => None,
+        _ => generate_preview(path, &ext),
+    };
+
+    let mut img = match preview {
+        // 生成模糊预览图
+        Some(img) => {
+            let mut obscured = obscure(img, &policy);
+            let lock = load_from_memory(LOCK_PNG).unwrap();
+            overlay(&mut obscured, &lock, 0, 0);
+            obscured
+        }
+        // 无法预览，使用预制图标
+        None => icon(policy.size),
+    };
+    draw_badge(&mut img, &ext);
+    Some(encode_jpeg(&DynamicImage::ImageRgba8(img)))
+}
@@ -0,0 +1,108 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, OnceLock};
+use anyhow::{anyhow, Context, Result};
+use hayro::hayro_interpret::font::{FontData, FontQuery};
+use hayro::hayro_interpret::hayro_cmap::CidFamily;
+use hayro::hayro_interpret::InterpreterSettings;
+use hayro::hayro_syntax::Pdf;
+use hayro::vello_cpu::color::palette::css::WHITE;
+use hayro::{render, PixmapSettings, RenderCache, RenderSettings};
+use image::imageops::{overlay, FilterType};
+use image::{load_from_memory, DynamicImage, RgbaImage};
+use lopdf::Document;
+use super::text::{blank_page, render_page, PAGE_HEIGHT, PAGE_WIDTH};
+use super::{ThumbnailGenerator, PREVIEW_SIZE};
+
+// 系统自带的中日韩字体，PDF 没有嵌入字体时用来显示 CJK 文字，依次查找，.ttc 使用第一个字体
+const CJK_FONTS: &[&str] = &[
+    "/System/Library/Fonts/PingFang.ttc",
+    "/System/Library/Fonts/STHeiti Light.ttc",
+    "/System/Library/Fonts/Hiragino Sans GB.ttc",
+    "C:\\Windows\\Fonts\\msyh.ttc",
+    "C:\\Windows\\Fonts\\simsun.ttc",
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+];
+
+// 找到的 CJK 字体只读取一次
+fn cjk_font() -> Option<FontData> {
+    static FONT: OnceLock<Option<FontData>> = OnceLock::new();
+    FONT.get_or_init(|| CJK_FONTS.iter().find_map(|p| std::fs::read(p).ok()).map(|data| Arc::new(data) as FontData)).clone()
+}
+
+// 标准字体使用 hayro 内置的字体，未嵌入的 CJK 字体使用系统字体
+fn interpreter_settings() -> InterpreterSettings {
+    InterpreterSettings {
+        font_resolver: Arc::new(|query| match query {
+            FontQuery::Standard(s) => Some(s.get_font_data()),
+            FontQuery::Fallback(f) => {
+                let cjk = f.character_collection.as_ref().is_some_and(|cc| matches!(
+                    cc.family,
+                    CidFamily::AdobeGB1 | CidFamily::AdobeCNS1 | CidFamily::AdobeJapan1 | CidFamily::AdobeKorea1
+                ));
+                match cjk.then(cjk_font).flatten() {
+                    Some(font) => Some((font, 0)),
+                    None => Some(f.pick_standard_font().get_font_data()),
+                }
+            }
+        }),
+        ..Default::default()
+    }
+}
+
+// 用 hayro 渲染第一页，长边为 PREVIEW_SIZE
+fn render_first_page(path: &str) -> Result<DynamicImage> {
+    let data = std::fs::read(path).context("读取 PDF 失败")?;
+    let pdf = Pdf::new(Arc::new(data)).map_err(|e| anyhow!("解析 PDF 失败: {:?}", e))?;
+    let pages = pdf.pages();
+    let page = pages.first().context("PDF 没有页面")?;
+    let (width, height) = page.render_dimensions();
+    let scale = PREVIEW_SIZE as f32 / width.max(height).max(1.0);
+    let settings = PixmapSettings { x_scale: scale, y_scale: scale, bg_color: WHITE };
+    let pixmap = render(page, &RenderCache::new(), &interpreter_settings(), &RenderSettings::default(), &settings);
+    // 背景不透明，预乘 alpha 的像素与普通 RGBA 相同
+    let image = RgbaImage::from_raw(pixmap.width() as u32, pixmap.height() as u32, pixmap.data_as_u8_slice().to_vec())
+        .context("渲染结果的尺寸不正确")?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+// 无法渲染时（例如加密的 PDF）的近似预览：第一页中最大的 JPEG 图片和文字，排版成一页
+fn approximate_first_page(path: &str) -> Result<DynamicImage> {
+    let doc = Document::load(path).context("解析 PDF 失败")?;
+    let (&page_number, &page_id) = doc.get_pages().iter().next().context("PDF 没有页面")?;
+
+    // 第一页中面积最大的 JPEG 图片
+    let image = doc.get_page_images(page_id).unwrap_or_default().into_iter()
+        .filter(|img| img.filters.as_ref().is_some_and(|f| f.iter().any(|f| f == "DCTDecode")))
+        .max_by_key(|img| img.width * img.height)
+        .and_then(|img| load_from_memory(img.content).ok());
+    let text = doc.extract_text(&[page_number]).unwrap_or_default();
+
+    let mut page = blank_page();
+    let mut top = 8;
+    if let Some(image) = image {
+        let image = image.resize(PAGE_WIDTH - 16, PAGE_HEIGHT / 2, FilterType::Triangle);
+        overlay(&mut page, &image.to_rgba8(), 8, 8);
+        top += image.height() + 6;
+    }
+    render_page(&mut page, top, &text);
+    Ok(DynamicImage::ImageRgba8(page))
+}
+
+// PDF：渲染第一页；hayro 遇到不支持的文件可能 panic，这时退回近似预览
+pub struct PdfGenerator;
+
+impl ThumbnailGenerator for PdfGenerator {
+    fn supports(&self, ext: &str, head: &[u8]) -> bool {
+        ext == "pdf" || head.starts_with(b"%PDF-")
+    }
+
+    fn generate(&self, path: &str) -> Result<DynamicImage> {
+        match catch_unwind(AssertUnwindSafe(|| render_first_page(path))) {
+            Ok(Ok(image)) => Ok(image),
+            _ => approximate_first_page(path),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::Read;
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, Rgba, RgbaImage};
+use super::font::{char_advance, draw_text, line_height};
+use super::ThumbnailGenerator;
+
+// 预览页面的尺寸（A4 比例）
+pub const PAGE_WIDTH: u32 = 212;
+pub const PAGE_HEIGHT: u32 = 300;
+const PAGE_MARGIN: u32 = 8;
+// 最多读取的文本长度
+const MAX_TEXT_BYTES: usize = 16 * 1024;
+
+// 常见的文本和代码扩展名，内容判断失败时也按文本处理
+const TEXT_EXTENSIONS: [&str; 32] = [
+    "txt", "md", "markdown", "log", "csv", "tsv", "json", "xml", "yaml", "yml", "toml", "ini", "conf",
+    "html", "css", "js", "ts", "tsx", "jsx", "rs", "py", "java", "kt", "go", "c", "h", "cpp", "hpp",
+    "cs", "sh", "sql", "srt",
+];
+
+// 判断内容是否像文本：没有 NUL 字节且是合法的 UTF-8（允许末尾字符被截断）
+pub fn looks_like_text(head: &[u8]) -> bool {
+    if head.is_empty() || head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+// 在白色页面上从 top 开始逐行绘制文字，返回页面
+pub fn render_page(page: &mut RgbaImage, top: u32, text: &str) {
+    let cols = ((PAGE_WIDTH - PAGE_MARGIN * 2) / char_advance(1)) as usize;
+    let mut y = top;
+    for line in text.lines() {
+        let line = line.replace('\t', "    ");
+        let chars: Vec<char> = line.chars().collect();
+        // 长行自动换行，空行保留
+        let chunks: Vec<String> = if chars.is_empty() {
+            vec![String::new()]
+        } else {
+            chars.chunks(cols).map(|c| c.iter().collect()).collect()
+        };
+        for chunk in chunks {
+            if y + line_height(1) > PAGE_HEIGHT - PAGE_MARGIN {
+                return;
+            }
+            draw_text(page, PAGE_MARGIN, y, &chunk, 1, Rgba([60, 60, 60, 255]));
+            y += line_height(1);
+        }
+    }
+}
+
+// 新建一张空白页面
+pub fn blank_page() -> RgbaImage {
+    RgbaImage::from_pixel(PAGE_WIDTH, PAGE_HEIGHT, Rgba([255, 255, 255, 255]))
+}
+
+// 将文字渲染为一页预览
+pub fn text_page(text: &str) -> DynamicImage {
+    let mut page = blank_page();
+    render_page(&mut page, PAGE_MARGIN, text);
+    DynamicImage::ImageRgba8(page)
+}
+
+// 文本与代码文件：渲染第一页内容
+pub struct TextGenerator;
+
+impl ThumbnailGenerator for TextGenerator {
+    fn supports(&self, ext: &str, head: &[u8]) -> bool {
+        TEXT_EXTENSIONS.contains(&ext) || looks_like_text(head)
+    }
+
+    fn generate(&self, path: &str) -> Result<DynamicImage> {
+        let mut buf = Vec::new();
+        File::open(path).context("打开文件失败")?.take(MAX_TEXT_BYTES as u64).read_to_end(&mut buf)?;
+        let text = String::from_utf8_lossy(&buf);
+        if text.trim().is_empty() {
+            bail!("空文本");
+        }
+        Ok(text_page(&text))
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use image::{load_from_memory, DynamicImage};
+use super::ThumbnailGenerator;
+
+// 单个容器内最多解析的 box 数量，防止损坏的文件导致死循环
+const MAX_BOXES: usize = 4096;
+// 封面或预览帧的最大字节数
+const MAX_IMAGE_BYTES: u64 = 32 * 1024 * 1024;
+// 可以直接当作图片解码的视频编码
+const IMAGE_CODECS: [&[u8; 4]; 4] = [b"jpeg", b"mjpa", b"mjpb", b"png "];
+
+// MP4/MOV 中的一个 box，start 和 end 为内容的起止位置
+#[derive(Clone, Copy)]
+struct Mp4Box {
+    kind: [u8; 4],
+    start: u64,
+    end: u64,
+}
+
+// 读取 [start, end) 范围内的全部子 box
+fn read_boxes(file: &mut File, start: u64, end: u64) -> Result<Vec<Mp4Box>> {
+    let mut boxes = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= end && boxes.len() < MAX_BOXES {
+        file.seek(SeekFrom::Start(pos))?;
+        let size = file.read_u32::<BigEndian>()? as u64;
+        let mut kind = [0u8; 4];
+        file.read_exact(&mut kind)?;
+        let (header_len, size) = match size {
+            0 => (8, end - pos),                                // 一直到容器结尾
+            1 => (16, file.read_u64::<BigEndian>()?),           // 64 位长度
+            _ => (8, size),
+        };
+        // 64 位长度可能接近 u64::MAX，相加时会溢出
+        let Some(box_end) = pos.checked_add(size).filter(|e| *e <= end) else { break };
+        if size < header_len {
+            break;
+        }
+        boxes.push(Mp4Box { kind, start: pos + header_len, end: box_end });
+        pos = box_end;
+    }
+    Ok(boxes)
+}
+
+fn find_box(file: &mut File, parent: Mp4Box, kind: &[u8; 4]) -> Result<Option<Mp4Box>> {
+    Ok(read_boxes(file, parent.start, parent.end)?.into_iter().find(|b| &b.kind == kind))
+}
+
+// 按路径查找 box，例如 [moov, udta, meta]
+fn find_path(file: &mut File, parent: Mp4Box, path: &[&[u8; 4]]) -> Result<Option<Mp4Box>> {
+    let mut current = parent;
+    for kind in path {
+        current = match find_box(file, current, kind)? {
+            Some(b) if &b.kind == b"meta" => skip_full_box_header(file, b)?,
+            Some(b) => b,
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(current))
+}
+
+// MP4 的 meta 是 full box（内容前有 4 字节版本和标志），QuickTime 的不是，需要判断
+fn skip_full_box_header(file: &mut File, meta: Mp4Box) -> Result<Mp4Box> {
+    file.seek(SeekFrom::Start(meta.start))?;
+    let first = file.read_u32::<BigEndian>()?;
+    if first == 0 {
+        return Ok(Mp4Box { kind: meta.kind, start: meta.start + 4, end: meta.end });
+    }
+    Ok(meta)
+}
+
+fn read_range(file: &mut File, start: u64, len: u64) -> Result<Vec<u8>> {
+    if len > MAX_IMAGE_BYTES {
+        bail!("图片过大");
+    }
+    let mut buf = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// iTunes 风格的封面：moov/udta/meta/ilst/covr/data 或 moov/meta/ilst/covr/data
+fn cover_art(file: &mut File, moov: Mp4Box) -> Result<Option<Vec<u8>>> {
+    for path in [&[b"udta", b"meta", b"ilst", b"covr", b"data"][..], &[b"meta", b"ilst", b"covr", b"data"][..]] {
+        if let Some(data) = find_path(file, moov, path)? {
+            // data 内容前有 4 字节类型和 4 字节语言
+            if data.end > data.start + 8 {
+                return Ok(Some(read_range(file, data.start + 8, data.end - data.start - 8)?));
+            }
+        }
+    }
+    Ok(None)
+}
+
+// 编码为 JPEG/PNG 的视频轨道（例如相机的 Motion JPEG），取第一帧作为预览帧
+fn poster_frame(file: &mut File, moov: Mp4Box) -> Result<Option<Vec<u8>>> {
+    for trak in read_boxes(file, moov.start, moov.end)?.into_iter().filter(|b| &b.kind == b"trak") {
+        let Some(stbl) = find_path(file, trak, &[b"mdia", b"minf", b"stbl"])? else { continue };
+
+        // stsd: 版本标志(4) + 数量(4) + 第一个条目的长度(4) + 编码类型(4)
+        let Some(stsd) = find_box(file, stbl, b"stsd")? else { continue };
+        file.seek(SeekFrom::Start(stsd.start + 12))?;
+        let mut codec = [0u8; 4];
+        file.read_exact(&mut codec)?;
+        if !IMAGE_CODECS.contains(&&codec) {
+            continue;
+        }
+
+        // 第一个 chunk 的偏移：stco(32 位) 或 co64(64 位)，内容前有版本标志(4) + 数量(4)
+        let offset = if let Some(stco) = find_box(file, stbl, b"stco")? {
+            file.seek(SeekFrom::Start(stco.start + 8))?;
+            file.read_u32::<BigEndian>()? as u64
+        } else if let Some(co64) = find_box(file, stbl, b"co64")? {
+            file.seek(SeekFrom::Start(co64.start + 8))?;
+            file.read_u64::<BigEndian>()?
+        } else {
+            continue;
+        };
+
+        // 第一个样本的大小：stsz 中统一大小为 0 时读取表中第一项
+        let Some(stsz) = find_box(file, stbl, b"stsz")? else { continue };
+        file.seek(SeekFrom::Start(stsz.start + 4))?;
+        let mut size = file.read_u32::<BigEndian>()? as u64;
+        if size == 0 {
+            file.seek(SeekFrom::Start(stsz.start + 12))?;
+            size = file.read_u32::<BigEndian>()? as u64;
+        }
+        return Ok(Some(read_range(file, offset, size)?));
+    }
+    Ok(None)
+}
+
+// MP4/MOV 视频：使用内嵌封面，没有封面时取图片编码轨道的第一帧
+// H.264/HEVC 关键帧需要视频解码器，这里不做解码，由调用方回退到默认图标
+pub struct VideoGenerator;
+
+impl ThumbnailGenerator for VideoGenerator {
+    fn supports(&self, ext: &str, head: &[u8]) -> bool {
+        matches!(ext, "mp4" | "m4v" | "mov" | "3gp") || (head.len() >= 8 && &head[4..8] == b"ftyp")
+    }
+
+    fn generate(&self, path: &str) -> Result<DynamicImage> {
+        let mut file = File::open(path).context("打开文件失败")?;
+        let len = file.metadata()?.len();
+        let root = Mp4Box { kind: *b"root", start: 0, end: len };
+        let moov = find_box(&mut file, root, b"moov")?.context("找不到 moov")?;
+        let image = match cover_art(&mut file, moov)? {
+            Some(image) => image,
+            None => poster_frame(&mut file, moov)?.context("视频没有封面")?,
+        };
+        Ok(load_from_memory(&image).context("解码封面失败")?)
+    }
+}
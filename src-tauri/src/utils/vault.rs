@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+use crate::utils::encrypt::{encrypt_file_with_entries, read_secret_metadata, unlock_file_with, update_secret_metadata, EncryptOptions, EntrySpec, FileKey};
+use crate::utils::session;
+
+// 保险库清单：放在加密的根文件夹中，本身就是一个内容为空的加密文件，
+// 清单数据保存在加密元数据中，密码与加密时使用的密码相同；启用主密钥后由主密钥解锁
+pub const MANIFEST_FILE: &str = ".little_secret.manifest";
+
+// 清单的读写需要串行，避免并发加密时互相覆盖
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+// 清单中的一个加密文件
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestRecord {
+    pub path: String,           // 加密文件相对于保险库的路径
+    pub name: String,           // 原文件名
+    pub size: u64,              // 原文件大小
+    pub kind: String,           // 原文件的小写扩展名
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub encrypted_at: u64,      // 加密时间 (Unix 秒)
+    pub modified: u64,          // 原文件的修改时间 (Unix 秒)
+    #[serde(default)]
+    pub content_hash: Option<String>,   // 原文的 HMAC (base64)，加密时启用去重才有
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    pub files: BTreeMap<String, ManifestRecord>,
+    #[serde(default)]
+    pub dedup_key: Option<String>,      // 去重密钥 (base64)，第一次启用去重时生成
+}
+
+pub fn is_manifest_file(path: &Path) -> bool {
+    path.file_name().map(|n| n == MANIFEST_FILE).unwrap_or(false)
+}
+
+// 向上查找已有清单所在的保险库根目录，找不到时返回 None
+pub fn find_vault_root(path: &Path) -> Option<PathBuf> {
+    let start = if path.is_dir() { path } else { path.parent()? };
+    start.ancestors().find(|p| p.join(MANIFEST_FILE).exists()).map(|p| p.to_path_buf())
+}
+
+// 生成加密前原文件的清单记录，path 为加密后的文件路径
+pub fn make_record(original: &Path, encrypted: &Path) -> ManifestRecord {
+    let metadata = fs::metadata(original).ok();
+    let modified = metadata.as_ref()
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let encrypted_at = std::time::SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    ManifestRecord {
+        path: encrypted.to_string_lossy().to_string(),
+        name: original.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+        size: metadata.map(|m| m.len()).unwrap_or(0),
+        kind: original.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default(),
+        tags: Vec::new(),
+        encrypted_at,
+        modified,
+        content_hash: None,
+    }
+}
+
+// 没有原文件时（例如从容器或压缩包导入的数据流）生成清单记录，原文件名为去掉 .cry 的文件名
+pub fn make_stream_record(encrypted: &Path, size: u64, modified: u64) -> ManifestRecord {
+    let original = encrypted.with_extension("");
+    let encrypted_at = std::time::SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    ManifestRecord {
+        path: encrypted.to_string_lossy().to_string(),
+        name: original.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+        size,
+        kind: original.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default(),
+        tags: Vec::new(),
+        encrypted_at,
+        modified,
+        content_hash: None,
+    }
+}
+
+// 文件相对于保险库的路径，统一使用 / 分隔
+fn relative(root: &Path, path: &str) -> String {
+    let path = Path::new(path);
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+fn read_manifest(path: &Path, key: &FileKey) -> Result<Manifest> {
+    let data = read_secret_metadata(&path.to_string_lossy(), key)?;
+    if data.is_empty() {
+        return Ok(Manifest::default());
+    }
+    Ok(serde_json::from_slice(&data).context("清单已损坏")?)
+}
+
+fn write_manifest(path: &Path, key: &FileKey, manifest: &Manifest) -> Result<()> {
+    update_secret_metadata(&path.to_string_lossy(), key, &serde_json::to_vec(manifest)?)
+}
+
+// 创建空清单
+fn create_manifest(path: &Path, specs: &[EntrySpec]) -> Result<()> {
+    let empty = NamedTempFile::new_in(path.parent().unwrap_or(Path::new(".")))?;
+    let data = serde_json::to_vec(&Manifest::default())?;
+    encrypt_file_with_entries(&empty.path().to_string_lossy(), &path.to_string_lossy(), specs, None, Some(&data), EncryptOptions::default())
+}
+
+// 依次尝试密码块参数解锁清单
+fn unlock_manifest(path: &Path, specs: &[EntrySpec]) -> Result<FileKey> {
+    specs.iter()
+        .find_map(|spec| unlock_file_with(&path.to_string_lossy(), spec).ok())
+        .context("密码无法解锁保险库清单")
+}
+
+// 打开 folder 所在保险库的清单，没有清单时在 folder 中创建；返回 (保险库根目录, 清单路径, 密钥)
+// 调用者需持有 MANIFEST_LOCK
+fn open_or_create(folder: &Path, specs: &[EntrySpec]) -> Result<(PathBuf, PathBuf, FileKey)> {
+    let root = find_vault_root(folder).unwrap_or_else(|| folder.to_path_buf());
+    let path = root.join(MANIFEST_FILE);
+    if !path.exists() {
+        create_manifest(&path, specs)?;
+    }
+    let key = unlock_manifest(&path, specs)?;
+    Ok((root, path, key))
+}
+
+// 加密完成后把新文件加入清单；folder 所在的保险库没有清单时，在 folder 中创建
+pub fn record_encrypted(folder: &Path, specs: &[EntrySpec], records: Vec<ManifestRecord>) -> Result<()> {
+    let _guard = MANIFEST_LOCK.lock().unwrap();
+    let (root, path, key) = open_or_create(folder, specs)?;
+    let mut manifest = read_manifest(&path, &key)?;
+    for mut record in records {
+        record.path = relative(&root, &record.path);
+        manifest.files.insert(record.path.clone(), record);
+    }
+    write_manifest(&path, &key, &manifest)
+}
+
+// 读取保险库的去重密钥，还没有时生成并保存到清单中
+pub fn dedup_key(folder: &Path, specs: &[EntrySpec]) -> Result<[u8; 32]> {
+    let _guard = MANIFEST_LOCK.lock().unwrap();
+    let (_, path, key) = open_or_create(folder, specs)?;
+    let mut manifest = read_manifest(&path, &key)?;
+    let existing = manifest.dedup_key.as_ref()
+        .and_then(|k| general_purpose::STANDARD.decode(k).ok())
+        .and_then(|k| <[u8; 32]>::try_from(k).ok());
+    if let Some(dedup_key) = existing {
+        return Ok(dedup_key);
+    }
+    let mut dedup_key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut dedup_key);
+    manifest.dedup_key = Some(general_purpose::STANDARD.encode(dedup_key));
+    write_manifest(&path, &key, &manifest)?;
+    Ok(dedup_key)
+}
+
+// 解密完成后把文件从清单中移除
+pub fn record_decrypted(folder: &Path, specs: &[EntrySpec], encrypted_paths: &[String]) -> Result<()> {
+    let _guard = MANIFEST_LOCK.lock().unwrap();
+    let Some(root) = find_vault_root(folder) else { return Ok(()) };
+    let path = root.join(MANIFEST_FILE);
+    let key = unlock_manifest(&path, specs)?;
+    let mut manifest = read_manifest(&path, &key)?;
+    for encrypted in encrypted_paths {
+        manifest.files.remove(&relative(&root, encrypted));
+    }
+    write_manifest(&path, &key, &manifest)
+}
+
+// 使用已解锁的会话修改清单中的记录，例如更新标签
+pub fn update_records(root: &Path, f: impl FnOnce(&mut Manifest)) -> Result<()> {
+    let _guard = MANIFEST_LOCK.lock().unwrap();
+    let path = root.join(MANIFEST_FILE);
+    let key = session::file_key(&path.to_string_lossy())?;
+    let mut manifest = read_manifest(&path, &key)?;
+    f(&mut manifest);
+    write_manifest(&path, &key, &manifest)
+}
+
+// 把文件的标签同步到所在保险库的清单，files 为 (加密文件路径, 标签)
+pub fn sync_tags(files: &[(String, Vec<String>)]) -> Result<()> {
+    let mut by_root: BTreeMap<PathBuf, Vec<&(String, Vec<String>)>> = BTreeMap::new();
+    for file in files {
+        if let Some(root) = find_vault_root(Path::new(&file.0)) {
+            by_root.entry(root).or_default().push(file);
+        }
+    }
+    for (root, files) in by_root {
+        update_records(&root, |manifest| {
+            for (path, tags) in files {
+                if let Some(record) = manifest.files.get_mut(&relative(&root, path)) {
+                    record.tags = tags.clone();
+                }
+            }
+        })?;
+    }
+    Ok(())
+}
+
+// 搜索条件，字段都为空时返回全部文件
+#[derive(Deserialize, Debug, Default)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub text: String,               // 匹配文件名和标签，不区分大小写
+    #[serde(default)]
+    pub tags: Vec<String>,          // 必须包含全部标签
+    pub kind: Option<String>,       // 扩展名
+    pub from: Option<u64>,          // 加密时间范围 (Unix 秒)
+    pub to: Option<u64>,
+}
+
+fn matches(record: &ManifestRecord, query: &SearchQuery) -> bool {
+    let text = query.text.to_lowercase();
+    let text_ok = text.is_empty()
+        || record.name.to_lowercase().contains(&text)
+        || record.tags.iter().any(|t| t.to_lowercase().contains(&text));
+    let tags_ok = query.tags.iter().all(|t| record.tags.iter().any(|r| r.eq_ignore_ascii_case(t)));
+    let kind_ok = query.kind.as_ref().map(|k| record.kind.eq_ignore_ascii_case(k)).unwrap_or(true);
+    let from_ok = query.from.map(|f| record.encrypted_at >= f).unwrap_or(true);
+    let to_ok = query.to.map(|t| record.encrypted_at <= t).unwrap_or(true);
+    text_ok && tags_ok && kind_ok && from_ok && to_ok
+}
+
+// 使用已解锁的会话读取 path 所在保险库的清单，返回 (保险库根目录, 清单)
+pub fn session_manifest(path: &Path) -> Result<(PathBuf, Manifest)> {
+    let Some(root) = find_vault_root(path) else { bail!("找不到保险库清单") };
+    let manifest_path = root.join(MANIFEST_FILE);
+    let key = session::file_key(&manifest_path.to_string_lossy())?;
+    let manifest = read_manifest(&manifest_path, &key)?;
+    Ok((root, manifest))
+}
+
+pub fn search(path: &Path, query: &SearchQuery) -> Result<Vec<ManifestRecord>> {
+    let (root, manifest) = session_manifest(path)?;
+    Ok(manifest.files.into_values()
+        .filter(|r| matches(r, query))
+        .map(|mut r| {
+            // 返回加密文件的完整路径，便于界面直接打开
+            r.path = root.join(&r.path).to_string_lossy().to_string();
+            r
+        })
+        .collect())
+}
+
+// 在已解锁的保险库中搜索文件，需要先调用 unlock_session
+#[tauri::command]
+pub fn search_vault(path: &str, query: SearchQuery) -> String {
+    match search(Path::new(path), &query) {
+        Ok(records) => serde_json::to_string(&records).unwrap(),
+        Err(e) => format!("搜索失败: {}", e),
+    }
+}
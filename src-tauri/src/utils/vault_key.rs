@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use anyhow::{bail, Context, Result};
+use tempfile::NamedTempFile;
+use crate::utils::cry_info::{parse_header, ENTRY_KDF_VAULT};
+use crate::utils::encrypt::{encrypt_file_with_entries, try_reset_entries, unlock_file, unlock_file_with_master, EncryptOptions, EntrySpec, FileKey, MasterKey};
+use crate::utils::policy::check_passwords;
+use crate::utils::session;
+use crate::utils::vault::{find_vault_root, MANIFEST_FILE};
+
+// 保险库主密钥：放在保险库根目录的配置文件本身是一个内容为空的加密文件，由密码块保护，主密钥由它的 CEK 经 HKDF 派生。
+// 保险库中新加密的文件不再为每个密码运行 Argon2，而是用 HKDF(主密钥, 盐) 派生 KEK，
+// 打开保险库只需要运行一次 Argon2，修改密码也只需要改写配置文件的文件头
+pub const VAULT_CONFIG_FILE: &str = ".little_secret.vault";
+
+pub fn is_vault_config(path: &Path) -> bool {
+    path.file_name().map(|n| n == VAULT_CONFIG_FILE).unwrap_or(false)
+}
+
+// 向上查找 path 所在保险库的配置文件，找不到时返回 None
+pub fn find_vault_config(path: &Path) -> Option<PathBuf> {
+    let start = if path.is_dir() { path } else { path.parent()? };
+    start.ancestors().map(|p| p.join(VAULT_CONFIG_FILE)).find(|p| p.exists())
+}
+
+// 依次尝试密码解锁配置文件，返回主密钥
+pub fn unlock_vault(config: &Path, passwords: &[&str]) -> Result<MasterKey> {
+    passwords.iter()
+        .find_map(|p| unlock_file(&config.to_string_lossy(), p).ok())
+        .map(|key| MasterKey::from_config_key(&key))
+        .context("密码无法解锁保险库")
+}
+
+// folder 所在保险库的主密钥，没有配置文件（未启用主密钥）时返回 None
+pub fn vault_master_key(folder: &Path, passwords: &[&str]) -> Result<Option<MasterKey>> {
+    match find_vault_config(folder) {
+        Some(config) => Ok(Some(unlock_vault(&config, passwords)?)),
+        None => Ok(None),
+    }
+}
+
+// 文件是否有保险库密码块
+pub fn uses_vault_entry(path: &str) -> bool {
+    parse_header(path).map(|h| h.entries.iter().any(|e| e.kdf == ENTRY_KDF_VAULT)).unwrap_or(false)
+}
+
+// 按密码解锁文件：使用保险库密码块的文件先用密码解锁所在保险库，再用主密钥解锁；
+// 其它文件（例如启用主密钥之前加密的文件）直接使用密码。批量处理时共用一个 KeyResolver，每个保险库只解锁一次
+pub struct KeyResolver {
+    passwords: Vec<String>,
+    master_keys: Mutex<HashMap<PathBuf, Option<MasterKey>>>,
+}
+
+impl KeyResolver {
+    pub fn new(passwords: &[&str]) -> KeyResolver {
+        KeyResolver { passwords: passwords.iter().map(|p| p.to_string()).collect(), master_keys: Mutex::new(HashMap::new()) }
+    }
+
+    // path 所在保险库的主密钥，没有保险库或密码无法解锁时返回 None
+    pub fn master_key(&self, path: &Path) -> Option<MasterKey> {
+        let config = find_vault_config(path)?;
+        let passwords: Vec<&str> = self.passwords.iter().map(|p| p.as_str()).collect();
+        self.master_keys.lock().unwrap()
+            .entry(config)
+            .or_insert_with_key(|config| unlock_vault(config, &passwords).ok())
+            .clone()
+    }
+
+    pub fn unlock(&self, path: &str) -> Result<FileKey> {
+        if uses_vault_entry(path) {
+            if let Some(key) = self.master_key(Path::new(path)).and_then(|master_key| unlock_file_with_master(path, &master_key).ok()) {
+                return Ok(key);
+            }
+        }
+        let mut error = None;
+        for password in &self.passwords {
+            match unlock_file(path, password) {
+                Ok(key) => return Ok(key),
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(error.unwrap_or_else(|| anyhow::anyhow!("没有提供密码")))
+    }
+}
+
+// 按密码解锁单个文件，见 KeyResolver
+pub fn resolve_file_key(path: &str, password: &str) -> Result<FileKey> {
+    KeyResolver::new(&[password]).unlock(path)
+}
+
+// 在保险库中加密时使用的密码块：启用主密钥时只有一个主密钥密码块，否则为原来的密码块
+pub fn vault_specs(folder: &Path, passwords: &[&str], specs: Vec<EntrySpec>) -> Result<Vec<EntrySpec>> {
+    match vault_master_key(folder, passwords)? {
+        Some(master_key) => Ok(vec![EntrySpec::from_master_key(&master_key)]),
+        None => Ok(specs),
+    }
+}
+
+// 把已有的清单改为由主密钥解锁；已经加密的文件保留原来的密码块，仍然可以用密码解密
+fn migrate_manifest(manifest: &Path, passwords: &[&str], master_key: &MasterKey) -> Result<()> {
+    let manifest = manifest.to_string_lossy().to_string();
+    let specs = [EntrySpec::from_master_key(master_key)];
+    for password in passwords {
+        if try_reset_entries(&manifest, password, &specs)? {
+            session::forget_key(&manifest);
+            return Ok(());
+        }
+    }
+    bail!("密码无法解锁保险库清单")
+}
+
+// 在 folder 所在保险库的根目录创建配置文件
+fn create(folder: &Path, passwords: &[&str], specs: &[EntrySpec]) -> Result<()> {
+    let root = find_vault_root(folder).unwrap_or_else(|| folder.to_path_buf());
+    if let Some(existing) = find_vault_config(&root) {
+        bail!("保险库已经启用主密钥: {}", existing.to_string_lossy());
+    }
+    // 主密钥由随机生成的 CEK 派生
+    let config = root.join(VAULT_CONFIG_FILE);
+    let empty = NamedTempFile::new_in(&root)?;
+    encrypt_file_with_entries(&empty.path().to_string_lossy(), &config.to_string_lossy(), specs, None, None, EncryptOptions::default())?;
+
+    let manifest = root.join(MANIFEST_FILE);
+    let result = unlock_vault(&config, passwords)
+        .and_then(|master_key| if manifest.exists() { migrate_manifest(&manifest, passwords, &master_key) } else { Ok(()) });
+    if result.is_err() {
+        let _ = fs::remove_file(&config);
+    }
+    result
+}
+
+// 为文件夹启用主密钥，标签与密码按顺序一一对应
+#[tauri::command]
+pub fn create_vault(path: &str, passwords: Vec<&str>, labels: Option<Vec<&str>>) -> String {
+    if path.is_empty() { return "创建失败: 路径为空！".to_string(); }
+    if let Err(e) = check_passwords(&passwords) { return format!("创建失败: {}", e); }
+    let specs = EntrySpec::from_labeled(&passwords, labels.as_deref(), false);
+    match create(Path::new(path), &passwords, &specs) {
+        Ok(()) => "已启用主密钥".to_string(),
+        Err(e) => format!("创建失败: {}", e),
+    }
+}
+
+// 修改保险库的密码，只改写配置文件的文件头，主密钥和保险库中的文件都不变
+#[tauri::command]
+pub fn change_vault_passwords(path: &str, old_password: &str, passwords: Vec<&str>) -> String {
+    if passwords.is_empty() { return "修改失败: 至少需要一个新密码".to_string(); }
+    if let Err(e) = check_passwords(&passwords) { return format!("修改失败: {}", e); }
+    let Some(config) = find_vault_config(Path::new(path)) else { return "修改失败: 没有启用主密钥".to_string() };
+    let specs = EntrySpec::from_passwords(&passwords.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+    match try_reset_entries(&config.to_string_lossy(), old_password, &specs) {
+        Ok(true) => {
+            session::forget_master_key(&config);
+            "修改完毕".to_string()
+        }
+        Ok(false) => "修改失败: 旧密码不正确".to_string(),
+        Err(e) => format!("修改失败: {}", e),
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use anyhow::{bail, Context, Result};
+use notify::{recommended_watcher, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use crate::utils::encrypt::{EncryptOptions, EntrySpec};
+use crate::utils::folder::{collect_files, encrypt_one, should_encrypt, EncryptJob};
+use crate::utils::policy::check_passwords;
+use crate::utils::vault::{find_vault_root, record_encrypted, MANIFEST_FILE};
+use crate::utils::vault_key::vault_master_key;
+
+// 监视文件夹：新建或修改的文件在写入稳定后自动用保险库主密钥加密并删除原文件，
+// 适合扫描仪、浏览器下载等不断放入新文件的“收件箱”文件夹。需要先用 create_vault 启用主密钥
+
+// 文件在这段时间内没有新的写入才认为已经写完
+const DEFAULT_SETTLE_SECS: u64 = 2;
+// 命令行模式从这个环境变量读取密码
+const PASSWORD_ENV: &str = "LITTLE_SECRET_PASSWORD";
+
+// 正在监视的文件夹，停止监视时丢弃 Watcher 即可，后台线程随之退出
+static WATCHERS: Mutex<Option<HashMap<PathBuf, RecommendedWatcher>>> = Mutex::new(None);
+
+// 每加密一个文件报告一次
+#[derive(Serialize, Clone, Debug)]
+pub struct WatchEvent {
+    folder: String,
+    path: String,
+    ok: bool,
+    error: Option<String>,
+}
+
+// 隐藏文件和下载中的临时文件，写完后通常会被重命名
+fn is_partial(path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    name.starts_with('.') || name.starts_with('~') || [".tmp", ".part", ".crdownload", ".download"].iter().any(|s| name.ends_with(s))
+}
+
+fn is_candidate(path: &Path) -> bool {
+    path.exists() && should_encrypt(path) && !is_partial(path)
+}
+
+// 文件的长度和修改时间，用来判断是否仍在写入
+type Snapshot = Option<(u64, SystemTime)>;
+
+fn snapshot(path: &Path) -> Snapshot {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+// 等待写入稳定的文件：最后一次看到的长度和修改时间，以及看到的时间
+#[derive(Default)]
+struct Pending {
+    files: HashMap<PathBuf, (Snapshot, Instant)>,
+}
+
+impl Pending {
+    // 文件有新的写入，重新计时
+    fn touch(&mut self, path: PathBuf) {
+        let s = snapshot(&path);
+        self.files.insert(path, (s, Instant::now()));
+    }
+
+    // 扫描到的文件，已经在等待的不重新计时
+    fn add(&mut self, path: PathBuf) {
+        self.files.entry(path.clone()).or_insert_with(|| (snapshot(&path), Instant::now()));
+    }
+
+    // 取出 settle 时间内长度和修改时间都没有变化的文件；有变化的重新计时，已不存在的丢弃
+    fn take_settled(&mut self, settle: Duration) -> BTreeSet<PathBuf> {
+        let mut settled = BTreeSet::new();
+        self.files.retain(|path, (last, seen)| {
+            if seen.elapsed() < settle {
+                return true;
+            }
+            let current = snapshot(path);
+            if current.is_none() {
+                return false;
+            }
+            if current == *last {
+                settled.insert(path.clone());
+                return false;
+            }
+            *last = current;
+            *seen = Instant::now();
+            true
+        });
+        settled
+    }
+}
+
+// 只使用保险库主密钥，不为每个文件运行 Argon2
+fn make_job(folder: &Path, password: &str) -> Result<EncryptJob> {
+    let Some(master_key) = vault_master_key(folder, &[password])? else { bail!("没有启用主密钥，请先调用 create_vault") };
+    Ok(EncryptJob {
+        specs: vec![EntrySpec::from_master_key(&master_key)],
+        thumbnail: Default::default(),
+        preview: true,
+        options: EncryptOptions::default(),
+        dedup_key: None,
+    })
+}
+
+// 加密一批文件，逐个报告结果，最后一起更新保险库清单
+fn encrypt_batch(folder: &Path, paths: BTreeSet<PathBuf>, job: &EncryptJob, report: &dyn Fn(WatchEvent)) {
+    let mut records = Vec::new();
+    for path in paths {
+        // 排队期间可能已被移走或删除
+        if !is_candidate(&path) {
+            continue;
+        }
+        let result = encrypt_one(&path, job);
+        report(WatchEvent {
+            folder: folder.to_string_lossy().to_string(),
+            path: path.to_string_lossy().to_string(),
+            ok: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        });
+        if let Ok(record) = result {
+            records.push(record);
+        }
+    }
+    // 清单更新失败时报告清单文件本身，文件已经加密但清单中没有
+    if !records.is_empty() {
+        if let Err(e) = record_encrypted(folder, &job.specs, records) {
+            report(WatchEvent {
+                folder: folder.to_string_lossy().to_string(),
+                path: find_vault_root(folder).unwrap_or_else(|| folder.to_path_buf()).join(MANIFEST_FILE).to_string_lossy().to_string(),
+                ok: false,
+                error: Some(format!("更新保险库清单失败: {:#}", e)),
+            });
+        }
+    }
+}
+
+// 把事件涉及的文件加入等待列表；文件夹（例如整个移入的文件夹）中的文件不会单独产生事件，需要扫描
+fn add_event(pending: &mut Pending, event: Event) {
+    // 只是读取（包括扫描文件夹本身产生的事件）不会改变文件
+    if matches!(event.kind, EventKind::Access(_)) {
+        return;
+    }
+    for path in event.paths {
+        if path.is_dir() {
+            let mut files = Vec::new();
+            collect_files(&path, &is_candidate, &mut files);
+            files.into_iter().for_each(|file| pending.add(file));
+        } else if is_candidate(&path) {
+            pending.touch(path);
+        }
+    }
+}
+
+// 后台线程：文件夹中已有的文件和之后新建、修改的文件都在写入稳定后加密，Watcher 被丢弃后退出
+fn run(folder: PathBuf, settle: Duration, job: EncryptJob, events: Receiver<notify::Result<Event>>, report: impl Fn(WatchEvent)) {
+    let mut pending = Pending::default();
+    let mut existing = Vec::new();
+    collect_files(&folder, &is_candidate, &mut existing);
+    existing.into_iter().for_each(|file| pending.add(file));
+
+    loop {
+        match events.recv_timeout(settle / 2) {
+            Ok(Ok(event)) => add_event(&mut pending, event),
+            // 监视出错（例如系统的监视数量达到上限）时报告文件夹本身，可能漏掉新文件
+            Ok(Err(e)) => report(WatchEvent {
+                folder: folder.to_string_lossy().to_string(),
+                path: folder.to_string_lossy().to_string(),
+                ok: false,
+                error: Some(format!("监视文件夹出错: {}", e)),
+            }),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        let settled = pending.take_settled(settle);
+        if !settled.is_empty() {
+            encrypt_batch(&folder, settled, &job, &report);
+        }
+    }
+}
+
+fn is_watching(folder: &Path) -> bool {
+    WATCHERS.lock().unwrap().as_ref().is_some_and(|watchers| watchers.contains_key(folder))
+}
+
+// 开始监视文件夹，settle 为判断写入完成的等待时间
+pub fn start(folder: &Path, password: &str, settle: Duration, report: impl Fn(WatchEvent) + Send + 'static) -> Result<()> {
+    let folder = folder.canonicalize().context("打开文件夹失败")?;
+    if is_watching(&folder) {
+        bail!("已经在监视这个文件夹");
+    }
+    // 解锁保险库需要运行 Argon2，不能持有 WATCHERS 锁，否则其它文件夹的 stop_watch、list_watches 会一直等待
+    let job = make_job(&folder, password)?;
+    let mut watchers = WATCHERS.lock().unwrap();
+    let watchers = watchers.get_or_insert_with(HashMap::new);
+    // 解锁期间可能已经从其它地方开始监视
+    if watchers.contains_key(&folder) {
+        bail!("已经在监视这个文件夹");
+    }
+    let (sender, receiver) = channel();
+    let mut watcher = recommended_watcher(sender).context("创建文件监视失败")?;
+    watcher.watch(&folder, RecursiveMode::Recursive).context("监视文件夹失败")?;
+    let worker_folder = folder.clone();
+    thread::spawn(move || run(worker_folder, settle, job, receiver, report));
+    watchers.insert(folder, watcher);
+    Ok(())
+}
+
+// 停止监视，正在加密的文件会处理完
+pub fn stop(folder: &Path) -> Result<()> {
+    let folder = folder.canonicalize().unwrap_or_else(|_| folder.to_path_buf());
+    match WATCHERS.lock().unwrap().as_mut().and_then(|watchers| watchers.remove(&folder)) {
+        Some(_) => Ok(()),
+        None => bail!("没有在监视这个文件夹"),
+    }
+}
+
+// 开始监视文件夹，每加密一个文件发送一次 watch_event 事件
+#[tauri::command]
+pub fn start_watch(app: AppHandle, path: &str, password: &str, settle_secs: Option<u64>) -> String {
+    if path.is_empty() { return "监视失败: 路径为空！".to_string(); }
+    if let Err(e) = check_passwords(&[password]) { return format!("监视失败: {}", e); }
+    let settle = Duration::from_secs(settle_secs.unwrap_or(DEFAULT_SETTLE_SECS).max(1));
+    match start(Path::new(path), password, settle, move |event| { let _ = app.emit("watch_event", event); }) {
+        Ok(()) => format!("开始监视：{}", path),
+        Err(e) => format!("监视失败: {}", e),
+    }
+}
+
+// 停止监视文件夹
+#[tauri::command]
+pub fn stop_watch(path: &str) -> String {
+    match stop(Path::new(path)) {
+        Ok(()) => format!("停止监视：{}", path),
+        Err(e) => format!("停止失败: {}", e),
+    }
+}
+
+// 列出正在监视的文件夹
+#[tauri::command]
+pub fn list_watches() -> String {
+    let folders: Vec<String> = WATCHERS.lock().unwrap().as_ref()
+        .map(|watchers| watchers.keys().map(|p| p.to_string_lossy().to_string()).collect())
+        .unwrap_or_default();
+    serde_json::to_string(&folders).unwrap()
+}
+
+// 命令行模式：little-secret watch <文件夹> [等待秒数]，不启动界面，按 Ctrl+C 停止；
+// 密码从环境变量 LITTLE_SECRET_PASSWORD 读取，没有时从标准输入读取一行
+pub fn run_cli(args: &[String]) -> i32 {
+    let Some(folder) = args.first() else {
+        eprintln!("用法: little-secret watch <文件夹> [等待秒数]");
+        return 2;
+    };
+    let settle = args.get(1).and_then(|s| s.parse::<u64>().ok()).unwrap_or(DEFAULT_SETTLE_SECS).max(1);
+    let password = match std::env::var(PASSWORD_ENV) {
+        Ok(password) => password,
+        Err(_) => {
+            eprint!("密码: ");
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).is_err() {
+                eprintln!("读取密码失败");
+                return 2;
+            }
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    let report = |event: WatchEvent| match event.error {
+        None => println!("已加密: {}", event.path),
+        Some(e) => eprintln!("加密失败: {}: {}", event.path, e),
+    };
+    if let Err(e) = start(Path::new(folder), &password, Duration::from_secs(settle), report) {
+        eprintln!("监视失败: {}", e);
+        return 1;
+    }
+    println!("正在监视 {}，按 Ctrl+C 停止", folder);
+    loop {
+        thread::park();
+    }
+}
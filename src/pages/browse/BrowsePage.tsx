@@ -4,13 +4,22 @@ import { open } from "@tauri-apps/plugin-dialog"
 import { h } from 'preact';
 import compat from 'preact/compat';
 import {List, RowComponentProps} from "react-window";
-import {invoke} from "@tauri-apps/api/core";
+import {Channel, invoke} from "@tauri-apps/api/core";
 import file from "../../assets/lock_file.png"
 import {path} from "@tauri-apps/api";
 import {useVirtualizer} from "@tanstack/react-virtual";
 import { convertFileSrc } from '@tauri-apps/api/core';
 
-type FileItem = { path:string, is_dir: boolean }
+type FileItem = {
+  path: string,
+  name: string,
+  is_dir: boolean,
+  size: number,
+  modified: number,
+  is_cry: boolean,
+  thumbnail?: string,
+  thumbnail_mime?: string,
+}
 
 export default function () {
 
@@ -37,11 +46,14 @@ export default function () {
     }
   }
 
-  // 读取文件夹中的文件
+  // 读取文件夹中的文件，元数据分批返回
   async function readFolder(path:string) {
-    let folderFiles = await invoke("read_folder", { path }) as string;
-    let files = JSON.parse(folderFiles) as FileItem[];
-    setFolderFiles(files.filter( item => item.path.endsWith(".cry") && !item.path.includes(".DS_Store") ));
+    setFolderFiles([])
+    const onBatch = new Channel<FileItem[]>()
+    onBatch.onmessage = (batch) => {
+      setFolderFiles(files => files.concat(batch.filter(item => item.is_cry)))
+    }
+    await invoke("list_folder_with_metadata", { path, onBatch, sortBy: "name" })
   }
 
   // 虚拟滚动加载
@@ -103,8 +115,8 @@ export default function () {
                   transform: `translateY(${virtualItem.start}px)`,
                 }}
               >
-                <RowComp item={folderFiles[virtualItem.index]} onClick={(metadata) => {
-                  setPreviewSrc(`data:image/*;base64,${metadata.thumbnail}`)
+                <RowComp key={folderFiles[virtualItem.index].path} item={folderFiles[virtualItem.index]} onClick={() => {
+                  setPreviewSrc(thumbnailSrc(folderFiles[virtualItem.index]))
                   setTimeout(() => {
                     setActiveItem(folderFiles[virtualItem.index])
                   }, 1000)
@@ -125,36 +137,25 @@ export default function () {
 }
 
 
-type MetaData = {
-  thumbnail: string;
+// 列表中已带缩略图时直接使用，否则通过 thumb:// 协议读取；没有缩略图时显示占位图标
+function thumbnailSrc(item: FileItem) {
+  if (!item.thumbnail_mime) {
+    return file
+  }
+  if (item.thumbnail) {
+    return `data:${item.thumbnail_mime ?? "image/jpeg"};base64,${item.thumbnail}`
+  }
+  return convertFileSrc(item.path, "thumb")
 }
 
-function RowComp({item, onClick}: { item: FileItem, onClick:(metadata:MetaData)=>void }) {
-
-  const [metadata, setMetadata] = useState<MetaData>()
-  const [text, setText] = useState("")
-
-  useEffect(() => {
-    load_metadata()
-  }, []);
-
-  // 读取元数据
-  async function load_metadata() {
-    if (!item.is_dir) {
-      let result = await invoke("read_file_metadata", {path: item.path}) as string;
-      let metadata = JSON.parse(result) as MetaData;
-      setMetadata(metadata);
-    }
-  }
+function RowComp({item, onClick}: { item: FileItem, onClick:()=>void }) {
 
   return (
-    metadata?.thumbnail && (
-      <div onClick={() => onClick(metadata)} className="relative  w-[150px] max-w-[150px] cursor-pointer group">
-        <div class="text-white">{text}</div>
-        <img src={`data:image/jpeg;base64,${metadata.thumbnail}`}
-             className="w-full h-[100px] object-contain hover:scale-105 active:scale-95 duration-150 p-1"/>
-      </div>
-    )
+    <div onClick={() => onClick()} className="relative  w-[150px] max-w-[150px] cursor-pointer group">
+      <img src={thumbnailSrc(item)}
+           className="w-full h-[100px] object-contain hover:scale-105 active:scale-95 duration-150 p-1"/>
+      { !item.thumbnail_mime && <div className="text-white text-xs truncate text-center">{item.name}</div> }
+    </div>
   )
 }
 
@@ -1,7 +1,16 @@
 import Layout from "../../comp/Layout.tsx";
-import {useState} from "preact/hooks";
+import {useEffect, useState} from "preact/hooks";
 import { open } from "@tauri-apps/plugin-dialog"
 import {invoke} from "@tauri-apps/api/core";
+import {listen} from "@tauri-apps/api/event";
+
+// 监视文件夹时每加密一个文件收到一次
+type WatchEvent = {
+  folder: string
+  path: string
+  ok: boolean
+  error: string | null
+}
 
 
 export default function EncryptPage() {
@@ -12,6 +21,18 @@ export default function EncryptPage() {
   const [password_dec, setPasswordDec] = useState("")
   const [dirFiles, setDirFiles] = useState<string[]>([])
   const [loading, setLoading] = useState(false)
+  const [watches, setWatches] = useState<string[]>([])
+  const [watchLog, setWatchLog] = useState<string[]>([])
+
+  useEffect(() => {
+    refreshWatches()
+    let unlisten = listen<WatchEvent>("watch_event", event => {
+      let {path, ok, error} = event.payload
+      // 只保留最近 50 条
+      setWatchLog(log => [ok ? `已加密 ${path}` : `失败 ${path}: ${error}`, ...log].slice(0, 50))
+    })
+    return () => { unlisten.then(f => f()) }
+  }, [])
 
   // 选择路径
   async function select_path() {
@@ -43,6 +64,27 @@ export default function EncryptPage() {
     alert(result)
   }
 
+  // 正在监视的文件夹
+  async function refreshWatches() {
+    let result: string = await invoke("list_watches")
+    setWatches(JSON.parse(result))
+  }
+
+  // 用加密密码解锁保险库并开始监视
+  async function startWatch() {
+    setLoading(true)
+    let result = await invoke("start_watch", {path, password})
+    setLoading(false)
+    await refreshWatches()
+    alert(result)
+  }
+
+  async function stopWatch(folder: string) {
+    let result = await invoke("stop_watch", {path: folder})
+    await refreshWatches()
+    alert(result)
+  }
+
   // 读取文件夹内容
   async function readDir(path: string) {
     let result : string[] = await (await fetch("/api/read_path", {
@@ -113,6 +155,28 @@ export default function EncryptPage() {
             </div>
           </div>
         </div>
+
+        <div className=" relative w-full min-h-48 border-1 rounded-2xl flex flex-col  pl-6 pr-6  gap-2 py-4 col-span-2">
+          <div class="font-bold mb-2">监视</div>
+          <div class="text-sm opacity-50">自动加密放入文件夹的新文件，使用上面的加密密码解锁保险库（需要先启用主密钥）</div>
+          {watches.map(folder => (
+            <div key={folder} className="flex flex-row items-center gap-2 text-sm">
+              <div className="flex-1 truncate">{folder}</div>
+              <div onClick={() => stopWatch(folder)}
+                   className="px-4 py-1 border-1 rounded-xl select-none cursor-pointer hover:scale-105 active:scale-95 duration-150">停止
+              </div>
+            </div>
+          ))}
+          <div className="text-xs opacity-50 flex flex-col max-h-32 overflow-auto mb-12">
+            {watchLog.map((line, i) => <div key={i} className="truncate">{line}</div>)}
+          </div>
+
+          <div className="flex flex-row gap-1 absolute right-2 bottom-2">
+            <div onClick={() => path && password.length >= 6 && !loading && startWatch()}
+                 className={`px-6 border-1 py-2.5 ${path && password.length >= 6 && !loading ? "bg-black cursor-pointer  hover:scale-105 active:scale-95" : "bg-black/50 "} text-white rounded-2xl select-none duration-150 `}>监视
+            </div>
+          </div>
+        </div>
       </div>
     </Layout>
   )